@@ -30,11 +30,28 @@ complete -c falsec -n "__fish_falsec_needs_command" -s h -l help -d 'Print help'
 complete -c falsec -n "__fish_falsec_needs_command" -s V -l version -d 'Print version'
 complete -c falsec -n "__fish_falsec_needs_command" -a "run" -d 'Execute a FALSE program'
 complete -c falsec -n "__fish_falsec_needs_command" -a "compile" -d 'Compile a FALSE program'
+complete -c falsec -n "__fish_falsec_needs_command" -a "parse" -d 'Parse a FALSE program and print the result'
+complete -c falsec -n "__fish_falsec_needs_command" -a "debug" -d 'Debug a FALSE program interactively'
+complete -c falsec -n "__fish_falsec_needs_command" -a "repl" -d 'Execute FALSE code interactively'
 complete -c falsec -n "__fish_falsec_needs_command" -a "help" -d 'Print this message or the help of the given subcommand(s)'
 complete -c falsec -n "__fish_falsec_using_subcommand run" -l type-safety -r -f -a "none\t'No type safety checks are performed'
 lambda\t'When trying to execute a lambda, make sure that the popped value is a lambda'
 lambda-and-var\t'Include all checks from [TypeSafety::Lambda], and make sure that when storing or loading a variable, the popped value is a variable name'
 full\t'Include all checks from [TypeSafety::LambdaAndVar], and ensure that only integers can be used for arithmetic operations'"
+complete -c falsec -n "__fish_falsec_using_subcommand run" -l trace -d 'Log every executed command to FILE, or to stderr if no file is given' -r -F
+complete -c falsec -n "__fish_falsec_using_subcommand run" -l trace-format -d 'The format of the trace' -r -f -a "text\t'One line per command'
+json\t'One JSON object per line'"
+complete -c falsec -n "__fish_falsec_using_subcommand run" -l trace-stack -d 'How many values from the top of the data stack to log after each command' -r
+complete -c falsec -n "__fish_falsec_using_subcommand run" -l profile-folded -d 'Write the time spent per call stack to FILE, for flamegraph tools. Implies --profile' -r -F
+complete -c falsec -n "__fish_falsec_using_subcommand run" -l coverage -d 'Write which lines, lambdas and branches were executed to FILE, in the LCOV format' -r -F
+complete -c falsec -n "__fish_falsec_using_subcommand run" -l snapshot -d 'When the program is stopped with Ctrl-C, save its state to FILE to continue with --resume' -r -F
+complete -c falsec -n "__fish_falsec_using_subcommand run" -l snapshot-every -d 'Also save a snapshot to the --snapshot FILE every COMMANDS executed commands' -r
+complete -c falsec -n "__fish_falsec_using_subcommand run" -l resume -d 'Continue from a snapshot saved with --snapshot, instead of from the start' -r -F
+complete -c falsec -n "__fish_falsec_using_subcommand run" -l record -d 'Save every byte the program reads, with when it was read, and its output to FILE' -r -F
+complete -c falsec -n "__fish_falsec_using_subcommand run" -l replay -d 'Run with the input and arguments saved by --record, and check that the output is the same' -r -F
+complete -c falsec -n "__fish_falsec_using_subcommand run" -s b -l print-backtrace -d 'Print backtrace on error'
+complete -c falsec -n "__fish_falsec_using_subcommand run" -l exit-status-from-stack -d 'Exit with the number on top of the stack when the program ends'
+complete -c falsec -n "__fish_falsec_using_subcommand run" -l profile -d 'Print the commands and lambdas that take the most time to stderr'
 complete -c falsec -n "__fish_falsec_using_subcommand run" -s h -l help -d 'Print help (see more with \'--help\')'
 complete -c falsec -n "__fish_falsec_using_subcommand run" -s V -l version -d 'Print version'
 complete -c falsec -n "__fish_falsec_using_subcommand compile" -l type-safety -r -f -a "none\t'No type safety checks are performed'
@@ -43,8 +60,34 @@ lambda-and-var\t'Include all checks from [TypeSafety::Lambda], and make sure tha
 full\t'Include all checks from [TypeSafety::LambdaAndVar], and ensure that only integers can be used for arithmetic operations'"
 complete -c falsec -n "__fish_falsec_using_subcommand compile" -l dump-asm -d 'The path to the intermediary assembly' -r -F
 complete -c falsec -n "__fish_falsec_using_subcommand compile" -s o -l out -d 'The path to the compiled FALSE program' -r -F
+complete -c falsec -n "__fish_falsec_using_subcommand compile" -l exit-status-from-stack -d 'Exit with the number on top of the stack when the program ends'
 complete -c falsec -n "__fish_falsec_using_subcommand compile" -s h -l help -d 'Print help (see more with \'--help\')'
 complete -c falsec -n "__fish_falsec_using_subcommand compile" -s V -l version -d 'Print version'
-complete -c falsec -n "__fish_falsec_using_subcommand help; and not __fish_seen_subcommand_from run compile help" -f -a "run" -d 'Execute a FALSE program'
-complete -c falsec -n "__fish_falsec_using_subcommand help; and not __fish_seen_subcommand_from run compile help" -f -a "compile" -d 'Compile a FALSE program'
-complete -c falsec -n "__fish_falsec_using_subcommand help; and not __fish_seen_subcommand_from run compile help" -f -a "help" -d 'Print this message or the help of the given subcommand(s)'
+complete -c falsec -n "__fish_falsec_using_subcommand parse" -l emit -d 'Which stage of the parse result to print' -r -f -a "tokens\t'The kind and source span of all commands, in source order. Lambdas are split into their opening and closing brackets'
+ast\t'The commands as returned by the parser, with nested lambda definitions'
+program\t'The analyzed program, with all lambdas extracted'"
+complete -c falsec -n "__fish_falsec_using_subcommand parse" -l format -d 'The output format' -r -f -a "json\t''
+debug\t'Rust debug output'"
+complete -c falsec -n "__fish_falsec_using_subcommand parse" -s o -l out -d 'The path to write the result to. Defaults to stdout' -r -F
+complete -c falsec -n "__fish_falsec_using_subcommand parse" -s h -l help -d 'Print help (see more with \'--help\')'
+complete -c falsec -n "__fish_falsec_using_subcommand parse" -s V -l version -d 'Print version'
+complete -c falsec -n "__fish_falsec_using_subcommand debug" -l type-safety -r -f -a "none\t'No type safety checks are performed'
+lambda\t'When trying to execute a lambda, make sure that the popped value is a lambda'
+lambda-and-var\t'Include all checks from [TypeSafety::Lambda], and make sure that when storing or loading a variable, the popped value is a variable name'
+full\t'Include all checks from [TypeSafety::LambdaAndVar], and ensure that only integers can be used for arithmetic operations'"
+complete -c falsec -n "__fish_falsec_using_subcommand debug" -s i -l input -d 'The path to read the program\'s input from. Defaults to stdin, which is shared with the debugger prompt' -r -F
+complete -c falsec -n "__fish_falsec_using_subcommand debug" -l history -d 'How many executed commands can be undone with reverse-step and reverse-continue, 0 to turn the history off' -r
+complete -c falsec -n "__fish_falsec_using_subcommand debug" -s h -l help -d 'Print help (see more with \'--help\')'
+complete -c falsec -n "__fish_falsec_using_subcommand debug" -s V -l version -d 'Print version'
+complete -c falsec -n "__fish_falsec_using_subcommand repl" -l type-safety -r -f -a "none\t'No type safety checks are performed'
+lambda\t'When trying to execute a lambda, make sure that the popped value is a lambda'
+lambda-and-var\t'Include all checks from [TypeSafety::Lambda], and make sure that when storing or loading a variable, the popped value is a variable name'
+full\t'Include all checks from [TypeSafety::LambdaAndVar], and ensure that only integers can be used for arithmetic operations'"
+complete -c falsec -n "__fish_falsec_using_subcommand repl" -s h -l help -d 'Print help (see more with \'--help\')'
+complete -c falsec -n "__fish_falsec_using_subcommand repl" -s V -l version -d 'Print version'
+complete -c falsec -n "__fish_falsec_using_subcommand help; and not __fish_seen_subcommand_from run compile parse debug repl help" -f -a "run" -d 'Execute a FALSE program'
+complete -c falsec -n "__fish_falsec_using_subcommand help; and not __fish_seen_subcommand_from run compile parse debug repl help" -f -a "compile" -d 'Compile a FALSE program'
+complete -c falsec -n "__fish_falsec_using_subcommand help; and not __fish_seen_subcommand_from run compile parse debug repl help" -f -a "parse" -d 'Parse a FALSE program and print the result'
+complete -c falsec -n "__fish_falsec_using_subcommand help; and not __fish_seen_subcommand_from run compile parse debug repl help" -f -a "debug" -d 'Debug a FALSE program interactively'
+complete -c falsec -n "__fish_falsec_using_subcommand help; and not __fish_seen_subcommand_from run compile parse debug repl help" -f -a "repl" -d 'Execute FALSE code interactively'
+complete -c falsec -n "__fish_falsec_using_subcommand help; and not __fish_seen_subcommand_from run compile parse debug repl help" -f -a "help" -d 'Print this message or the help of the given subcommand(s)'
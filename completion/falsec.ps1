@@ -31,11 +31,28 @@ Register-ArgumentCompleter -Native -CommandName 'falsec' -ScriptBlock {
             [CompletionResult]::new('--version', '--version', [CompletionResultType]::ParameterName, 'Print version')
             [CompletionResult]::new('run', 'run', [CompletionResultType]::ParameterValue, 'Execute a FALSE program')
             [CompletionResult]::new('compile', 'compile', [CompletionResultType]::ParameterValue, 'Compile a FALSE program')
+            [CompletionResult]::new('parse', 'parse', [CompletionResultType]::ParameterValue, 'Parse a FALSE program and print the result')
+            [CompletionResult]::new('debug', 'debug', [CompletionResultType]::ParameterValue, 'Debug a FALSE program interactively')
+            [CompletionResult]::new('repl', 'repl', [CompletionResultType]::ParameterValue, 'Execute FALSE code interactively')
             [CompletionResult]::new('help', 'help', [CompletionResultType]::ParameterValue, 'Print this message or the help of the given subcommand(s)')
             break
         }
         'falsec;run' {
             [CompletionResult]::new('--type-safety', '--type-safety', [CompletionResultType]::ParameterName, 'type-safety')
+            [CompletionResult]::new('--trace', '--trace', [CompletionResultType]::ParameterName, 'Log every executed command to FILE, or to stderr if no file is given')
+            [CompletionResult]::new('--trace-format', '--trace-format', [CompletionResultType]::ParameterName, 'The format of the trace')
+            [CompletionResult]::new('--trace-stack', '--trace-stack', [CompletionResultType]::ParameterName, 'How many values from the top of the data stack to log after each command')
+            [CompletionResult]::new('--profile-folded', '--profile-folded', [CompletionResultType]::ParameterName, 'Write the time spent per call stack to FILE, for flamegraph tools. Implies --profile')
+            [CompletionResult]::new('--coverage', '--coverage', [CompletionResultType]::ParameterName, 'Write which lines, lambdas and branches were executed to FILE, in the LCOV format')
+            [CompletionResult]::new('--snapshot', '--snapshot', [CompletionResultType]::ParameterName, 'When the program is stopped with Ctrl-C, save its state to FILE to continue with --resume')
+            [CompletionResult]::new('--snapshot-every', '--snapshot-every', [CompletionResultType]::ParameterName, 'Also save a snapshot to the --snapshot FILE every COMMANDS executed commands')
+            [CompletionResult]::new('--resume', '--resume', [CompletionResultType]::ParameterName, 'Continue from a snapshot saved with --snapshot, instead of from the start')
+            [CompletionResult]::new('--record', '--record', [CompletionResultType]::ParameterName, 'Save every byte the program reads, with when it was read, and its output to FILE')
+            [CompletionResult]::new('--replay', '--replay', [CompletionResultType]::ParameterName, 'Run with the input and arguments saved by --record, and check that the output is the same')
+            [CompletionResult]::new('-b', '-b', [CompletionResultType]::ParameterName, 'Print backtrace on error')
+            [CompletionResult]::new('--print-backtrace', '--print-backtrace', [CompletionResultType]::ParameterName, 'Print backtrace on error')
+            [CompletionResult]::new('--exit-status-from-stack', '--exit-status-from-stack', [CompletionResultType]::ParameterName, 'Exit with the number on top of the stack when the program ends')
+            [CompletionResult]::new('--profile', '--profile', [CompletionResultType]::ParameterName, 'Print the commands and lambdas that take the most time to stderr')
             [CompletionResult]::new('-h', '-h', [CompletionResultType]::ParameterName, 'Print help (see more with ''--help'')')
             [CompletionResult]::new('--help', '--help', [CompletionResultType]::ParameterName, 'Print help (see more with ''--help'')')
             [CompletionResult]::new('-V', '-V ', [CompletionResultType]::ParameterName, 'Print version')
@@ -47,6 +64,37 @@ Register-ArgumentCompleter -Native -CommandName 'falsec' -ScriptBlock {
             [CompletionResult]::new('--dump-asm', '--dump-asm', [CompletionResultType]::ParameterName, 'The path to the intermediary assembly')
             [CompletionResult]::new('-o', '-o', [CompletionResultType]::ParameterName, 'The path to the compiled FALSE program')
             [CompletionResult]::new('--out', '--out', [CompletionResultType]::ParameterName, 'The path to the compiled FALSE program')
+            [CompletionResult]::new('--exit-status-from-stack', '--exit-status-from-stack', [CompletionResultType]::ParameterName, 'Exit with the number on top of the stack when the program ends')
+            [CompletionResult]::new('-h', '-h', [CompletionResultType]::ParameterName, 'Print help (see more with ''--help'')')
+            [CompletionResult]::new('--help', '--help', [CompletionResultType]::ParameterName, 'Print help (see more with ''--help'')')
+            [CompletionResult]::new('-V', '-V ', [CompletionResultType]::ParameterName, 'Print version')
+            [CompletionResult]::new('--version', '--version', [CompletionResultType]::ParameterName, 'Print version')
+            break
+        }
+        'falsec;parse' {
+            [CompletionResult]::new('--emit', '--emit', [CompletionResultType]::ParameterName, 'Which stage of the parse result to print')
+            [CompletionResult]::new('--format', '--format', [CompletionResultType]::ParameterName, 'The output format')
+            [CompletionResult]::new('-o', '-o', [CompletionResultType]::ParameterName, 'The path to write the result to. Defaults to stdout')
+            [CompletionResult]::new('--out', '--out', [CompletionResultType]::ParameterName, 'The path to write the result to. Defaults to stdout')
+            [CompletionResult]::new('-h', '-h', [CompletionResultType]::ParameterName, 'Print help (see more with ''--help'')')
+            [CompletionResult]::new('--help', '--help', [CompletionResultType]::ParameterName, 'Print help (see more with ''--help'')')
+            [CompletionResult]::new('-V', '-V ', [CompletionResultType]::ParameterName, 'Print version')
+            [CompletionResult]::new('--version', '--version', [CompletionResultType]::ParameterName, 'Print version')
+            break
+        }
+        'falsec;debug' {
+            [CompletionResult]::new('--type-safety', '--type-safety', [CompletionResultType]::ParameterName, 'type-safety')
+            [CompletionResult]::new('-i', '-i', [CompletionResultType]::ParameterName, 'The path to read the program''s input from. Defaults to stdin, which is shared with the debugger prompt')
+            [CompletionResult]::new('--input', '--input', [CompletionResultType]::ParameterName, 'The path to read the program''s input from. Defaults to stdin, which is shared with the debugger prompt')
+            [CompletionResult]::new('--history', '--history', [CompletionResultType]::ParameterName, 'How many executed commands can be undone with reverse-step and reverse-continue, 0 to turn the history off')
+            [CompletionResult]::new('-h', '-h', [CompletionResultType]::ParameterName, 'Print help (see more with ''--help'')')
+            [CompletionResult]::new('--help', '--help', [CompletionResultType]::ParameterName, 'Print help (see more with ''--help'')')
+            [CompletionResult]::new('-V', '-V ', [CompletionResultType]::ParameterName, 'Print version')
+            [CompletionResult]::new('--version', '--version', [CompletionResultType]::ParameterName, 'Print version')
+            break
+        }
+        'falsec;repl' {
+            [CompletionResult]::new('--type-safety', '--type-safety', [CompletionResultType]::ParameterName, 'type-safety')
             [CompletionResult]::new('-h', '-h', [CompletionResultType]::ParameterName, 'Print help (see more with ''--help'')')
             [CompletionResult]::new('--help', '--help', [CompletionResultType]::ParameterName, 'Print help (see more with ''--help'')')
             [CompletionResult]::new('-V', '-V ', [CompletionResultType]::ParameterName, 'Print version')
@@ -56,6 +104,9 @@ Register-ArgumentCompleter -Native -CommandName 'falsec' -ScriptBlock {
         'falsec;help' {
             [CompletionResult]::new('run', 'run', [CompletionResultType]::ParameterValue, 'Execute a FALSE program')
             [CompletionResult]::new('compile', 'compile', [CompletionResultType]::ParameterValue, 'Compile a FALSE program')
+            [CompletionResult]::new('parse', 'parse', [CompletionResultType]::ParameterValue, 'Parse a FALSE program and print the result')
+            [CompletionResult]::new('debug', 'debug', [CompletionResultType]::ParameterValue, 'Debug a FALSE program interactively')
+            [CompletionResult]::new('repl', 'repl', [CompletionResultType]::ParameterValue, 'Execute FALSE code interactively')
             [CompletionResult]::new('help', 'help', [CompletionResultType]::ParameterValue, 'Print this message or the help of the given subcommand(s)')
             break
         }
@@ -65,6 +116,15 @@ Register-ArgumentCompleter -Native -CommandName 'falsec' -ScriptBlock {
         'falsec;help;compile' {
             break
         }
+        'falsec;help;parse' {
+            break
+        }
+        'falsec;help;debug' {
+            break
+        }
+        'falsec;help;repl' {
+            break
+        }
         'falsec;help;help' {
             break
         }
@@ -1,29 +1,99 @@
+use falsec_types::source::Span;
 use std::error::Error;
 use std::fmt;
 
 #[derive(Clone, Debug)]
-pub struct AnalyzerError {
+pub struct AnalyzerError<'source> {
+    /// The offending command, if the error can be attributed to one.
+    pub span: Option<Span<'source>>,
     pub kind: AnalyzerErrorKind,
 }
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, Eq, PartialEq)]
 pub enum AnalyzerErrorKind {
-    InvalidInput(String),
+    /// The input already contains a reference to an extracted lambda.
+    /// Only lambda definitions are allowed before analysis.
+    LambdaReferenceNotAllowed(u64),
+    /// The program does not contain its main lambda.
+    MissingMainLambda(u64),
+    /// A lambda reference points to a lambda that does not exist.
+    InvalidLambdaReference(u64),
+    /// A nested lambda definition is left in an analyzed program.
+    LambdaDefinitionNotAllowed,
+    /// A string literal is missing from `Program::strings`.
+    MissingStringLiteral(u64),
+    /// A variable name is not in `a..=z`.
+    InvalidVariableName(char),
 }
 
-impl Error for AnalyzerError {}
+impl Error for AnalyzerError<'_> {}
 
-impl AnalyzerError {
-    pub fn invalid_input(message: impl Into<String>) -> Self {
-        Self {
-            kind: AnalyzerErrorKind::InvalidInput(message.into()),
+impl fmt::Display for AnalyzerError<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Analyzer Error")?;
+        if let Some(span) = self.span {
+            write!(f, " at {}:{}", span.start.line, span.start.column)?;
         }
+        write!(f, ": {}", self.kind)
     }
 }
 
-impl fmt::Display for AnalyzerError {
+impl fmt::Display for AnalyzerErrorKind {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        // TODO: Implement this
-        write!(f, "Analyzer Error: {:?}", self.kind)
+        use AnalyzerErrorKind::*;
+        match self {
+            LambdaReferenceNotAllowed(id) => {
+                write!(f, "Lambda reference not allowed before analysis: {}", id)
+            }
+            MissingMainLambda(id) => write!(f, "Missing main lambda: {}", id),
+            InvalidLambdaReference(id) => write!(f, "Invalid lambda reference: {}", id),
+            LambdaDefinitionNotAllowed => write!(f, "Lambda definition not allowed"),
+            MissingStringLiteral(id) => write!(f, "Missing string literal: {}", id),
+            InvalidVariableName(c) => write!(f, "Invalid variable name: '{}'", c),
+        }
+    }
+}
+
+impl<'source> AnalyzerError<'source> {
+    pub fn lambda_reference_not_allowed(span: Span<'source>, id: u64) -> Self {
+        Self {
+            span: Some(span),
+            kind: AnalyzerErrorKind::LambdaReferenceNotAllowed(id),
+        }
+    }
+
+    pub fn missing_main_lambda(id: u64) -> Self {
+        Self {
+            span: None,
+            kind: AnalyzerErrorKind::MissingMainLambda(id),
+        }
+    }
+
+    pub fn invalid_lambda_reference(span: Span<'source>, id: u64) -> Self {
+        Self {
+            span: Some(span),
+            kind: AnalyzerErrorKind::InvalidLambdaReference(id),
+        }
+    }
+
+    pub fn lambda_definition_not_allowed(span: Span<'source>) -> Self {
+        Self {
+            span: Some(span),
+            kind: AnalyzerErrorKind::LambdaDefinitionNotAllowed,
+        }
+    }
+
+    pub fn missing_string_literal(span: Span<'source>, id: u64) -> Self {
+        Self {
+            span: Some(span),
+            kind: AnalyzerErrorKind::MissingStringLiteral(id),
+        }
+    }
+
+    pub fn invalid_variable_name(span: Span<'source>, c: char) -> Self {
+        Self {
+            span: Some(span),
+            kind: AnalyzerErrorKind::InvalidVariableName(c),
+        }
     }
 }
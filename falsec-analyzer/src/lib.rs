@@ -6,6 +6,9 @@
 use std::collections::HashMap;
 
 pub mod error;
+pub mod lint;
+pub mod verify;
+pub mod warning;
 
 pub struct Analyzer<'source> {
     program: Vec<(Command<'source>, Span<'source>)>,
@@ -18,7 +21,7 @@ pub fn new(program: Vec<(Command<'source>, Span<'source>)>, config: Config) -> S
         Self { program, config }
     }
 
-    pub fn analyze(self) -> Result<Program<'source>, AnalyzerError> {
+    pub fn analyze(self) -> Result<Program<'source>, AnalyzerError<'source>> {
         let lambdas = Self::extract_lambdas(self.program, HashMap::new(), 0)?;
         let strings = lambdas
             .values()
@@ -35,7 +38,7 @@ fn extract_lambdas(
         program: Lambda<'source>,
         mut lambdas: HashMap<u64, Lambda<'source>>,
         id: u64,
-    ) -> Result<HashMap<u64, Lambda<'source>>, AnalyzerError> {
+    ) -> Result<HashMap<u64, Lambda<'source>>, AnalyzerError<'source>> {
         let mut lambda = Lambda::new();
         let mut current_id = id + 1;
         for (command, span) in program {
@@ -48,10 +51,8 @@ fn extract_lambdas(
                         current_id += (lambdas.len() - c) as u64;
                         com
                     }
-                    Command::Lambda(LambdaCommand::LambdaReference(..)) => {
-                        return Err(AnalyzerError::invalid_input(
-                            "Lambda references are not allowed in lambda definitions",
-                        ));
+                    Command::Lambda(LambdaCommand::LambdaReference(id)) => {
+                        return Err(AnalyzerError::lambda_reference_not_allowed(span, id));
                     }
                     command => command,
                 },
@@ -65,7 +66,7 @@ fn extract_lambdas(
     fn extract_strings(
         mut strings: HashMap<u64, Cow<'source, str>>,
         lambda: &Lambda<'source>,
-    ) -> Result<HashMap<u64, Cow<'source, str>>, AnalyzerError> {
+    ) -> Result<HashMap<u64, Cow<'source, str>>, AnalyzerError<'source>> {
         for (command, _) in lambda {
             if let Command::StringLiteral(s) = command {
                 strings.entry(string_id(s)).or_insert_with(|| s.clone());
@@ -78,6 +79,7 @@ fn extract_strings(
 #[cfg(test)]
 mod tests {
     use crate::Analyzer;
+    use crate::error::AnalyzerErrorKind;
     use falsec_types::source::{Command, LambdaCommand, Pos, Span};
     use std::borrow::Cow;
 
@@ -183,4 +185,27 @@ fn test_nested() {
             &insert_dummy_spans(vec![Command::IntLiteral(5), Command::WriteInt])
         );
     }
+
+    #[test]
+    fn reference_not_allowed() {
+        let program = vec![
+            (
+                Command::IntLiteral(1),
+                Span::new(Pos::new(0, 1, 1), Pos::new(1, 1, 2), "1"),
+            ),
+            (
+                Command::Lambda(LambdaCommand::LambdaReference(7)),
+                Span::new(Pos::new(1, 1, 2), Pos::new(2, 1, 3), "?"),
+            ),
+        ];
+        let err = Analyzer::new(program, Default::default())
+            .analyze()
+            .unwrap_err();
+        assert_eq!(err.kind, AnalyzerErrorKind::LambdaReferenceNotAllowed(7));
+        assert_eq!(err.span.unwrap().start, Pos::new(1, 1, 2));
+        assert_eq!(
+            err.to_string(),
+            "Analyzer Error at 1:2: Lambda reference not allowed before analysis: 7"
+        );
+    }
 }
@@ -0,0 +1,440 @@
+use crate::warning::AnalyzerWarning;
+use falsec_types::source::{Command, Lambda, LambdaCommand, Program, Span};
+use std::collections::{HashMap, HashSet};
+
+/// Find likely mistakes in an analyzed program, such as loops that can never terminate.
+///
+/// The checks are conservative: only lambdas that can be resolved statically (literal `[...]`
+/// or variables that are only ever assigned a single lambda) are considered.
+pub fn lint<'source>(program: &Program<'source>) -> Vec<AnalyzerWarning<'source>> {
+    let linter = Linter::new(program);
+    let mut warnings = linter.infinite_loops();
+    warnings.extend(linter.unbounded_recursion());
+    warnings
+}
+
+struct Linter<'p, 'source> {
+    program: &'p Program<'source>,
+    /// all lambda ids, sorted so that warnings are reported in a stable order.
+    ids: Vec<u64>,
+    /// span of the `[...]` that defines each lambda.
+    definitions: HashMap<u64, Span<'source>>,
+    /// variables that are only ever assigned one specific lambda.
+    variables: HashMap<char, u64>,
+}
+
+type Edges<'source> = HashMap<u64, Vec<(u64, Span<'source>)>>;
+
+impl<'p, 'source> Linter<'p, 'source> {
+    fn new(program: &'p Program<'source>) -> Self {
+        let mut ids: Vec<_> = program.lambdas.keys().copied().collect();
+        ids.sort_unstable();
+        let mut definitions = HashMap::new();
+        let mut assignments = HashMap::<char, Option<u64>>::new();
+        let mut unknown_assignment = false;
+        for lambda in program.lambdas.values() {
+            for (i, (command, span)) in lambda.iter().enumerate() {
+                match command {
+                    Command::Lambda(LambdaCommand::LambdaReference(id)) => {
+                        definitions.insert(*id, *span);
+                    }
+                    Command::Store => {
+                        let mut operands = significant_before(lambda, i);
+                        match (operands.next(), operands.next()) {
+                            (
+                                Some((_, Command::Var(v))),
+                                Some((_, Command::Lambda(LambdaCommand::LambdaReference(id)))),
+                            ) => {
+                                assignments
+                                    .entry(*v)
+                                    .and_modify(|a| {
+                                        if *a != Some(*id) {
+                                            *a = None
+                                        }
+                                    })
+                                    .or_insert(Some(*id));
+                            }
+                            (Some((_, Command::Var(v))), _) => {
+                                assignments.insert(*v, None);
+                            }
+                            // the target variable is computed, so it could be any of them.
+                            _ => unknown_assignment = true,
+                        }
+                    }
+                    _ => (),
+                }
+            }
+        }
+        let variables = if unknown_assignment {
+            HashMap::new()
+        } else {
+            assignments
+                .into_iter()
+                .filter_map(|(v, id)| Some((v, id?)))
+                .collect()
+        };
+        Self {
+            program,
+            ids,
+            definitions,
+            variables,
+        }
+    }
+
+    /// Resolve the lambda that is on top of the stack right before `lambda[end]`.
+    /// Returns its id, and the index of the first command that produces it.
+    fn operand_before(&self, lambda: &Lambda<'source>, end: usize) -> Option<(u64, usize)> {
+        let mut commands = significant_before(lambda, end);
+        match commands.next()? {
+            (i, Command::Lambda(LambdaCommand::LambdaReference(id))) => Some((*id, i)),
+            (_, Command::Load) => match commands.next()? {
+                (i, Command::Var(v)) => Some((*self.variables.get(v)?, i)),
+                _ => None,
+            },
+            _ => None,
+        }
+    }
+
+    /// The condition and body of the while loop at `lambda[index]`.
+    fn loop_operands(&self, lambda: &Lambda<'source>, index: usize) -> Option<(u64, u64)> {
+        let (body, start) = self.operand_before(lambda, index)?;
+        let (condition, _) = self.operand_before(lambda, start)?;
+        Some((condition, body))
+    }
+
+    fn infinite_loops(&self) -> Vec<AnalyzerWarning<'source>> {
+        let mut warnings = Vec::new();
+        for id in &self.ids {
+            let lambda = &self.program.lambdas[id];
+            for (i, (command, span)) in lambda.iter().enumerate() {
+                if *command != Command::While {
+                    continue;
+                }
+                let Some((condition, _)) = self.loop_operands(lambda, i) else {
+                    continue;
+                };
+                let Some(value) = self
+                    .program
+                    .lambdas
+                    .get(&condition)
+                    .and_then(constant_value)
+                else {
+                    continue;
+                };
+                if value != 0 {
+                    warnings.push(AnalyzerWarning::infinite_loop(
+                        [Some(*span), self.definitions.get(&condition).copied()]
+                            .into_iter()
+                            .flatten()
+                            .collect(),
+                    ));
+                }
+            }
+        }
+        warnings
+    }
+
+    /// Calls that happen every time a lambda is executed: `!`, and the first evaluation
+    /// of a loop condition. The bodies of `?` and `#` are not guaranteed to run.
+    fn unconditional_calls(&self) -> Edges<'source> {
+        let mut edges = Edges::new();
+        for id in &self.ids {
+            let lambda = &self.program.lambdas[id];
+            let calls = edges.entry(*id).or_default();
+            for (i, (command, span)) in lambda.iter().enumerate() {
+                let callee = match command {
+                    Command::Exec => self.operand_before(lambda, i).map(|(id, _)| id),
+                    Command::While => self.loop_operands(lambda, i).map(|(c, _)| c),
+                    _ => None,
+                };
+                if let Some(callee) = callee {
+                    calls.push((callee, *span));
+                }
+            }
+        }
+        edges
+    }
+
+    fn unbounded_recursion(&self) -> Vec<AnalyzerWarning<'source>> {
+        let edges = self.unconditional_calls();
+        let mut reported = HashSet::new();
+        let mut warnings = Vec::new();
+        for id in &self.ids {
+            if reported.contains(id) {
+                continue;
+            }
+            let mut path = Vec::new();
+            if !find_path(&edges, *id, *id, &mut HashSet::new(), &mut path) {
+                continue;
+            }
+            reported.extend(path.iter().map(|(callee, _)| *callee));
+            warnings.push(AnalyzerWarning::unbounded_recursion(
+                self.definitions
+                    .get(id)
+                    .copied()
+                    .into_iter()
+                    .chain(path.into_iter().map(|(_, span)| span))
+                    .collect(),
+                *id,
+            ));
+        }
+        warnings
+    }
+}
+
+/// Iterate backwards over the commands before `lambda[end]`, skipping comments.
+fn significant_before<'l, 'source>(
+    lambda: &'l Lambda<'source>,
+    end: usize,
+) -> impl Iterator<Item = (usize, &'l Command<'source>)> {
+    lambda[..end]
+        .iter()
+        .enumerate()
+        .rev()
+        .map(|(i, (command, _))| (i, command))
+        .filter(|(_, command)| !matches!(command, Command::Comment(_)))
+}
+
+/// Depth-first search for a chain of calls from `from` to `to`. The call sites are collected
+/// in `path`.
+fn find_path<'source>(
+    edges: &Edges<'source>,
+    from: u64,
+    to: u64,
+    visited: &mut HashSet<u64>,
+    path: &mut Vec<(u64, Span<'source>)>,
+) -> bool {
+    for (callee, span) in edges.get(&from).into_iter().flatten() {
+        path.push((*callee, *span));
+        if *callee == to
+            || (visited.insert(*callee) && find_path(edges, *callee, to, visited, path))
+        {
+            return true;
+        }
+        path.pop();
+    }
+    false
+}
+
+/// Evaluate a lambda that only depends on literals. Returns the value on top of the stack,
+/// or `None` if the result depends on input, variables or the surrounding stack.
+fn constant_value(lambda: &Lambda) -> Option<i64> {
+    let mut stack = Vec::new();
+    for (command, _) in lambda {
+        let binary = |stack: &mut Vec<i64>, op: fn(i64, i64) -> Option<i64>| {
+            let a = stack.pop()?;
+            let b = stack.pop()?;
+            stack.push(op(b, a)?);
+            Some(())
+        };
+        match command {
+            Command::IntLiteral(i) => stack.push(*i as i64),
+            Command::CharLiteral(c) => stack.push(*c as i64),
+            Command::Dup => stack.push(*stack.last()?),
+            Command::Drop | Command::WriteChar | Command::WriteInt => _ = stack.pop()?,
+            Command::Swap => {
+                let a = stack.pop()?;
+                let b = stack.pop()?;
+                stack.extend([a, b]);
+            }
+            Command::Rot => {
+                let a = stack.pop()?;
+                let b = stack.pop()?;
+                let c = stack.pop()?;
+                stack.extend([b, a, c]);
+            }
+            Command::Add => binary(&mut stack, |b, a| Some(b.wrapping_add(a)))?,
+            Command::Sub => binary(&mut stack, |b, a| Some(b.wrapping_sub(a)))?,
+            Command::Mul => binary(&mut stack, |b, a| Some(b.wrapping_mul(a)))?,
+            Command::Div => binary(&mut stack, |b, a| b.checked_div(a))?,
+            Command::BitAnd => binary(&mut stack, |b, a| Some(b & a))?,
+            Command::BitOr => binary(&mut stack, |b, a| Some(b | a))?,
+            Command::Gt => binary(&mut stack, |b, a| Some(if b > a { -1 } else { 0 }))?,
+            Command::Eq => binary(&mut stack, |b, a| Some(if b == a { -1 } else { 0 }))?,
+            Command::Neg => {
+                let a = stack.pop()?;
+                stack.push(a.wrapping_neg());
+            }
+            Command::BitNot => {
+                let a = stack.pop()?;
+                stack.push(!a);
+            }
+            Command::StringLiteral(_) | Command::Flush | Command::Comment(_) => (),
+            _ => return None,
+        }
+    }
+    stack.last().copied()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::lint::lint;
+    use crate::warning::AnalyzerWarningKind;
+    use falsec_types::source::LambdaCommand::LambdaReference;
+    use falsec_types::source::{Command, Lambda, Pos, Program, Span};
+    use std::collections::HashMap;
+
+    fn lambda(commands: Vec<Command<'static>>) -> Lambda<'static> {
+        commands
+            .into_iter()
+            .enumerate()
+            .map(|(i, command)| {
+                (
+                    command,
+                    Span::new(Pos::new(i, 1, i + 1), Pos::new(i + 1, 1, i + 2), ""),
+                )
+            })
+            .collect()
+    }
+
+    fn program(lambdas: Vec<Vec<Command<'static>>>) -> Program<'static> {
+        Program {
+            main_id: 0,
+            lambdas: lambdas
+                .into_iter()
+                .enumerate()
+                .map(|(id, commands)| (id as u64, lambda(commands)))
+                .collect::<HashMap<_, _>>(),
+            ..Default::default()
+        }
+    }
+
+    fn kinds(program: &Program) -> Vec<AnalyzerWarningKind> {
+        lint(program).into_iter().map(|w| w.kind).collect()
+    }
+
+    #[test]
+    fn constant_condition() {
+        // [1][...]#
+        let program = program(vec![
+            vec![
+                Command::Lambda(LambdaReference(1)),
+                Command::Lambda(LambdaReference(2)),
+                Command::While,
+            ],
+            vec![Command::IntLiteral(1)],
+            vec![Command::IntLiteral(1), Command::WriteInt],
+        ]);
+        let warnings = lint(&program);
+        assert_eq!(warnings.len(), 1);
+        assert_eq!(warnings[0].kind, AnalyzerWarningKind::InfiniteLoop);
+        assert_eq!(warnings[0].spans[0].start.column, 3);
+        assert_eq!(warnings[0].spans[1].start.column, 1);
+    }
+
+    #[test]
+    fn folded_condition() {
+        // [3 2>][...]# and [0 0=~][...]#
+        let program = program(vec![
+            vec![
+                Command::Lambda(LambdaReference(1)),
+                Command::Lambda(LambdaReference(3)),
+                Command::While,
+                Command::Lambda(LambdaReference(2)),
+                Command::Lambda(LambdaReference(3)),
+                Command::While,
+            ],
+            vec![Command::IntLiteral(3), Command::IntLiteral(2), Command::Gt],
+            vec![
+                Command::IntLiteral(0),
+                Command::IntLiteral(0),
+                Command::Eq,
+                Command::BitNot,
+            ],
+            vec![],
+        ]);
+        assert_eq!(kinds(&program), [AnalyzerWarningKind::InfiniteLoop]);
+    }
+
+    #[test]
+    fn condition_with_input() {
+        // [^1][...]# and [$0>][...]#
+        let program = program(vec![
+            vec![
+                Command::Lambda(LambdaReference(1)),
+                Command::Lambda(LambdaReference(3)),
+                Command::While,
+                Command::Lambda(LambdaReference(2)),
+                Command::Lambda(LambdaReference(3)),
+                Command::While,
+            ],
+            vec![Command::ReadChar, Command::Drop, Command::IntLiteral(1)],
+            vec![Command::Dup, Command::IntLiteral(0), Command::Gt],
+            vec![],
+        ]);
+        assert_eq!(kinds(&program), []);
+    }
+
+    #[test]
+    fn recursion_through_variable() {
+        // [f;!]f: and [$[1-g;!]?]g:
+        let program = program(vec![
+            vec![
+                Command::Lambda(LambdaReference(1)),
+                Command::Var('f'),
+                Command::Store,
+                Command::Lambda(LambdaReference(2)),
+                Command::Var('g'),
+                Command::Store,
+            ],
+            vec![Command::Var('f'), Command::Load, Command::Exec],
+            vec![
+                Command::Dup,
+                Command::Lambda(LambdaReference(3)),
+                Command::Conditional,
+            ],
+            vec![
+                Command::IntLiteral(1),
+                Command::Sub,
+                Command::Var('g'),
+                Command::Load,
+                Command::Exec,
+            ],
+        ]);
+        let warnings = lint(&program);
+        assert_eq!(warnings.len(), 1);
+        assert_eq!(warnings[0].kind, AnalyzerWarningKind::UnboundedRecursion(1));
+        // definition of f, and the `!` inside of it
+        assert_eq!(warnings[0].spans.len(), 2);
+        assert_eq!(warnings[0].spans[1].start.column, 3);
+    }
+
+    #[test]
+    fn mutual_recursion() {
+        // [g;!]f: [f;!]g:
+        let program = program(vec![
+            vec![
+                Command::Lambda(LambdaReference(1)),
+                Command::Var('f'),
+                Command::Store,
+                Command::Lambda(LambdaReference(2)),
+                Command::Var('g'),
+                Command::Store,
+            ],
+            vec![Command::Var('g'), Command::Load, Command::Exec],
+            vec![Command::Var('f'), Command::Load, Command::Exec],
+        ]);
+        assert_eq!(
+            kinds(&program),
+            [AnalyzerWarningKind::UnboundedRecursion(1)]
+        );
+    }
+
+    #[test]
+    fn reassigned_variable() {
+        // [f;!]f: [0]f:
+        let program = program(vec![
+            vec![
+                Command::Lambda(LambdaReference(1)),
+                Command::Var('f'),
+                Command::Store,
+                Command::Lambda(LambdaReference(2)),
+                Command::Var('f'),
+                Command::Store,
+            ],
+            vec![Command::Var('f'), Command::Load, Command::Exec],
+            vec![Command::IntLiteral(0)],
+        ]);
+        assert_eq!(kinds(&program), []);
+    }
+}
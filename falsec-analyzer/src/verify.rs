@@ -0,0 +1,120 @@
+use crate::error::AnalyzerError;
+use falsec_types::source::{Command, LambdaCommand, Program};
+use falsec_util::string_id;
+
+/// Check the invariants that the interpreter and compiler rely on:
+/// - the main lambda exists
+/// - every lambda reference points to an existing lambda
+/// - no lambda definitions are left
+/// - every string literal is present in [Program::strings]
+/// - every variable name is in `a..=z`
+///
+/// Programs produced by [Analyzer](crate::Analyzer) always pass. This is meant for programs
+/// that were built by hand or deserialized. All violations are returned, in a stable order.
+pub fn verify<'source>(program: &Program<'source>) -> Result<(), Vec<AnalyzerError<'source>>> {
+    let mut errors = Vec::new();
+    if !program.lambdas.contains_key(&program.main_id) {
+        errors.push(AnalyzerError::missing_main_lambda(program.main_id));
+    }
+    let mut ids: Vec<_> = program.lambdas.keys().collect();
+    ids.sort_unstable();
+    for id in ids {
+        for (command, span) in &program.lambdas[id] {
+            match command {
+                Command::Lambda(LambdaCommand::LambdaReference(id))
+                    if !program.lambdas.contains_key(id) =>
+                {
+                    errors.push(AnalyzerError::invalid_lambda_reference(*span, *id));
+                }
+                Command::Lambda(LambdaCommand::LambdaDefinition(..)) => {
+                    errors.push(AnalyzerError::lambda_definition_not_allowed(*span));
+                }
+                Command::StringLiteral(s) => {
+                    let id = string_id(s);
+                    if program.strings.get(&id) != Some(s) {
+                        errors.push(AnalyzerError::missing_string_literal(*span, id));
+                    }
+                }
+                Command::Var(c) if !c.is_ascii_lowercase() => {
+                    errors.push(AnalyzerError::invalid_variable_name(*span, *c));
+                }
+                _ => (),
+            }
+        }
+    }
+    if errors.is_empty() {
+        Ok(())
+    } else {
+        Err(errors)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::Analyzer;
+    use crate::error::AnalyzerErrorKind;
+    use crate::verify::verify;
+    use falsec_types::source::LambdaCommand::{LambdaDefinition, LambdaReference};
+    use falsec_types::source::{Command, Pos, Program, Span};
+    use std::borrow::Cow;
+    use std::collections::HashMap;
+
+    fn dummy_span() -> Span<'static> {
+        Span::new(Pos::at_start(), Pos::at_start(), "")
+    }
+
+    #[test]
+    fn analyzed_program_is_valid() {
+        let program = Analyzer::new(
+            vec![
+                (
+                    Command::Lambda(LambdaDefinition(vec![(
+                        Command::StringLiteral(Cow::Borrowed("Hi")),
+                        dummy_span(),
+                    )])),
+                    dummy_span(),
+                ),
+                (Command::Exec, dummy_span()),
+            ],
+            Default::default(),
+        )
+        .analyze()
+        .unwrap();
+        verify(&program).unwrap();
+    }
+
+    #[test]
+    fn all_violations() {
+        let program = Program {
+            main_id: 0,
+            lambdas: HashMap::from([
+                (
+                    1,
+                    vec![
+                        (Command::Lambda(LambdaReference(5)), dummy_span()),
+                        (Command::Lambda(LambdaDefinition(vec![])), dummy_span()),
+                        (Command::StringLiteral(Cow::Borrowed("Hi")), dummy_span()),
+                        (Command::Var('A'), dummy_span()),
+                    ],
+                ),
+                (2, vec![(Command::Lambda(LambdaReference(1)), dummy_span())]),
+            ]),
+            ..Default::default()
+        };
+        let kinds: Vec<_> = verify(&program)
+            .unwrap_err()
+            .into_iter()
+            .map(|e| e.kind)
+            .collect();
+        assert!(matches!(
+            kinds[..],
+            [
+                AnalyzerErrorKind::MissingMainLambda(0),
+                AnalyzerErrorKind::InvalidLambdaReference(5),
+                AnalyzerErrorKind::LambdaDefinitionNotAllowed,
+                AnalyzerErrorKind::MissingStringLiteral(_),
+                AnalyzerErrorKind::InvalidVariableName('A'),
+            ]
+        ));
+    }
+}
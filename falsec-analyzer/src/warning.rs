@@ -0,0 +1,59 @@
+use falsec_types::source::Span;
+use std::fmt;
+
+#[derive(Clone, Debug)]
+pub struct AnalyzerWarning<'source> {
+    /// The spans involved, most relevant first.
+    pub spans: Vec<Span<'source>>,
+    pub kind: AnalyzerWarningKind,
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum AnalyzerWarningKind {
+    /// The condition of a while loop always evaluates to a nonzero value.
+    InfiniteLoop,
+    /// The lambda calls itself (possibly through other lambdas) on every path.
+    UnboundedRecursion(u64),
+}
+
+impl fmt::Display for AnalyzerWarning<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Warning")?;
+        if let Some(span) = self.spans.first() {
+            write!(f, " at {}:{}", span.start.line, span.start.column)?;
+        }
+        write!(f, ": {}", self.kind)
+    }
+}
+
+impl fmt::Display for AnalyzerWarningKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AnalyzerWarningKind::InfiniteLoop => {
+                write!(f, "Infinite loop: the condition is always true")
+            }
+            AnalyzerWarningKind::UnboundedRecursion(_) => {
+                write!(
+                    f,
+                    "Unbounded recursion: the lambda calls itself on every path"
+                )
+            }
+        }
+    }
+}
+
+impl<'source> AnalyzerWarning<'source> {
+    pub fn infinite_loop(spans: Vec<Span<'source>>) -> Self {
+        Self {
+            spans,
+            kind: AnalyzerWarningKind::InfiniteLoop,
+        }
+    }
+
+    pub fn unbounded_recursion(spans: Vec<Span<'source>>, lambda_id: u64) -> Self {
+        Self {
+            spans,
+            kind: AnalyzerWarningKind::UnboundedRecursion(lambda_id),
+        }
+    }
+}
@@ -15,6 +15,8 @@ pub enum CompilerErrorKind {
     IO(Rc<io::Error>),
     LambdaDefinitionNotAllowed,
     InvalidVariableName(char),
+    /// Builtin ids must be negative and unique.
+    InvalidBuiltinId(i64),
 }
 
 impl Error for CompilerError {}
@@ -39,6 +41,7 @@ fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
             CompilerErrorKind::InvalidVariableName(c) => {
                 write!(f, "Invalid variable name: '{}'", c)
             }
+            CompilerErrorKind::InvalidBuiltinId(id) => write!(f, "Invalid builtin id: {}", id),
         }
     }
 }
@@ -73,4 +76,11 @@ pub fn invalid_variable_name(source_location: Pos, c: char) -> Self {
             kind: CompilerErrorKind::InvalidVariableName(c),
         }
     }
+
+    pub fn invalid_builtin_id(id: i64) -> Self {
+        Self {
+            source_location: None,
+            kind: CompilerErrorKind::InvalidBuiltinId(id),
+        }
+    }
 }
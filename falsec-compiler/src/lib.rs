@@ -21,6 +21,22 @@ pub enum Target {
 pub trait Dump: Debug + Write + Any {}
 impl<T: Debug + Write + Any> Dump for T {}
 
+/// A function in assembly that FALSE code calls like a lambda, through a negative id.
+/// `1_!` calls the builtin with id -1, `?` and `#` can call builtins the same way.
+///
+/// For [Target::LinuxX86_64Elf], `code` is the NASM source of a function that is entered
+/// with `call` and returns with `ret`. The data stack holds `r12` 8-byte values starting at
+/// `r13`, the top one is at `[r13 + r12 * 8 - 8]`. With type safety, `r14` points at one type
+/// byte per value: 0 for numbers, 1 for lambdas and 2 for variables. `r12` to `r15` must
+/// be preserved apart from pushing and popping, other registers may be clobbered.
+/// The runtime functions `print_string`, `print_char`, `print_decimal` and `flush_stdout`
+/// can be called.
+#[derive(Clone, Debug, Default)]
+pub struct AsmBuiltin {
+    pub id: i64,
+    pub code: String,
+}
+
 #[derive(Debug, Default)]
 pub struct CompileRequest<'source, Output: Write> {
     pub source: &'source str,
@@ -29,6 +45,9 @@ pub struct CompileRequest<'source, Output: Write> {
     pub target: Target,
     pub config: Config,
     pub dump_asm: Option<Box<dyn Dump>>,
+    pub builtins: Vec<AsmBuiltin>,
+    /// Include the builtins from [falsec_types::builtins], e.g. to access the arguments.
+    pub standard_builtins: bool,
 }
 
 pub fn compile<Output: Write>(
@@ -38,12 +57,20 @@ pub fn compile<Output: Write>(
         mut output,
         config,
         dump_asm,
+        builtins,
+        standard_builtins,
         ..
     }: CompileRequest<Output>,
 ) -> Result<(), CompilerError> {
     let mut assembly_file = NamedTempFile::new()?;
     match target {
-        Target::LinuxX86_64Elf => linux_x86_64_elf::compile(program, &mut assembly_file, config)?,
+        Target::LinuxX86_64Elf => linux_x86_64_elf::compile(
+            program,
+            &mut assembly_file,
+            config,
+            builtins,
+            standard_builtins,
+        )?,
         t => panic!("Unsupported target: {:?}", t),
     }
     if let Some(mut dump_asm) = dump_asm {
@@ -78,6 +105,8 @@ fn test_compile() {
             target: super::Target::LinuxX86_64Elf,
             config: Default::default(),
             dump_asm: None,
+            builtins: Vec::new(),
+            standard_builtins: false,
         })
         .unwrap();
         assert_ne!(output.len(), 0);
@@ -1,12 +1,14 @@
 mod asm;
 mod boilerplate;
+mod stdlib;
 
+use crate::AsmBuiltin;
 use crate::error::CompilerError;
 use crate::linux_x86_64_elf::asm::{
     Address, Instruction, Label, Operand, Register, RegisterSize, SectionId,
 };
-use falsec_types::source::{Command, LambdaCommand, Program};
-use falsec_types::{Config, TypeSafety};
+use falsec_types::source::{Command, LambdaCommand, Pos, Program};
+use falsec_types::{Arithmetic, Config, TypeSafety, exit_code};
 use falsec_util::string_id;
 use std::borrow::Cow;
 use std::collections::HashMap;
@@ -18,9 +20,12 @@ pub fn compile<Output: Write>(
     program: Program,
     output: Output,
     config: Config,
+    builtins: Vec<AsmBuiltin>,
+    standard_builtins: bool,
 ) -> Result<(), CompilerError> {
     let mut asm = Assembly {
         config: config.clone(),
+        has_builtins: standard_builtins || !builtins.is_empty(),
         ..Default::default()
     };
 
@@ -48,13 +53,29 @@ pub fn compile<Output: Write>(
             Instruction::Label(Label::Named("main")),
         ],
     );
+    if standard_builtins {
+        use crate::linux_x86_64_elf::stdlib::StandardBuiltins;
+        asm.write_standard_setup();
+    }
     {
         use crate::linux_x86_64_elf::boilerplate::Boilerplate;
         asm.write_setup(&config);
     }
-    asm.call(Label::Lambda(program.main_id))
-        .call(Label::FlushStdout)
-        .exit(0);
+    asm.call(Label::Lambda(program.main_id));
+    if config.exit_status_from_stack {
+        let empty = asm.new_label();
+        asm.com("Exit with the top of the stack:")
+            .xor(Register::RDI, Register::RDI)
+            .test(Register::STACK_COUNTER, Register::STACK_COUNTER)
+            .jz(empty)
+            .pop(Register::RDI, ValueType::Number)
+            .label(empty)
+            .call(Label::FlushStdout) // preserves rdi
+            .mov(Register::RAX, 60) // sys_exit
+            .ins(Instruction::Syscall);
+    } else {
+        asm.call(Label::FlushStdout).exit(0);
+    }
 
     for (id, lambda) in program.lambdas {
         asm.ins(Instruction::Label(Label::Lambda(id)));
@@ -186,26 +207,74 @@ pub fn compile<Output: Write>(
                     .pop(Register::RDX, ValueType::Number)
                     .peek(Register::RAX, ValueType::Number)
                     .add(Register::RAX, Register::RDX)
+                    .handle_overflow(span.start, "addition", |asm| {
+                        // the sign of the result is the sign of both operands
+                        asm.mov(Register::RAX, Register::RDX)
+                            .sar(Register::RAX, 63)
+                            .mov(Register::RCX, i64::MAX)
+                            .xor(Register::RAX, Register::RCX)
+                    })
                     .replace(Register::RAX, ValueType::Number),
                 Command::Sub => asm
                     .pop(Register::RDX, ValueType::Number)
                     .peek(Register::RAX, ValueType::Number)
                     .sub(Register::RAX, Register::RDX)
+                    .handle_overflow(span.start, "subtraction", |asm| {
+                        // the sign of the result is the opposite of the subtrahend's
+                        asm.mov(Register::RAX, Register::RDX)
+                            .sar(Register::RAX, 63)
+                            .not(Register::RAX)
+                            .mov(Register::RCX, i64::MAX)
+                            .xor(Register::RAX, Register::RCX)
+                    })
                     .replace(Register::RAX, ValueType::Number),
-                Command::Mul => asm
-                    .pop(Register::RDX, ValueType::Number)
-                    .peek(Register::RAX, ValueType::Number)
-                    .imul(Register::RAX, Register::RDX)
-                    .replace(Register::RAX, ValueType::Number),
-                Command::Div => asm
-                    .pop(Register::RDI, ValueType::Number)
-                    .peek(Register::RAX, ValueType::Number)
-                    .ins(Instruction::Cqo)
-                    .idiv(Register::RDI)
-                    .replace(Register::RAX, ValueType::Number),
+                Command::Mul => {
+                    asm.pop(Register::RDX, ValueType::Number)
+                        .peek(Register::RAX, ValueType::Number);
+                    if config.arithmetic == Arithmetic::Saturating {
+                        asm.mov(Register::RCX, Register::RAX);
+                    }
+                    asm.imul(Register::RAX, Register::RDX)
+                        .handle_overflow(span.start, "multiplication", |asm| {
+                            // the result is negative if the signs of the operands differ
+                            asm.mov(Register::RAX, Register::RCX)
+                                .xor(Register::RAX, Register::RDX)
+                                .sar(Register::RAX, 63)
+                                .mov(Register::RCX, i64::MAX)
+                                .xor(Register::RAX, Register::RCX)
+                        })
+                        .replace(Register::RAX, ValueType::Number)
+                }
+                Command::Div => {
+                    let non_zero = asm.new_label();
+                    let divide = asm.new_label();
+                    let done = asm.new_label();
+                    asm.pop(Register::RDI, ValueType::Number)
+                        .peek(Register::RAX, ValueType::Number)
+                        .test(Register::RDI, Register::RDI)
+                        .jnz(non_zero)
+                        .runtime_error(span.start, "Division by zero")
+                        .label(non_zero)
+                        // idiv raises SIGFPE for i64::MIN / -1, negate instead.
+                        .cmp(Register::RDI, -1)
+                        .jne(divide)
+                        .neg(Register::RAX)
+                        .handle_overflow(span.start, "division", |asm| {
+                            asm.mov(Register::RAX, i64::MAX)
+                        })
+                        .jmp(done)
+                        .label(divide)
+                        .ins(Instruction::Cqo)
+                        .idiv(Register::RDI)
+                        .label(done)
+                        .replace(Register::RAX, ValueType::Number)
+                }
                 Command::Neg => asm
                     .peek(Register::RAX, ValueType::Number)
                     .neg(Register::RAX)
+                    .handle_overflow(span.start, "negation", |asm| {
+                        asm.mov(Register::RAX, i64::MAX)
+                    })
                     .replace(Register::RAX, ValueType::Number),
                 Command::BitAnd => asm
                     .pop(Register::RDX, ValueType::Number)
@@ -243,33 +312,31 @@ pub fn compile<Output: Write>(
                 Command::Lambda(LambdaCommand::LambdaReference(id)) => asm
                     .lea(Register::RAX, Address::b(Label::Lambda(id)))
                     .push(Register::RAX, ValueType::Lambda),
-                Command::Exec => asm
-                    .pop(Register::RAX, ValueType::Lambda)
-                    .call(Register::RAX),
+                Command::Exec => asm.pop_callable(Register::RAX).call_callable(Register::RAX),
                 Command::Conditional => {
                     let label = asm.label_generator.next().unwrap();
-                    asm.pop(Register::RAX, ValueType::Lambda) // body
+                    asm.pop_callable(Register::RAX) // body
                         .pop(Register::RDX, ValueType::Number) // condition
                         .test(Register::RDX, Register::RDX)
                         .jz(label)
-                        .call(Register::RAX)
+                        .call_callable(Register::RAX)
                         .label(label)
                         .ins(Instruction::Nop)
                 }
                 Command::While => {
                     let start = asm.label_generator.next().unwrap();
                     let end = asm.label_generator.next().unwrap();
-                    asm.pop(Register::RAX, ValueType::Lambda) // body
-                        .pop(Register::RDX, ValueType::Lambda) // condition
+                    asm.pop_callable(Register::RAX) // body
+                        .pop_callable(Register::RDX) // condition
                         .cpush(Register::RAX)
                         .cpush(Register::RDX)
                         .label(start)
-                        .call(Register::RDX)
+                        .call_callable(Register::RDX)
                         .pop(Register::RAX, ValueType::Number) // condition result
                         .test(Register::RAX, Register::RAX)
                         .jz(end)
                         .mov(Register::RAX, Address::ba(Register::RSP, 8))
-                        .call(Register::RAX) // call body
+                        .call_callable(Register::RAX) // call body
                         .mov(Register::RDX, Address::b(Register::RSP))
                         .jmp(start)
                         .label(end)
@@ -363,6 +430,7 @@ pub fn compile<Output: Write>(
         }
         asm.ins(Instruction::Ret);
     }
+    asm.write_builtins(builtins, standard_builtins)?;
     write_assembly(asm, output)?;
     Ok(())
 }
@@ -420,6 +488,9 @@ struct Assembly<'source> {
     sections: HashMap<SectionId, Section<'source>>,
     config: Config,
     label_generator: LabelGenerator,
+    error_message_count: u64,
+    /// Whether negative lambda values are dispatched to [AsmBuiltin]s.
+    has_builtins: bool,
 }
 
 #[derive(Copy, Clone, Debug, Default)]
@@ -564,6 +635,10 @@ impl<'source> Assembly<'source> {
         fn js -> Js;
         /// Jump if not sign
         fn jns -> Jns;
+        /// Jump if overflow
+        fn jo -> Jo;
+        /// Jump if not overflow
+        fn jno -> Jno;
         /// decrement rcx and jump if not zero
         fn loop_ -> Loop;
 
@@ -689,6 +764,77 @@ fn replace(
         self
     }
 
+    /// Pop a value for `!`, `?` or `#`, a lambda or the negative id of a builtin.
+    fn pop_callable(&mut self, register: Register) -> &mut Self {
+        if !self.has_builtins {
+            return self.pop(register, ValueType::Lambda);
+        }
+        let builtin = self.new_label();
+        self.pop_any(register)
+            .test(register, register)
+            .js(builtin)
+            .verify_current(ValueType::Lambda)
+            .label(builtin)
+    }
+
+    /// Call a value popped by [Self::pop_callable].
+    fn call_callable(&mut self, register: Register) -> &mut Self {
+        if !self.has_builtins {
+            return self.call(register);
+        }
+        let lambda = self.new_label();
+        let done = self.new_label();
+        self.test(register, register)
+            .jns(lambda)
+            .mov(Register::RAX, register)
+            .call(Label::BuiltinDispatch)
+            .jmp(done)
+            .label(lambda)
+            .call(register)
+            .label(done)
+    }
+
+    /// Write the builtins, and a function that jumps to the builtin with the id in rax.
+    fn write_builtins(
+        &mut self,
+        builtins: Vec<AsmBuiltin>,
+        standard_builtins: bool,
+    ) -> Result<&mut Self, CompilerError> {
+        use crate::linux_x86_64_elf::stdlib::{STANDARD_BUILTINS, StandardBuiltins};
+
+        if !self.has_builtins {
+            return Ok(self);
+        }
+        let mut ids = Vec::new();
+        if standard_builtins {
+            ids.extend(STANDARD_BUILTINS);
+        }
+        for builtin in &builtins {
+            if builtin.id >= 0 || ids.contains(&builtin.id) {
+                return Err(CompilerError::invalid_builtin_id(builtin.id));
+            }
+            ids.push(builtin.id);
+        }
+        self.label(Label::BuiltinDispatch);
+        for &id in &ids {
+            self.mov(Register::RCX, id)
+                .cmp(Register::RAX, Register::RCX)
+                .je(Label::Builtin(id));
+        }
+        self.fatal_error("\nRuntime Error: Invalid lambda reference\n".to_string());
+        if standard_builtins {
+            self.write_push_cstring();
+            for id in STANDARD_BUILTINS {
+                self.label(Label::Builtin(id)).write_standard_builtin(id);
+            }
+        }
+        for AsmBuiltin { id, code } in builtins {
+            self.label(Label::Builtin(id))
+                .ins(Instruction::Raw(Cow::Owned(code)));
+        }
+        Ok(self)
+    }
+
     fn verify_current(&mut self, value_type: ValueType) -> &mut Self {
         match (self.config.type_safety, value_type) {
             (TypeSafety::Lambda, ValueType::Lambda) => (),
@@ -703,10 +849,60 @@ fn verify_current(&mut self, value_type: ValueType) -> &mut Self {
             .lea(Register::RSI, Address::b(label_expected_type(value_type)))
             .mov(Register::RDX, label_expected_type_len(value_type))
             .call(Label::PrintString)
-            .exit(1)
+            .exit(exit_code::RUNTIME_ERROR as u64)
             .label(label)
     }
 
+    /// Handle a signed overflow of the previous instruction according to [Arithmetic].
+    /// `saturate` loads the clamped result into rax.
+    fn handle_overflow(
+        &mut self,
+        pos: Pos,
+        op: &str,
+        saturate: impl FnOnce(&mut Self) -> &mut Self,
+    ) -> &mut Self {
+        let no_overflow = match self.config.arithmetic {
+            Arithmetic::Wrapping => return self,
+            Arithmetic::Checked | Arithmetic::Saturating => self.new_label(),
+        };
+        self.jno(no_overflow);
+        if self.config.arithmetic == Arithmetic::Checked {
+            self.runtime_error(pos, &format!("Integer overflow in {}", op));
+        } else {
+            saturate(self);
+        }
+        self.label(no_overflow)
+    }
+
+    /// Print an error message with the source location to stderr and exit with
+    /// [exit_code::RUNTIME_ERROR].
+    fn runtime_error(&mut self, pos: Pos, message: &str) -> &mut Self {
+        self.fatal_error(format!(
+            "\nRuntime Error at {}:{}: {}\n",
+            pos.line, pos.column, message
+        ))
+    }
+
+    /// Print `message` to stderr and exit with [exit_code::RUNTIME_ERROR].
+    fn fatal_error(&mut self, message: String) -> &mut Self {
+        let id = self.error_message_count;
+        self.error_message_count += 1;
+        self.add_instructions(
+            SectionId::RoData,
+            [
+                Instruction::Label(Label::ErrorMessage(id)),
+                Instruction::DB(Cow::Owned(message.into_bytes())),
+                Instruction::Label(Label::ErrorMessageLen(id)),
+                Instruction::Equ(Cow::Owned(format!("$ - {}", Label::ErrorMessage(id)))),
+            ],
+        );
+        self.mov(Register::RDI, 2) // stderr
+            .lea(Register::RSI, Address::b(Label::ErrorMessage(id)))
+            .mov(Register::RDX, Label::ErrorMessageLen(id))
+            .call(Label::PrintString)
+            .exit(exit_code::RUNTIME_ERROR as u64)
+    }
+
     fn exit(&mut self, code: u64) -> &mut Self {
         self.mov(Register::RAX, 60)
             .mov(Register::RDI, code)
@@ -813,6 +1009,8 @@ fn write_assembly(assembly: Assembly, mut output: impl Write) -> Result<(), Comp
                 Instruction::Jnz(operand) => write!(current_line, "\tjnz {}", operand)?,
                 Instruction::Js(operand) => write!(current_line, "\tjs {}", operand)?,
                 Instruction::Jns(operand) => write!(current_line, "\tjns {}", operand)?,
+                Instruction::Jo(operand) => write!(current_line, "\tjo {}", operand)?,
+                Instruction::Jno(operand) => write!(current_line, "\tjno {}", operand)?,
                 Instruction::Loop(operand) => write!(current_line, "\tloop {}", operand)?,
 
                 Instruction::Label(label) => write!(current_line, "{}:", label)?,
@@ -826,6 +1024,7 @@ fn write_assembly(assembly: Assembly, mut output: impl Write) -> Result<(), Comp
                 Instruction::Pop(operand) => write!(current_line, "\tpop {}", operand)?,
                 Instruction::Push(operand) => write!(current_line, "\tpush {}", operand)?,
                 Instruction::Or(a, b) => write!(current_line, "\tor {}, {}", a, b)?,
+                Instruction::Raw(code) => write!(current_line, "{}", code.trim_end())?,
                 Instruction::Ret => write!(current_line, "\tret")?,
                 Instruction::SetE(operand) => write!(current_line, "\tsete {}", operand)?,
                 Instruction::SetG(operand) => write!(current_line, "\tsetg {}", operand)?,
@@ -861,13 +1060,146 @@ fn write_assembly(assembly: Assembly, mut output: impl Write) -> Result<(), Comp
 
 #[cfg(test)]
 mod tests {
+    use crate::AsmBuiltin;
     use crate::linux_x86_64_elf::compile;
+    use falsec_types::source::{Command, Pos, Program, Span};
+    use falsec_types::{Arithmetic, Config};
+    use std::collections::HashMap;
 
     #[test]
     fn simple_compile() {
         let mut output = Vec::new();
-        compile(Default::default(), &mut output, Default::default()).unwrap();
+        compile(
+            Default::default(),
+            &mut output,
+            Default::default(),
+            Vec::new(),
+            false,
+        )
+        .unwrap();
         let asm = String::from_utf8(output).unwrap();
         assert_ne!(asm.len(), 0);
     }
+
+    #[test]
+    fn checked_arithmetic() {
+        let span = Span::new(Pos::new(2, 1, 3), Pos::new(3, 1, 4), "/");
+        let program = Program {
+            main_id: 0,
+            lambdas: HashMap::from([(0, vec![(Command::Div, span)])]),
+            ..Default::default()
+        };
+        let config = Config {
+            arithmetic: Arithmetic::Checked,
+            ..Default::default()
+        };
+        let mut output = Vec::new();
+        compile(program, &mut output, config, Vec::new(), false).unwrap();
+        let asm = String::from_utf8(output).unwrap();
+        assert!(asm.contains("\"Runtime Error at 1\", 0x3a, \"3\", 0x3a, \" Division by zero\""));
+        assert!(asm.contains("Integer overflow in division"));
+    }
+
+    #[test]
+    fn exit_status_from_stack() {
+        let span = Span::new(Pos::new(0, 1, 1), Pos::new(1, 1, 2), "1");
+        let program = Program {
+            main_id: 0,
+            lambdas: HashMap::from([(0, vec![(Command::IntLiteral(1), span)])]),
+            ..Default::default()
+        };
+        let config = Config {
+            exit_status_from_stack: true,
+            ..Default::default()
+        };
+        let mut output = Vec::new();
+        compile(program.clone(), &mut output, config, Vec::new(), false).unwrap();
+        let asm = String::from_utf8(output).unwrap();
+        assert!(asm.contains("; Exit with the top of the stack:"));
+
+        let mut output = Vec::new();
+        compile(program, &mut output, Config::default(), Vec::new(), false).unwrap();
+        let asm = String::from_utf8(output).unwrap();
+        assert!(!asm.contains("; Exit with the top of the stack:"));
+    }
+
+    #[test]
+    fn builtins() {
+        let span = Span::new(Pos::new(0, 1, 1), Pos::new(1, 1, 2), "!");
+        let program = Program {
+            main_id: 0,
+            lambdas: HashMap::from([(0, vec![(Command::Exec, span)])]),
+            ..Default::default()
+        };
+        let builtin = AsmBuiltin {
+            id: -1,
+            code: "\tinc r12\n\tret\n".to_string(),
+        };
+        let mut output = Vec::new();
+        compile(
+            program.clone(),
+            &mut output,
+            Config::default(),
+            vec![builtin.clone()],
+            false,
+        )
+        .unwrap();
+        let asm = String::from_utf8(output).unwrap();
+        assert!(asm.contains("call builtin_dispatch"));
+        assert!(asm.contains("je _builtin_m001"));
+        assert!(asm.contains("_builtin_m001:\n\tinc r12\n\tret\n"));
+
+        let invalid = AsmBuiltin { id: 1, ..builtin };
+        let err = compile(
+            program,
+            &mut Vec::new(),
+            Config::default(),
+            vec![invalid],
+            false,
+        );
+        assert_eq!(
+            err.unwrap_err().to_string(),
+            "Compiler error: Invalid builtin id: 1"
+        );
+    }
+
+    #[test]
+    fn standard_builtins() {
+        let span = Span::new(Pos::new(0, 1, 1), Pos::new(1, 1, 2), "!");
+        let program = Program {
+            main_id: 0,
+            lambdas: HashMap::from([(0, vec![(Command::Exec, span)])]),
+            ..Default::default()
+        };
+        let mut output = Vec::new();
+        compile(
+            program.clone(),
+            &mut output,
+            Config::default(),
+            Vec::new(),
+            true,
+        )
+        .unwrap();
+        let asm = String::from_utf8(output).unwrap();
+        assert!(asm.contains("je _builtin_m001"));
+        assert!(asm.contains("je _builtin_m008"));
+        assert!(asm.contains("_builtin_m008:"));
+
+        // the standard builtins take their ids
+        let exit = AsmBuiltin {
+            id: falsec_types::builtins::EXIT,
+            code: "\tret\n".to_string(),
+        };
+        let err = compile(
+            program,
+            &mut Vec::new(),
+            Config::default(),
+            vec![exit],
+            true,
+        );
+        assert_eq!(
+            err.unwrap_err().to_string(),
+            "Compiler error: Invalid builtin id: -8"
+        );
+    }
 }
@@ -64,6 +64,10 @@ pub enum Instruction<'source> {
     Js(Operand<'source>),
     /// Jump if not sign
     Jns(Operand<'source>),
+    /// Jump if overflow
+    Jo(Operand<'source>),
+    /// Jump if not overflow
+    Jno(Operand<'source>),
     /// decrement rcx and jump if not zero
     Loop(Operand<'source>),
 
@@ -100,6 +104,8 @@ pub enum Instruction<'source> {
     Pop(Operand<'source>),
     /// push to call stack. not to be confused with the data stack.
     Push(Operand<'source>),
+    /// Assembly source that is written as is.
+    Raw(Cow<'source, str>),
     Ret,
     SetE(Operand<'source>),
     SetG(Operand<'source>),
@@ -165,9 +171,13 @@ pub enum Label<'source> {
     DecimalBuffer,
     StringLiteral(u64),
     StringLiteralLen(u64),
+    ErrorMessage(u64),
+    ErrorMessageLen(u64),
     Variables,
     VariableTypes,
     Named(&'source str),
+    Builtin(i64),
+    BuiltinDispatch,
 }
 
 impl fmt::Display for Label<'_> {
@@ -184,9 +194,14 @@ fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
             Label::DecimalBuffer => write!(f, "decimal_buffer"),
             Label::StringLiteral(id) => write!(f, "_string_{:03}", id),
             Label::StringLiteralLen(id) => write!(f, "_string_{:03}_len", id),
+            Label::ErrorMessage(id) => write!(f, "_error_{:03}", id),
+            Label::ErrorMessageLen(id) => write!(f, "_error_{:03}_len", id),
             Label::Variables => write!(f, "variables"),
             Label::VariableTypes => write!(f, "variable_types"),
             Label::Named(name) => write!(f, "{}", name),
+            Label::Builtin(id) if id < 0 => write!(f, "_builtin_m{:03}", id.unsigned_abs()),
+            Label::Builtin(id) => write!(f, "_builtin_{:03}", id),
+            Label::BuiltinDispatch => write!(f, "builtin_dispatch"),
         }
     }
 }
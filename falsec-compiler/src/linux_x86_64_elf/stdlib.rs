@@ -0,0 +1,314 @@
+use crate::linux_x86_64_elf::asm::{
+    Address, Instruction, Label, Register, RegisterSize, SectionId,
+};
+use crate::linux_x86_64_elf::{Assembly, ValueType};
+use falsec_types::builtins::{ARG, ARGC, CLOSE, EXIT, GETENV, OPEN, READ, WRITE};
+
+/// The ids of the standard builtins, see [falsec_types::builtins].
+pub(super) const STANDARD_BUILTINS: [i64; 8] = [ARGC, ARG, GETENV, OPEN, CLOSE, READ, WRITE, EXIT];
+
+const ARGC_LABEL: Label = Label::Named("argc");
+const ARGV_LABEL: Label = Label::Named("argv");
+const BYTE_BUFFER: Label = Label::Named("byte_buffer");
+const PATH_BUFFER: Label = Label::Named("path_buffer");
+const PATH_BUFFER_SIZE: u64 = 4096;
+/// `void push_cstring(const char *s)`, pushes the bytes of s and its length.
+const PUSH_CSTRING: Label = Label::Named("push_cstring");
+
+pub(super) trait StandardBuiltins {
+    /// Reserve memory for the builtins, and remember the arguments of the program.
+    /// Must be called at the start of the program, before anything is pushed to the call stack.
+    fn write_standard_setup(&mut self) -> &mut Self;
+
+    /// Write the builtin with the given id from [STANDARD_BUILTINS], after its label.
+    fn write_standard_builtin(&mut self, id: i64) -> &mut Self;
+
+    fn write_push_cstring(&mut self) -> &mut Self;
+}
+
+impl StandardBuiltins for Assembly<'_> {
+    fn write_standard_setup(&mut self) -> &mut Self {
+        self.add_instructions(
+            SectionId::Bss,
+            [
+                Instruction::Label(ARGC_LABEL),
+                Instruction::Reserve(RegisterSize::R, 1),
+                Instruction::Label(ARGV_LABEL),
+                Instruction::Reserve(RegisterSize::R, 1),
+                Instruction::Label(BYTE_BUFFER),
+                Instruction::Reserve(RegisterSize::L, 1),
+                Instruction::Label(PATH_BUFFER),
+                Instruction::Reserve(RegisterSize::L, PATH_BUFFER_SIZE),
+            ],
+        );
+        // the kernel passes argc at [rsp], followed by the argv pointers.
+        self.com("Save program arguments:")
+            .mov(Register::RAX, Address::b(Register::RSP))
+            .mov(Address::b(ARGC_LABEL), Register::RAX)
+            .lea(Register::RAX, Address::ba(Register::RSP, 8))
+            .mov(Address::b(ARGV_LABEL), Register::RAX)
+    }
+
+    fn write_standard_builtin(&mut self, id: i64) -> &mut Self {
+        match id {
+            ARGC => self
+                .com("( -- n )")
+                .mov(Register::RAX, Address::b(ARGC_LABEL))
+                .dec(Register::RAX) // without the program itself
+                .push(Register::RAX, ValueType::Number)
+                .ins(Instruction::Ret),
+            ARG => {
+                let missing = self.new_label();
+                self.com("( i -- c1 .. cn n )")
+                    .pop(Register::RAX, ValueType::Number)
+                    .test(Register::RAX, Register::RAX)
+                    .js(missing)
+                    .mov(Register::RCX, Address::b(ARGC_LABEL))
+                    .dec(Register::RCX)
+                    .cmp(Register::RAX, Register::RCX)
+                    .jnl(missing)
+                    .mov(Register::RSI, Address::b(ARGV_LABEL))
+                    .mov(
+                        Register::RSI,
+                        Address::biis(Register::RSI, Register::RAX, 1, 8),
+                    )
+                    .jmp(PUSH_CSTRING)
+                    .label(missing)
+                    .mov(Register::RAX, -1)
+                    .push(Register::RAX, ValueType::Number)
+                    .ins(Instruction::Ret)
+            }
+            GETENV => {
+                let next_variable = self.new_label();
+                let compare = self.new_label();
+                let name_end = self.new_label();
+                let next_entry = self.new_label();
+                let not_found = self.new_label();
+                self.com("( c1 .. cn n -- c1 .. cm m )")
+                    .pop_string()
+                    // envp follows the argv pointers and their terminating null pointer.
+                    .mov(Register::RDX, Address::b(ARGC_LABEL))
+                    .mov(Register::RSI, Address::b(ARGV_LABEL))
+                    .lea(
+                        Register::RSI,
+                        Address::biis(Register::RSI, Register::RDX, 1, 8),
+                    )
+                    .label(next_variable)
+                    .mov(Register::RDI, Address::b(Register::RSI))
+                    .test(Register::RDI, Register::RDI)
+                    .jz(not_found)
+                    .xor(Register::R9, Register::R9)
+                    // compare "NAME=" with the name on the stack
+                    .label(compare)
+                    .cmp(Register::R9, Register::RCX)
+                    .je(name_end)
+                    .movzx(
+                        Register::RAX,
+                        Address::bi(Register::RDI, Register::R9).with_size(RegisterSize::L),
+                    )
+                    .lea(Register::R10, Address::bi(Register::R8, Register::R9))
+                    .mov(
+                        Register::RDX,
+                        Address::bis(Register::STACK_BASE, Register::R10, 8),
+                    )
+                    .cmp(Register::AL, Register::DL)
+                    .jne(next_entry)
+                    .inc(Register::R9)
+                    .jmp(compare)
+                    .label(name_end)
+                    .cmp(
+                        Address::bi(Register::RDI, Register::R9).with_size(RegisterSize::L),
+                        b'=' as u64,
+                    )
+                    .jne(next_entry)
+                    .mov(Register::STACK_COUNTER, Register::R8)
+                    .lea(Register::RSI, Address::bia(Register::RDI, Register::R9, 1))
+                    .jmp(PUSH_CSTRING)
+                    .label(next_entry)
+                    .add(Register::RSI, 8)
+                    .jmp(next_variable)
+                    .label(not_found)
+                    .mov(Register::STACK_COUNTER, Register::R8)
+                    .mov(Register::RAX, -1)
+                    .push(Register::RAX, ValueType::Number)
+                    .ins(Instruction::Ret)
+            }
+            OPEN => {
+                let copy = self.new_label();
+                let copied = self.new_label();
+                let open = self.new_label();
+                let opened = self.new_label();
+                let too_long = self.new_label();
+                self.com("( c1 .. cn n mode -- fd )")
+                    .pop(Register::RDX, ValueType::Number)
+                    .pop_string()
+                    // the bytes stay in memory until something else is pushed.
+                    .mov(Register::STACK_COUNTER, Register::R8)
+                    .cmp(Register::RCX, PATH_BUFFER_SIZE as i64)
+                    .jnl(too_long)
+                    // copy the path into a null-terminated buffer
+                    .xor(Register::R9, Register::R9)
+                    .label(copy)
+                    .cmp(Register::R9, Register::RCX)
+                    .je(copied)
+                    .lea(Register::R10, Address::bi(Register::R8, Register::R9))
+                    .mov(
+                        Register::RAX,
+                        Address::bis(Register::STACK_BASE, Register::R10, 8),
+                    )
+                    .mov(
+                        Address::bi(PATH_BUFFER, Register::R9).with_size(RegisterSize::L),
+                        Register::AL,
+                    )
+                    .inc(Register::R9)
+                    .jmp(copy)
+                    .label(copied)
+                    .mov(
+                        Address::bi(PATH_BUFFER, Register::R9).with_size(RegisterSize::L),
+                        0,
+                    );
+                // O_RDONLY, O_WRONLY | O_CREAT | O_TRUNC, O_WRONLY | O_CREAT | O_APPEND
+                for (mode, flags) in [(0, 0o0), (1, 0o1101), (2, 0o2101)] {
+                    self.mov(Register::RSI, flags as u64)
+                        .cmp(Register::RDX, mode)
+                        .je(open);
+                }
+                self.fatal_error("\nRuntime Error: Builtin error: Invalid file mode\n".to_string())
+                    .label(open)
+                    .mov(Register::RAX, 2) // sys_open
+                    .lea(Register::RDI, Address::b(PATH_BUFFER))
+                    .mov(Register::RDX, 0o644)
+                    .ins(Instruction::Syscall)
+                    .test(Register::RAX, Register::RAX)
+                    .jns(opened)
+                    .label(too_long)
+                    .mov(Register::RAX, -1)
+                    .label(opened)
+                    .push(Register::RAX, ValueType::Number)
+                    .ins(Instruction::Ret)
+            }
+            CLOSE => {
+                let invalid = self.new_label();
+                let done = self.new_label();
+                self.com("( fd -- )")
+                    .pop(Register::RDI, ValueType::Number)
+                    .cmp(Register::RDI, 3) // stdin, stdout and stderr stay open
+                    .jl(invalid)
+                    .mov(Register::RAX, 3) // sys_close
+                    .ins(Instruction::Syscall)
+                    .test(Register::RAX, Register::RAX)
+                    .jns(done)
+                    .label(invalid)
+                    .invalid_file_descriptor()
+                    .label(done)
+                    .ins(Instruction::Ret)
+            }
+            READ => {
+                let invalid = self.new_label();
+                let eof = self.new_label();
+                let done = self.new_label();
+                self.com("( fd -- c )")
+                    .pop(Register::RDI, ValueType::Number)
+                    .cmp(Register::RDI, 1)
+                    .je(invalid)
+                    .cmp(Register::RDI, 2)
+                    .je(invalid)
+                    .xor(Register::RAX, Register::RAX) // sys_read
+                    .lea(Register::RSI, Address::b(BYTE_BUFFER))
+                    .mov(Register::RDX, 1)
+                    .ins(Instruction::Syscall)
+                    .test(Register::RAX, Register::RAX)
+                    .js(invalid)
+                    .jz(eof)
+                    .movzx(
+                        Register::RAX,
+                        Address::b(BYTE_BUFFER).with_size(RegisterSize::L),
+                    )
+                    .jmp(done)
+                    .label(eof)
+                    .mov(Register::RAX, -1)
+                    .label(done)
+                    .push(Register::RAX, ValueType::Number)
+                    .ins(Instruction::Ret)
+                    .label(invalid)
+                    .invalid_file_descriptor()
+            }
+            WRITE => {
+                let other = self.new_label();
+                let done = self.new_label();
+                self.com("( c fd -- )")
+                    .pop(Register::R8, ValueType::Number)
+                    .pop(Register::RDI, ValueType::Number)
+                    .cmp(Register::R8, 1)
+                    .jne(other)
+                    .call(Label::PrintChar)
+                    .ins(Instruction::Ret)
+                    .label(other)
+                    .mov(Address::b(BYTE_BUFFER), Register::DIL)
+                    // keep the order of messages that go to the same terminal.
+                    .call(Label::FlushStdout)
+                    .mov(Register::RAX, 1) // sys_write
+                    .mov(Register::RDI, Register::R8)
+                    .lea(Register::RSI, Address::b(BYTE_BUFFER))
+                    .mov(Register::RDX, 1)
+                    .ins(Instruction::Syscall)
+                    .test(Register::RAX, Register::RAX)
+                    .jns(done)
+                    .invalid_file_descriptor()
+                    .label(done)
+                    .ins(Instruction::Ret)
+            }
+            EXIT => self
+                .com("( n -- )")
+                .pop(Register::RDI, ValueType::Number)
+                .call(Label::FlushStdout) // preserves rdi
+                .mov(Register::RAX, 60) // sys_exit
+                .ins(Instruction::Syscall),
+            id => unreachable!("not a standard builtin: {id}"),
+        }
+    }
+
+    fn write_push_cstring(&mut self) -> &mut Self {
+        let next = self.new_label();
+        let done = self.new_label();
+        self.label(PUSH_CSTRING)
+            .com("void push_cstring(const char *s)")
+            .xor(Register::RCX, Register::RCX)
+            .label(next)
+            .movzx(
+                Register::RAX,
+                Address::bi(Register::RSI, Register::RCX).with_size(RegisterSize::L),
+            )
+            .test(Register::RAX, Register::RAX)
+            .jz(done)
+            .push(Register::RAX, ValueType::Number)
+            .inc(Register::RCX)
+            .jmp(next)
+            .label(done)
+            .push(Register::RCX, ValueType::Number)
+            .ins(Instruction::Ret)
+    }
+}
+
+impl Assembly<'_> {
+    /// Pop the length of a string into rcx, and leave the index of its first byte on the data
+    /// stack in r8. The bytes stay on the stack until the stack counter is set to r8.
+    fn pop_string(&mut self) -> &mut Self {
+        let invalid = self.new_label();
+        let valid = self.new_label();
+        self.pop(Register::RCX, ValueType::Number)
+            .test(Register::RCX, Register::RCX)
+            .js(invalid)
+            .cmp(Register::RCX, Register::STACK_COUNTER)
+            .jng(valid)
+            .label(invalid)
+            .fatal_error("\nRuntime Error: Builtin error: Invalid string length\n".to_string())
+            .label(valid)
+            .mov(Register::R8, Register::STACK_COUNTER)
+            .sub(Register::R8, Register::RCX)
+    }
+
+    fn invalid_file_descriptor(&mut self) -> &mut Self {
+        self.fatal_error("\nRuntime Error: Builtin error: Invalid file descriptor\n".to_string())
+    }
+}
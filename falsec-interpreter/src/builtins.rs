@@ -0,0 +1,188 @@
+use crate::error::{InterpreterError, ProgramPos};
+use crate::history::{Change, History};
+use crate::observer::ExecutionObserver;
+use crate::{Origins, StackValue, cast_integer, cast_lambda, cast_var, read_byte};
+use falsec_types::{Limits, TypeSafety};
+use std::collections::HashMap;
+use std::io::{Read, Write};
+
+/// A function implemented in Rust that FALSE code calls like a lambda.
+pub type Builtin = Box<dyn FnMut(&mut BuiltinContext) -> Result<(), InterpreterError> + Send>;
+
+/// Builtins by id, see [Builtins::register].
+///
+/// Builtins have negative ids, so they never collide with the lambdas of a program.
+/// `1_!` calls the builtin with id -1, `?` and `#` can call builtins the same way.
+/// A negative integer that names a registered builtin is accepted wherever a lambda is
+/// expected, regardless of [TypeSafety].
+#[derive(Default)]
+pub struct Builtins(HashMap<u64, Builtin>);
+
+impl Builtins {
+    /// Register `builtin` under `id`, replacing any builtin with the same id.
+    ///
+    /// # Panics
+    ///
+    /// If `id` is not negative.
+    pub fn register(
+        &mut self,
+        id: i64,
+        builtin: impl FnMut(&mut BuiltinContext) -> Result<(), InterpreterError> + Send + 'static,
+    ) {
+        assert!(id < 0, "builtin ids must be negative, got {id}");
+        self.0.insert(id as u64, Box::new(builtin));
+    }
+
+    /// Returns true if a builtin was registered.
+    pub fn remove(&mut self, id: i64) -> bool {
+        self.0.remove(&(id as u64)).is_some()
+    }
+
+    /// The ids of all registered builtins.
+    pub fn ids(&self) -> impl Iterator<Item = i64> + '_ {
+        self.0.keys().map(|id| *id as i64)
+    }
+
+    /// Whether the lambda id belongs to a builtin.
+    pub fn contains(&self, lambda_id: u64) -> bool {
+        !self.0.is_empty() && self.0.contains_key(&lambda_id)
+    }
+
+    pub(crate) fn get_mut(&mut self, lambda_id: u64) -> Option<&mut Builtin> {
+        self.0.get_mut(&lambda_id)
+    }
+
+    /// The lambda id of a value that is called with `!`, `?` or `#`.
+    pub(crate) fn callee(
+        &self,
+        value: StackValue,
+        type_safety: TypeSafety,
+    ) -> Result<u64, (&'static str, &'static str)> {
+        match value {
+            StackValue::Integer(i) if i < 0 && self.contains(i as u64) => Ok(i as u64),
+            value => cast_lambda(value, type_safety),
+        }
+    }
+}
+
+/// What a [Builtin] can access while it runs: the data stack, the program's input and
+/// output, and the observer of the interpreter. Limits and type safety apply as for commands.
+pub struct BuiltinContext<'a> {
+    pub(crate) data_stack: &'a mut Vec<StackValue>,
+    /// Kept in sync with the data stack, if the interpreter tracks origins.
+    pub(crate) origins: Option<&'a mut Origins>,
+    /// Records the changes, if the interpreter keeps a history.
+    pub(crate) history: Option<&'a mut History>,
+    /// Where the builtin was called, the origin of the values it pushes.
+    pub(crate) origin: Option<ProgramPos>,
+    pub(crate) input: &'a mut dyn Read,
+    pub(crate) output: &'a mut dyn Write,
+    pub(crate) observer: &'a mut dyn ExecutionObserver,
+    pub(crate) type_safety: TypeSafety,
+    pub(crate) limits: Limits,
+    pub(crate) output_bytes: &'a mut u64,
+    /// Where the builtin was called.
+    pub(crate) backtrace: Vec<ProgramPos>,
+}
+
+impl BuiltinContext<'_> {
+    /// The data stack, bottom first.
+    pub fn data_stack(&self) -> &[StackValue] {
+        self.data_stack
+    }
+
+    pub fn pop(&mut self) -> Result<StackValue, InterpreterError> {
+        let value = self.data_stack.pop().ok_or_else(|| {
+            InterpreterError::tried_to_pop_from_empty_data_stack(self.backtrace.clone())
+        })?;
+        let origin = self.origins.as_mut().and_then(|origins| origins.pop(value));
+        if let Some(history) = &mut self.history {
+            history.record(Change::Pop(value, origin));
+        }
+        Ok(value)
+    }
+
+    pub fn pop_integer(&mut self) -> Result<i64, InterpreterError> {
+        let value = self.pop()?;
+        cast_integer(value, self.type_safety).map_err(|(from, to)| self.type_cast_error(from, to))
+    }
+
+    pub fn pop_var(&mut self) -> Result<char, InterpreterError> {
+        let value = self.pop()?;
+        cast_var(value, self.type_safety).map_err(|(from, to)| self.type_cast_error(from, to))
+    }
+
+    pub fn pop_lambda(&mut self) -> Result<u64, InterpreterError> {
+        let value = self.pop()?;
+        cast_lambda(value, self.type_safety).map_err(|(from, to)| self.type_cast_error(from, to))
+    }
+
+    pub fn push(&mut self, value: StackValue) -> Result<(), InterpreterError> {
+        if let Some(limit) = self.limits.max_stack_depth
+            && self.data_stack.len() >= limit
+        {
+            return Err(InterpreterError::stack_limit_exceeded(
+                self.backtrace.clone(),
+                limit,
+            ));
+        }
+        self.data_stack.push(value);
+        if let Some(origins) = &mut self.origins {
+            origins.push(self.origin);
+        }
+        if let Some(history) = &mut self.history {
+            history.record(Change::Push);
+        }
+        Ok(())
+    }
+
+    pub fn push_integer(&mut self, i: i64) -> Result<(), InterpreterError> {
+        self.push(StackValue::Integer(i))
+    }
+
+    /// Read a byte from the input like `^`, or `None` at the end of the input.
+    pub fn read_byte(&mut self) -> Result<Option<u8>, InterpreterError> {
+        let byte =
+            read_byte(self.input, self.history.as_deref_mut()).map_err(|e| self.io_error(e))?;
+        self.observer.read(byte);
+        Ok(byte)
+    }
+
+    /// Write to the output, counting towards [Limits::max_output_bytes].
+    pub fn write(&mut self, bytes: &[u8]) -> Result<(), InterpreterError> {
+        if let Some(limit) = self.limits.max_output_bytes
+            && *self.output_bytes + bytes.len() as u64 > limit
+        {
+            return Err(InterpreterError::output_limit_exceeded(
+                self.backtrace.clone(),
+                limit,
+            ));
+        }
+        *self.output_bytes += bytes.len() as u64;
+        self.output.write_all(bytes).map_err(|e| self.io_error(e))?;
+        self.observer.write(bytes);
+        Ok(())
+    }
+
+    pub fn flush(&mut self) -> Result<(), InterpreterError> {
+        self.output.flush().map_err(|e| self.io_error(e))
+    }
+
+    /// An error at the position where the builtin was called.
+    pub fn error(&self, message: impl Into<String>) -> InterpreterError {
+        InterpreterError::builtin_error(self.backtrace.clone(), message.into())
+    }
+
+    /// Stop the program with [crate::error::InterpreterErrorKind::Exit].
+    pub fn exit(&self, status: i64) -> InterpreterError {
+        InterpreterError::exit(self.backtrace.clone(), status)
+    }
+
+    pub fn io_error(&self, err: std::io::Error) -> InterpreterError {
+        InterpreterError::io_error(self.backtrace.clone(), err)
+    }
+
+    fn type_cast_error(&self, from: &'static str, to: &'static str) -> InterpreterError {
+        InterpreterError::type_cast_error(self.backtrace.clone(), from, to)
+    }
+}
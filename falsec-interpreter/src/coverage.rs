@@ -0,0 +1,130 @@
+use crate::observer::{CommandEvent, ExecutionObserver};
+use falsec_types::source::{Command, LambdaCommand, Program};
+use std::collections::{BTreeMap, HashMap};
+use std::io::{self, Write};
+
+/// Records which commands were executed and which branches were taken, when attached to an
+/// interpreter with [crate::Interpreter::with_observer].
+#[derive(Clone, Eq, PartialEq, Debug, Default)]
+pub struct Coverage {
+    /// How often each command was executed, keyed by (lambda id, program counter).
+    /// A `#` is executed once more for each evaluation of its condition.
+    pub commands: HashMap<(u64, usize), u64>,
+    /// How often the condition of each `?` and `#` was true and false, keyed by
+    /// (lambda id, program counter) of the `?` or `#`.
+    pub branches: HashMap<(u64, usize), Branches>,
+    /// How often each lambda was called by `!`, `?` or `#`.
+    pub calls: HashMap<u64, u64>,
+}
+
+#[derive(Copy, Clone, Eq, PartialEq, Debug, Default)]
+pub struct Branches {
+    pub taken: u64,
+    pub not_taken: u64,
+}
+
+impl ExecutionObserver for Coverage {
+    fn before_command(&mut self, event: &CommandEvent) {
+        *self
+            .commands
+            .entry((event.lambda_id, event.program_counter))
+            .or_default() += 1;
+    }
+
+    fn lambda_call(&mut self, lambda_id: u64) {
+        *self.calls.entry(lambda_id).or_default() += 1;
+    }
+
+    fn conditional(&mut self, event: &CommandEvent, taken: bool) {
+        self.record_branch(event, taken);
+    }
+
+    fn loop_iteration(&mut self, event: &CommandEvent, continues: bool) {
+        self.record_branch(event, continues);
+    }
+}
+
+impl Coverage {
+    fn record_branch(&mut self, event: &CommandEvent, taken: bool) {
+        let branches = self
+            .branches
+            .entry((event.lambda_id, event.program_counter))
+            .or_default();
+        if taken {
+            branches.taken += 1;
+        } else {
+            branches.not_taken += 1;
+        }
+    }
+
+    /// Write the coverage of `program` in the LCOV tracefile format.
+    ///
+    /// Each line gets the highest execution count of the commands starting on it, comments
+    /// are ignored. Lambdas are reported as functions named after their position, e.g.
+    /// `lambda@3:5`, and each `?` and `#` as a branch with two outcomes.
+    pub fn write_lcov(
+        &self,
+        program: &Program,
+        source_path: &str,
+        out: &mut impl Write,
+    ) -> io::Result<()> {
+        let mut lines = BTreeMap::<usize, u64>::new();
+        let mut branches = BTreeMap::<(usize, usize), Branches>::new();
+        let mut functions = BTreeMap::<(usize, usize), u64>::new();
+        for (&lambda_id, lambda) in &program.lambdas {
+            for (pc, (command, span)) in lambda.iter().enumerate() {
+                let position = (span.start.line, span.start.column);
+                match command {
+                    Command::Comment(_) => continue,
+                    Command::Lambda(LambdaCommand::LambdaReference(id)) => {
+                        functions.insert(position, self.calls.get(id).copied().unwrap_or(0));
+                    }
+                    Command::Conditional | Command::While => {
+                        let taken = self.branches.get(&(lambda_id, pc)).copied();
+                        branches.insert(position, taken.unwrap_or_default());
+                    }
+                    _ => (),
+                }
+                let count = self.commands.get(&(lambda_id, pc)).copied().unwrap_or(0);
+                let line = lines.entry(span.start.line).or_default();
+                *line = (*line).max(count);
+            }
+        }
+
+        writeln!(out, "TN:")?;
+        writeln!(out, "SF:{source_path}")?;
+        for (line, column) in functions.keys() {
+            writeln!(out, "FN:{line},lambda@{line}:{column}")?;
+        }
+        for ((line, column), calls) in &functions {
+            writeln!(out, "FNDA:{calls},lambda@{line}:{column}")?;
+        }
+        writeln!(out, "FNF:{}", functions.len())?;
+        writeln!(
+            out,
+            "FNH:{}",
+            functions.values().filter(|c| **c > 0).count()
+        )?;
+        let mut hit = 0;
+        for (block, ((line, _), b)) in branches.iter().enumerate() {
+            // "-" means that the `?` or `#` itself was never executed.
+            let executed = b.taken + b.not_taken > 0;
+            for (branch, count) in [b.taken, b.not_taken].into_iter().enumerate() {
+                if executed {
+                    writeln!(out, "BRDA:{line},{block},{branch},{count}")?;
+                } else {
+                    writeln!(out, "BRDA:{line},{block},{branch},-")?;
+                }
+                hit += (count > 0) as usize;
+            }
+        }
+        writeln!(out, "BRF:{}", branches.len() * 2)?;
+        writeln!(out, "BRH:{hit}")?;
+        for (line, count) in &lines {
+            writeln!(out, "DA:{line},{count}")?;
+        }
+        writeln!(out, "LF:{}", lines.len())?;
+        writeln!(out, "LH:{}", lines.values().filter(|c| **c > 0).count())?;
+        writeln!(out, "end_of_record")
+    }
+}
@@ -1,4 +1,6 @@
-use falsec_types::source::Pos;
+use crate::StackValue;
+use falsec_types::source::{Command, LambdaCommand, OwnedSpan, Pos, Program};
+use std::collections::HashMap;
 use std::error::Error;
 use std::fmt;
 use std::rc::Rc;
@@ -14,6 +16,35 @@ pub struct ProgramPos {
 pub struct InterpreterError {
     pub backtrace: Vec<ProgramPos>,
     pub kind: InterpreterErrorKind,
+    /// The state of the program when the error occurred, if the interpreter captured it.
+    pub context: Option<Box<ErrorContext>>,
+}
+
+/// The state of the program when an error occurred. The interpreter captures it if
+/// [falsec_types::Config::print_backtrace] is set.
+#[derive(Clone, Eq, PartialEq, Debug, Default)]
+pub struct ErrorContext {
+    /// The top of the data stack before the failing command, bottom first.
+    /// At most [ErrorContext::STACK_TOP] values.
+    pub data_stack: Vec<StackValue>,
+    /// Number of values below [ErrorContext::data_stack].
+    pub hidden_values: usize,
+    /// The assigned variables, in alphabetical order.
+    pub variables: Vec<(char, StackValue)>,
+    /// The value that caused the error, e.g. one that could not be cast or a zero divisor.
+    pub value: Option<OffendingValue>,
+}
+
+impl ErrorContext {
+    pub const STACK_TOP: usize = 16;
+}
+
+#[derive(Clone, Eq, PartialEq, Debug)]
+pub struct OffendingValue {
+    pub value: StackValue,
+    /// The command that pushed the value, or `None` if it was on the stack from the start.
+    /// Values keep their origin when they are moved, e.g. with `\` or through a variable.
+    pub origin: Option<OwnedSpan>,
 }
 
 #[derive(Clone, Debug)]
@@ -21,6 +52,8 @@ pub enum InterpreterErrorKind {
     InvalidLambdaReference(u64),
     InvalidProgramCounter(usize),
     LambdaDefinitionNotAllowed,
+    /// A variable name is not in `a..=z`, as in [falsec_types::source::Command::Var].
+    InvalidVariableName(char),
     TriedToPopFromEmptyCallStack,
     TriedToPopFromEmptyDataStack,
     TypeCastError {
@@ -29,6 +62,23 @@ pub enum InterpreterErrorKind {
     },
     IndexOutOfBounds(i64, usize),
     IO(Rc<std::io::Error>),
+    DivisionByZero,
+    /// The result of the named operation does not fit into 64 bits.
+    IntegerOverflow(&'static str),
+    InstructionLimitExceeded(u64),
+    StackLimitExceeded(usize),
+    CallDepthLimitExceeded(usize),
+    /// The program ran longer than the given number of milliseconds.
+    Timeout(u64),
+    OutputLimitExceeded(u64),
+    /// Execution was cancelled through a [crate::CancellationToken].
+    /// Contains the data stack at that point, bottom first.
+    Interrupted(Vec<StackValue>),
+    /// A [crate::builtins::Builtin] failed with this message.
+    Builtin(String),
+    /// The program asked to exit with this status, e.g. through
+    /// [falsec_types::builtins::EXIT].
+    Exit(i64),
 }
 
 impl Error for InterpreterError {}
@@ -69,6 +119,195 @@ macro_rules! write_pos {
 
         BT(self, path)
     }
+
+    /// Like [InterpreterError::fmt_backtrace], but the frames name their lambdas and show the
+    /// source line of each call site. Repeated frames of a recursion are collapsed.
+    pub fn fmt_symbolic_backtrace<'a>(
+        &'a self,
+        path: &'a str,
+        symbols: &'a BacktraceSymbols,
+    ) -> impl fmt::Display + 'a {
+        struct BT<'a, 'p, 's>(&'a InterpreterError, &'a str, &'a BacktraceSymbols<'p, 's>);
+
+        impl fmt::Display for BT<'_, '_, '_> {
+            fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                let BT(error, path, symbols) = self;
+                let frames = &error.backtrace;
+                let mut i = 0;
+                while i < frames.len() {
+                    let (period, hidden) = find_recursion(&frames[i..]);
+                    let shown = if hidden > 0 { period } else { 1 };
+                    for frame in &frames[i..i + shown] {
+                        if i > 0 {
+                            writeln!(f)?;
+                        }
+                        let label = if i == 0 { "raised at  " } else { "called from" };
+                        let name = symbols.describe_lambda(frame.lambda_id);
+                        let (line, column) = (frame.pos.line, frame.pos.column);
+                        write!(f, "  {label} {path}:{line}:{column} in {name}")?;
+                        symbols.fmt_snippet(f, frame)?;
+                        i += 1;
+                    }
+                    if hidden > 0 {
+                        let name = symbols.recursion_name(&frames[i - shown..i]);
+                        let noun = if hidden == 1 { "frame" } else { "frames" };
+                        write!(f, "\n  ... {hidden} more {noun} of {name}")?;
+                        i += hidden;
+                    }
+                }
+                Ok(())
+            }
+        }
+
+        BT(self, path, symbols)
+    }
+}
+
+/// Recursion through `?` or `#` alternates between lambdas, so look for cycles of up to this
+/// many frames.
+const MAX_RECURSION_PERIOD: usize = 4;
+
+/// Find frames that repeat the lambdas of the first `period` frames, e.g. in a recursion.
+/// Returns the period that hides the most frames, and the number of hidden frames.
+fn find_recursion(frames: &[ProgramPos]) -> (usize, usize) {
+    (1..=MAX_RECURSION_PERIOD.min(frames.len()))
+        .map(|period| {
+            let (cycle, rest) = frames.split_at(period);
+            let repeats = rest
+                .chunks_exact(period)
+                .take_while(|chunk| {
+                    chunk
+                        .iter()
+                        .zip(cycle)
+                        .all(|(a, b)| a.lambda_id == b.lambda_id)
+                })
+                .count();
+            (period, repeats * period)
+        })
+        // prefer the shortest cycle, e.g. `f f f f` over `(f f) (f f)`
+        .max_by_key(|&(period, hidden)| (hidden, std::cmp::Reverse(period)))
+        .unwrap_or((1, 0))
+}
+
+/// What [InterpreterError::fmt_symbolic_backtrace] needs to describe the frames.
+pub struct BacktraceSymbols<'a, 'source> {
+    pub program: &'a Program<'source>,
+    /// The source code of the program, for the snippets of the call sites.
+    pub source: &'a str,
+    /// The variable each lambda was most recently stored in,
+    /// e.g. from [crate::Interpreter::lambda_names].
+    pub lambda_names: &'a HashMap<u64, char>,
+}
+
+impl BacktraceSymbols<'_, '_> {
+    /// The name of a lambda with where it is defined, e.g. `f (defined at 3:5)`.
+    pub fn describe_lambda(&self, id: u64) -> String {
+        let name = self.short_name(id);
+        match self.definition(id) {
+            Some(pos) => format!("{name} (defined at {}:{})", pos.line, pos.column),
+            None => name,
+        }
+    }
+
+    /// The name of a recursion through `cycle`: its first named lambda, as the helpers in
+    /// between are usually the bodies of `?` and `#`.
+    fn recursion_name(&self, cycle: &[ProgramPos]) -> String {
+        let named = cycle
+            .iter()
+            .find(|frame| self.lambda_names.contains_key(&frame.lambda_id))
+            .unwrap_or(&cycle[0]);
+        self.short_name(named.lambda_id)
+    }
+
+    /// `main`, the variable the lambda was stored in, or `lambda 3`.
+    fn short_name(&self, id: u64) -> String {
+        if id == self.program.main_id {
+            "main".to_string()
+        } else if let Some(name) = self.lambda_names.get(&id) {
+            name.to_string()
+        } else {
+            format!("lambda {id}")
+        }
+    }
+
+    /// Where the lambda literal starts.
+    fn definition(&self, id: u64) -> Option<Pos> {
+        self.program
+            .lambdas
+            .values()
+            .flatten()
+            .find_map(|(command, span)| {
+                matches!(command, Command::Lambda(LambdaCommand::LambdaReference(i)) if *i == id)
+                    .then_some(span.start)
+            })
+    }
+
+    /// The source line of the command at `frame`, with the command underlined.
+    fn fmt_snippet(&self, f: &mut fmt::Formatter<'_>, frame: &ProgramPos) -> fmt::Result {
+        let Some((_, span)) = self
+            .program
+            .lambdas
+            .get(&frame.lambda_id)
+            .and_then(|lambda| lambda.get(frame.program_counter))
+        else {
+            return Ok(());
+        };
+        let Some(before) = self.source.get(..span.start.offset) else {
+            return Ok(());
+        };
+        let line_start = before.rfind('\n').map_or(0, |i| i + 1);
+        let line_end = self.source[span.start.offset..]
+            .find('\n')
+            .map_or(self.source.len(), |i| span.start.offset + i);
+        // multi-line commands are only underlined on their first line.
+        let end = span.end.offset.clamp(span.start.offset, line_end);
+        let line = &self.source[line_start..line_end];
+        let before = &self.source[line_start..span.start.offset];
+        let command = &self.source[span.start.offset..end];
+        let number = span.start.line.to_string();
+        let gutter = " ".repeat(number.len());
+        // keep tabs, so that the underline lines up with the command.
+        let indent: String = before
+            .chars()
+            .map(|c| if c == '\t' { '\t' } else { ' ' })
+            .collect();
+        let underline = "^".repeat(command.chars().count().max(1));
+        write!(
+            f,
+            "\n    {number} | {line}\n    {gutter} | {indent}{underline}"
+        )
+    }
+}
+
+impl fmt::Display for ErrorContext {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let values: Vec<_> = self.data_stack.iter().map(|v| v.to_string()).collect();
+        write!(f, "data stack: ")?;
+        if self.hidden_values > 0 {
+            write!(f, "{} more values, then ", self.hidden_values)?;
+        }
+        writeln!(f, "[{}]", values.join(", "))?;
+        write!(f, "variables: ")?;
+        if self.variables.is_empty() {
+            write!(f, "none")?;
+        }
+        for (i, (name, value)) in self.variables.iter().enumerate() {
+            let sep = if i == 0 { "" } else { ", " };
+            write!(f, "{sep}{name} = {value}")?;
+        }
+        if let Some(OffendingValue { value, origin }) = &self.value {
+            write!(f, "\nvalue: {} ({})", value, value.type_name())?;
+            match origin {
+                Some(span) => write!(
+                    f,
+                    ", pushed at {}:{} by `{}`",
+                    span.start.line, span.start.column, span.source
+                )?,
+                None => write!(f, ", on the stack from the start")?,
+            }
+        }
+        Ok(())
+    }
 }
 
 impl fmt::Display for InterpreterErrorKind {
@@ -78,6 +317,7 @@ fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
             InvalidLambdaReference(id) => write!(f, "Invalid lambda reference: {}", id),
             InvalidProgramCounter(pc) => write!(f, "Invalid program counter: {}", pc),
             LambdaDefinitionNotAllowed => write!(f, "Lambda definition not allowed"),
+            InvalidVariableName(c) => write!(f, "Invalid variable name: '{}'", c),
             TriedToPopFromEmptyCallStack => write!(f, "Tried to pop from empty call stack"),
             TriedToPopFromEmptyDataStack => write!(f, "Tried to pop from empty data stack"),
             TypeCastError { from, to } => write!(f, "Type cast error: {} -> {}", from, to),
@@ -85,6 +325,20 @@ fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
                 write!(f, "Index out of bounds: {} must be in 0..{}", index, len)
             }
             IO(err) => write!(f, "IO error: {}", err),
+            DivisionByZero => write!(f, "Division by zero"),
+            IntegerOverflow(op) => write!(f, "Integer overflow in {}", op),
+            InstructionLimitExceeded(limit) => {
+                write!(f, "Instruction limit exceeded: {} instructions", limit)
+            }
+            StackLimitExceeded(limit) => write!(f, "Stack limit exceeded: {} values", limit),
+            CallDepthLimitExceeded(limit) => {
+                write!(f, "Call depth limit exceeded: {} calls", limit)
+            }
+            Timeout(ms) => write!(f, "Timeout: {} ms", ms),
+            OutputLimitExceeded(limit) => write!(f, "Output limit exceeded: {} bytes", limit),
+            Interrupted(_) => write!(f, "Interrupted"),
+            Builtin(message) => write!(f, "Builtin error: {}", message),
+            Exit(status) => write!(f, "Exit with status {}", status),
         }
     }
 }
@@ -94,6 +348,7 @@ pub fn invalid_lambda_reference(backtrace: Vec<ProgramPos>, id: u64) -> Self {
         Self {
             backtrace,
             kind: InterpreterErrorKind::InvalidLambdaReference(id),
+            context: None,
         }
     }
 
@@ -101,6 +356,7 @@ pub fn invalid_program_counter(backtrace: Vec<ProgramPos>, pc: usize) -> Self {
         Self {
             backtrace,
             kind: InterpreterErrorKind::InvalidProgramCounter(pc),
+            context: None,
         }
     }
 
@@ -108,6 +364,15 @@ pub fn lambda_definition_not_allowed(backtrace: Vec<ProgramPos>) -> Self {
         Self {
             backtrace,
             kind: InterpreterErrorKind::LambdaDefinitionNotAllowed,
+            context: None,
+        }
+    }
+
+    pub fn invalid_variable_name(backtrace: Vec<ProgramPos>, c: char) -> Self {
+        Self {
+            backtrace,
+            kind: InterpreterErrorKind::InvalidVariableName(c),
+            context: None,
         }
     }
 
@@ -115,6 +380,7 @@ pub fn tried_to_pop_from_empty_call_stack(backtrace: Vec<ProgramPos>) -> Self {
         Self {
             backtrace,
             kind: InterpreterErrorKind::TriedToPopFromEmptyCallStack,
+            context: None,
         }
     }
 
@@ -122,6 +388,7 @@ pub fn tried_to_pop_from_empty_data_stack(backtrace: Vec<ProgramPos>) -> Self {
         Self {
             backtrace,
             kind: InterpreterErrorKind::TriedToPopFromEmptyDataStack,
+            context: None,
         }
     }
 
@@ -133,6 +400,7 @@ pub fn type_cast_error(
         Self {
             backtrace,
             kind: InterpreterErrorKind::TypeCastError { from, to },
+            context: None,
         }
     }
 
@@ -140,6 +408,7 @@ pub fn index_out_of_bounds(backtrace: Vec<ProgramPos>, index: i64, len: usize) -
         Self {
             backtrace,
             kind: InterpreterErrorKind::IndexOutOfBounds(index, len),
+            context: None,
         }
     }
 
@@ -147,6 +416,87 @@ pub fn io_error(backtrace: Vec<ProgramPos>, err: std::io::Error) -> Self {
         Self {
             backtrace,
             kind: InterpreterErrorKind::IO(Rc::new(err)),
+            context: None,
+        }
+    }
+
+    pub fn division_by_zero(backtrace: Vec<ProgramPos>) -> Self {
+        Self {
+            backtrace,
+            kind: InterpreterErrorKind::DivisionByZero,
+            context: None,
+        }
+    }
+
+    pub fn integer_overflow(backtrace: Vec<ProgramPos>, op: &'static str) -> Self {
+        Self {
+            backtrace,
+            kind: InterpreterErrorKind::IntegerOverflow(op),
+            context: None,
+        }
+    }
+
+    pub fn instruction_limit_exceeded(backtrace: Vec<ProgramPos>, limit: u64) -> Self {
+        Self {
+            backtrace,
+            kind: InterpreterErrorKind::InstructionLimitExceeded(limit),
+            context: None,
+        }
+    }
+
+    pub fn stack_limit_exceeded(backtrace: Vec<ProgramPos>, limit: usize) -> Self {
+        Self {
+            backtrace,
+            kind: InterpreterErrorKind::StackLimitExceeded(limit),
+            context: None,
+        }
+    }
+
+    pub fn call_depth_limit_exceeded(backtrace: Vec<ProgramPos>, limit: usize) -> Self {
+        Self {
+            backtrace,
+            kind: InterpreterErrorKind::CallDepthLimitExceeded(limit),
+            context: None,
+        }
+    }
+
+    pub fn timeout(backtrace: Vec<ProgramPos>, ms: u64) -> Self {
+        Self {
+            backtrace,
+            kind: InterpreterErrorKind::Timeout(ms),
+            context: None,
+        }
+    }
+
+    pub fn output_limit_exceeded(backtrace: Vec<ProgramPos>, limit: u64) -> Self {
+        Self {
+            backtrace,
+            kind: InterpreterErrorKind::OutputLimitExceeded(limit),
+            context: None,
+        }
+    }
+
+    pub fn interrupted(backtrace: Vec<ProgramPos>, data_stack: Vec<StackValue>) -> Self {
+        Self {
+            backtrace,
+            kind: InterpreterErrorKind::Interrupted(data_stack),
+            context: None,
+        }
+    }
+
+    pub fn builtin_error(backtrace: Vec<ProgramPos>, message: String) -> Self {
+        Self {
+            backtrace,
+            kind: InterpreterErrorKind::Builtin(message),
+            context: None,
+        }
+    }
+
+    pub fn exit(backtrace: Vec<ProgramPos>, status: i64) -> Self {
+        Self {
+            backtrace,
+            kind: InterpreterErrorKind::Exit(status),
+            context: None,
         }
     }
 }
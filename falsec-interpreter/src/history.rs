@@ -0,0 +1,110 @@
+use crate::error::ProgramPos;
+use crate::{StackFrame, StackValue};
+use falsec_types::source::Pos;
+use std::collections::VecDeque;
+
+/// The undo log of an interpreter, see [crate::Interpreter::set_history_size].
+pub(crate) struct History {
+    /// Oldest first, at most `size` steps.
+    steps: VecDeque<Step>,
+    size: usize,
+    /// The changes of the step that is executing.
+    changes: Vec<Change>,
+    /// Bytes that were read by undone steps, to be read again when they are executed again.
+    /// The next byte is last, `None` is the end of the input.
+    unread: Vec<Option<u8>>,
+}
+
+/// An executed command, with what is needed to undo it.
+pub(crate) struct Step {
+    /// The frame before the command, which points at it.
+    pub(crate) frame: StackFrame,
+    pub(crate) instructions: u64,
+    pub(crate) output_bytes: u64,
+    /// In the order they happened, undone in reverse.
+    pub(crate) changes: Vec<Change>,
+}
+
+pub(crate) enum Change {
+    Push,
+    Pop(StackValue, Option<ProgramPos>),
+    /// The current frame was pushed onto the call stack to call a lambda.
+    Call,
+    /// A lambda returned, this was its frame and the position of the call in its caller,
+    /// which then moves on to its next command.
+    Return(StackFrame, Pos),
+    /// A variable was stored, this was its previous value and origin.
+    Store(char, Option<StackValue>, Option<Option<ProgramPos>>),
+    /// A lambda was stored in a variable, this was its previous name.
+    Name(u64, Option<char>),
+    Read(Option<u8>),
+}
+
+impl History {
+    pub(crate) fn new(size: usize) -> Self {
+        Self {
+            steps: VecDeque::new(),
+            size,
+            changes: Vec::new(),
+            unread: Vec::new(),
+        }
+    }
+
+    pub(crate) fn record(&mut self, change: Change) {
+        self.changes.push(change);
+    }
+
+    /// Finish the step that recorded the changes since the last call, dropping the oldest
+    /// step if the history is full.
+    pub(crate) fn push(&mut self, frame: StackFrame, instructions: u64, output_bytes: u64) {
+        if self.steps.len() == self.size {
+            self.steps.pop_front();
+        }
+        self.steps.push_back(Step {
+            frame,
+            instructions,
+            output_bytes,
+            changes: std::mem::take(&mut self.changes),
+        });
+    }
+
+    /// Forget changes that did not belong to an executed command.
+    pub(crate) fn discard(&mut self) {
+        self.changes.clear();
+    }
+
+    pub(crate) fn pop(&mut self) -> Option<Step> {
+        self.steps.pop_back()
+    }
+
+    /// Read a byte again that was read by an undone step.
+    pub(crate) fn reread(&mut self) -> Option<Option<u8>> {
+        self.unread.pop()
+    }
+
+    pub(crate) fn unread(&mut self, byte: Option<u8>) {
+        self.unread.push(byte);
+    }
+
+    /// Forget all steps, e.g. when the state is replaced. Bytes that are to be read again
+    /// are kept, as they were already taken from the input.
+    pub(crate) fn clear(&mut self) {
+        self.steps.clear();
+        self.changes.clear();
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.steps.len()
+    }
+
+    pub(crate) fn resize(&mut self, size: usize) {
+        self.size = size;
+        let excess = self.steps.len().saturating_sub(size);
+        self.steps.drain(..excess);
+    }
+
+    /// Newest first.
+    pub(crate) fn steps(&self) -> impl Iterator<Item = &Step> {
+        self.steps.iter().rev()
+    }
+}
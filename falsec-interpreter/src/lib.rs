@@ -1,472 +1,1542 @@
-mod error;
+pub mod builtins;
+pub mod coverage;
+pub mod error;
+mod history;
+pub mod observer;
+pub mod snapshot;
+pub mod stdlib;
+pub mod vm;
 
-use crate::error::{InterpreterError, ProgramPos};
+use crate::builtins::{BuiltinContext, Builtins};
+use crate::error::{
+    ErrorContext, InterpreterError, InterpreterErrorKind, OffendingValue, ProgramPos,
+};
+use crate::history::{Change, History};
+use crate::observer::{CommandEvent, ExecutionObserver};
+use crate::snapshot::{Snapshot, SnapshotError, program_hash};
 use falsec_types::source::{Command, Lambda, LambdaCommand, Pos, Program, Span};
-use falsec_types::{Config, TypeSafety};
+use falsec_types::{Arithmetic, Config, Limits, TypeSafety};
 use std::collections::HashMap;
+use std::fmt;
 use std::io::{ErrorKind, Read, Write};
 use std::iter::once;
-#[cfg(test)]
-use std::{cell::RefCell, rc::Rc};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Instant;
 
-pub struct Interpreter<'source, Input: Read, Output: Write> {
+/// A resumable FALSE interpreter.
+///
+/// Use [Interpreter::run] to execute a program to completion, or [Interpreter::step] and
+/// [Interpreter::run_until] to pause execution and inspect the state in between.
+/// Attach an [ExecutionObserver] with [Interpreter::with_observer] to be notified of events.
+pub struct Interpreter<'source, Input: Read, Output: Write, Observer: ExecutionObserver = ()> {
     input: Input,
     output: Output,
+    observer: Observer,
     program: Program<'source>,
     config: Config,
-    #[cfg(test)]
-    stack: Rc<RefCell<Vec<StackValue>>>,
+    state: State,
+    breakpoints: Vec<Breakpoint>,
+    builtins: Builtins,
 }
 
 impl<'source, Input: Read, Output: Write> Interpreter<'source, Input, Output> {
     pub fn new(input: Input, output: Output, program: Program<'source>, config: Config) -> Self {
+        let mut state = State {
+            type_safety: config.type_safety,
+            arithmetic: config.arithmetic,
+            print_backtrace: config.print_backtrace,
+            limits: config.limits,
+            instructions: 0,
+            output_bytes: 0,
+            started: None,
+            cancellation: CancellationToken::default(),
+            call_stack: Vec::new(),
+            frame: StackFrame {
+                lambda_id: program.main_id,
+                program_counter: 0,
+                loop_state: LoopState::None,
+                pos: Pos::at_start(),
+            },
+            data_stack: Vec::new(),
+            variables: HashMap::new(),
+            lambda_names: HashMap::new(),
+            origins: config.print_backtrace.then(Origins::default),
+            history: None,
+        };
+        // a missing main lambda is reported by the first step.
+        _ = state.unwind(&program, &mut ());
         Self {
             input,
             output,
+            observer: (),
             program,
             config,
-            #[cfg(test)]
-            stack: Default::default(),
+            state,
+            breakpoints: Vec::new(),
+            builtins: Builtins::default(),
         }
     }
 }
 
+/// The state of a program that ran to completion, see [Interpreter::run].
+#[derive(Clone, Eq, PartialEq, Debug, Default)]
+pub struct RunOutcome {
+    /// The data stack, bottom first.
+    pub data_stack: Vec<StackValue>,
+    pub variables: HashMap<char, StackValue>,
+    /// Number of executed commands.
+    pub instructions: u64,
+    /// Number of bytes written to the output.
+    pub output_bytes: u64,
+}
+
+/// Progress of the `#` command that is executing in a stack frame.
 #[derive(Copy, Clone, Eq, PartialEq, Debug, Default)]
-enum LoopState {
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum LoopState {
     #[default]
     None,
+    /// (condition, body)
     ExecutingCondition(u64, u64),
+    /// (condition, body)
     ExecutingBody(u64, u64),
 }
 
-struct StackFrame {
-    lambda_id: u64,
-    program_counter: usize,
-    loop_state: LoopState,
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct StackFrame {
+    pub lambda_id: u64,
+    /// Index of the next command to execute in this lambda.
+    /// For callers, this is where execution continues after the call returns.
+    pub program_counter: usize,
+    pub loop_state: LoopState,
+    /// Position of the next command to execute. For callers, this is the position of the
+    /// command that made the call.
+    pub pos: Pos,
 }
 
 #[derive(Copy, Clone, Eq, PartialEq, Debug)]
-enum StackValue {
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum StackValue {
     Integer(i64),
     Var(char),
     Lambda(u64),
 }
 
-impl<Input: Read, Output: Write> Interpreter<'_, Input, Output> {
-    pub fn run(mut self) -> Result<(), InterpreterError> {
-        struct State<'i, 'source> {
-            program: &'source Program<'source>,
-            config: &'i Config,
-            call_stack: Vec<StackFrame>,
-            data_stack: Vec<StackValue>,
-            variables: HashMap<char, StackValue>,
-            current_lambda_id: u64,
-            program_counter: usize,
-            current_pos: Pos,
-            current_lambda: &'source Lambda<'source>,
-            loop_state: LoopState,
-        }
-
-        fn get_lambda<'source>(
-            program: &'source Program<'source>,
-            id: u64,
-            backtrace: impl Fn() -> Vec<ProgramPos>,
-        ) -> Result<&'source Lambda<'source>, InterpreterError> {
-            program
-                .lambdas
-                .get(&id)
-                .ok_or_else(|| InterpreterError::invalid_lambda_reference(backtrace(), id))
+impl StackValue {
+    /// The value as an arithmetic command sees it, or `None` if `type_safety` forbids that.
+    pub fn as_integer(self, type_safety: TypeSafety) -> Option<i64> {
+        cast_integer(self, type_safety).ok()
+    }
+
+    /// The name of the type, as in [error::InterpreterErrorKind::TypeCastError].
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            StackValue::Integer(_) => "Integer",
+            StackValue::Var(_) => "Var",
+            StackValue::Lambda(_) => "Lambda",
+        }
+    }
+}
+
+impl fmt::Display for StackValue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StackValue::Integer(i) => write!(f, "{}", i),
+            StackValue::Var(c) => write!(f, "<var {}>", c),
+            StackValue::Lambda(id) => write!(f, "<lambda {}>", id),
         }
+    }
+}
 
-        impl<'source> State<'_, 'source> {
-            fn backtrace(&self) -> Vec<ProgramPos> {
-                let pos = ProgramPos {
-                    pos: self.current_pos,
-                    lambda_id: self.current_lambda_id,
-                    program_counter: self.program_counter,
-                };
-                if self.config.print_backtrace {
-                    once(pos)
-                        .chain(self.call_stack.iter().rev().map(|sf| {
-                            let lambda = get_lambda(self.program, sf.lambda_id, Vec::new).unwrap();
-                            ProgramPos {
-                                pos: lambda[sf.program_counter - 1].1.start,
-                                program_counter: sf.program_counter - 1,
-                                lambda_id: sf.lambda_id,
-                            }
-                        }))
-                        .collect()
-                } else {
-                    vec![pos]
-                }
+/// A condition for pausing execution in [Interpreter::run_until] and [Interpreter::resume].
+/// Positions are matched by line and column, the offset is ignored.
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub enum Breakpoint {
+    /// Stop before a command that starts at this position.
+    Pos(Pos),
+    /// Stop before a command that starts within `start..end`.
+    Span(Pos, Pos),
+    /// Stop before the first command of a lambda, every time it is called.
+    Lambda(u64),
+}
+
+impl Breakpoint {
+    fn matches(&self, frame: &StackFrame) -> bool {
+        let line_column = |p: Pos| (p.line, p.column);
+        match *self {
+            Breakpoint::Pos(pos) => line_column(frame.pos) == line_column(pos),
+            Breakpoint::Span(start, end) => {
+                (line_column(start)..line_column(end)).contains(&line_column(frame.pos))
             }
+            Breakpoint::Lambda(id) => frame.lambda_id == id && frame.program_counter == 0,
+        }
+    }
+}
+
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub enum Status {
+    /// Execution can be resumed.
+    Paused,
+    /// Execution stopped before a command that matches this breakpoint.
+    Breakpoint(Breakpoint),
+    /// The program ran to completion.
+    Finished,
+    /// Execution was undone up to the oldest step in the history, see
+    /// [Interpreter::run_back_until].
+    StartOfHistory,
+}
+
+/// Stops a running interpreter from another thread or a signal handler.
+///
+/// The interpreter checks the token when it is resumed and every 1024 commands, and fails with
+/// [error::InterpreterErrorKind::Interrupted] once it is cancelled. A cancelled token stays
+/// cancelled, so resuming the interpreter fails again.
+#[derive(Clone, Debug, Default)]
+pub struct CancellationToken(Arc<AtomicBool>);
+
+impl CancellationToken {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+/// How many commands are executed between checks of the [CancellationToken].
+const POLL_INTERVAL: u64 = 1024;
+
+/// Where [Interpreter::run_steps] stops, besides the end of the program.
+#[derive(Copy, Clone, Eq, PartialEq)]
+enum Until {
+    NextCommand,
+    Breakpoint,
+    /// Like [Until::Breakpoint], or until this many commands were executed.
+    Instructions(u64),
+    /// Breakpoints are ignored.
+    Finished,
+}
+
+/// Everything that changes while the program executes.
+struct State {
+    type_safety: TypeSafety,
+    arithmetic: Arithmetic,
+    print_backtrace: bool,
+    limits: Limits,
+    /// Number of executed commands.
+    instructions: u64,
+    output_bytes: u64,
+    /// Time of the first step, for [Limits::timeout_ms].
+    started: Option<Instant>,
+    cancellation: CancellationToken,
+    /// Callers of the current frame, outermost first.
+    call_stack: Vec<StackFrame>,
+    frame: StackFrame,
+    data_stack: Vec<StackValue>,
+    variables: HashMap<char, StackValue>,
+    /// The variable each lambda was most recently stored in.
+    lambda_names: HashMap<u64, char>,
+    /// Only tracked if errors capture an [ErrorContext].
+    origins: Option<Origins>,
+    /// Only kept if [Interpreter::set_history_size] turned it on.
+    history: Option<History>,
+}
+
+/// Where the values of the data stack and the variables were pushed, for
+/// [OffendingValue::origin]. `None` for values that were not pushed by a command, e.g. the
+/// ones passed to [Interpreter::with_data_stack].
+#[derive(Default)]
+pub(crate) struct Origins {
+    /// Parallel to the data stack.
+    stack: Vec<Option<ProgramPos>>,
+    variables: HashMap<char, Option<ProgramPos>>,
+    /// The values popped by the current command, to show the stack from before it failed.
+    popped: Vec<(StackValue, Option<ProgramPos>)>,
+}
+
+impl Origins {
+    pub(crate) fn push(&mut self, origin: Option<ProgramPos>) {
+        self.stack.push(origin);
+    }
+
+    pub(crate) fn pop(&mut self, value: StackValue) -> Option<ProgramPos> {
+        let origin = self.stack.pop().flatten();
+        self.popped.push((value, origin));
+        origin
+    }
+
+    fn top(&self, index: usize) -> Option<ProgramPos> {
+        self.stack.iter().rev().nth(index).copied().flatten()
+    }
+}
+
+fn get_lambda<'p, 'source>(
+    program: &'p Program<'source>,
+    id: u64,
+    backtrace: impl FnOnce() -> Vec<ProgramPos>,
+) -> Result<&'p Lambda<'source>, InterpreterError> {
+    program
+        .lambdas
+        .get(&id)
+        .ok_or_else(|| InterpreterError::invalid_lambda_reference(backtrace(), id))
+}
+
+impl State {
+    fn backtrace(&self, full: bool) -> Vec<ProgramPos> {
+        let pos = ProgramPos {
+            pos: self.frame.pos,
+            program_counter: self.frame.program_counter,
+            lambda_id: self.frame.lambda_id,
+        };
+        if full {
+            once(pos)
+                .chain(self.call_stack.iter().rev().map(|sf| ProgramPos {
+                    pos: sf.pos,
+                    // `#` continues at itself, all other calls continue after themselves.
+                    program_counter: match sf.loop_state {
+                        LoopState::None => sf.program_counter - 1,
+                        _ => sf.program_counter,
+                    },
+                    lambda_id: sf.lambda_id,
+                }))
+                .collect()
+        } else {
+            vec![pos]
+        }
+    }
+
+    fn error_backtrace(&self) -> Vec<ProgramPos> {
+        self.backtrace(self.print_backtrace)
+    }
 
-            fn get_current_instruction(
-                &self,
-            ) -> Result<&'source (Command<'source>, Span<'source>), InterpreterError> {
-                self.current_lambda
-                    .get(self.program_counter)
-                    .ok_or_else(|| {
-                        InterpreterError::invalid_program_counter(
-                            self.backtrace(),
-                            self.program_counter,
-                        )
-                    })
+    /// Enter the lambda `id`, and return it.
+    fn call_lambda<'p, 'source>(
+        &mut self,
+        program: &'p Program<'source>,
+        id: u64,
+        return_to: usize,
+        observer: &mut impl ExecutionObserver,
+    ) -> Result<&'p Lambda<'source>, InterpreterError> {
+        let lambda = get_lambda(program, id, || self.error_backtrace())?;
+        if let Some(limit) = self.limits.max_call_depth
+            && self.call_stack.len() >= limit
+        {
+            return Err(InterpreterError::call_depth_limit_exceeded(
+                self.error_backtrace(),
+                limit,
+            ));
+        }
+        let caller = std::mem::replace(
+            &mut self.frame,
+            StackFrame {
+                lambda_id: id,
+                program_counter: 0,
+                loop_state: LoopState::None,
+                pos: Pos::at_start(),
+            },
+        );
+        self.call_stack.push(StackFrame {
+            program_counter: return_to,
+            ..caller
+        });
+        self.record(Change::Call);
+        observer.lambda_call(id);
+        Ok(lambda)
+    }
+
+    /// Return from all lambdas that have no commands left, so that the current frame points
+    /// at the next command to execute.
+    fn unwind(
+        &mut self,
+        program: &Program,
+        observer: &mut impl ExecutionObserver,
+    ) -> Result<(), InterpreterError> {
+        let lambda = get_lambda(program, self.frame.lambda_id, || self.error_backtrace())?;
+        self.unwind_from(program, lambda, observer).map(|_| ())
+    }
+
+    /// Like [State::unwind], with the lambda of the current frame. Returns the lambda of the
+    /// frame it stopped in.
+    #[inline(always)]
+    fn unwind_from<'p, 'source>(
+        &mut self,
+        program: &'p Program<'source>,
+        mut lambda: &'p Lambda<'source>,
+        observer: &mut impl ExecutionObserver,
+    ) -> Result<&'p Lambda<'source>, InterpreterError> {
+        loop {
+            if let Some((_, span)) = lambda.get(self.frame.program_counter) {
+                self.frame.pos = span.start;
+                return Ok(lambda);
             }
+            match self.call_stack.pop() {
+                Some(caller) => {
+                    observer.lambda_return(self.frame.lambda_id);
+                    let call = caller.pos;
+                    let callee = std::mem::replace(&mut self.frame, caller);
+                    self.record(Change::Return(callee, call));
+                }
+                None => return Ok(lambda),
+            }
+            lambda = get_lambda(program, self.frame.lambda_id, || self.error_backtrace())?;
+        }
+    }
+
+    fn is_finished(&self, program: &Program) -> bool {
+        self.call_stack.is_empty()
+            && program
+                .lambdas
+                .get(&self.frame.lambda_id)
+                .is_some_and(|lambda| self.frame.program_counter >= lambda.len())
+    }
+
+    /// The current command, as the origin of the values it pushes.
+    fn origin(&self) -> Option<ProgramPos> {
+        Some(ProgramPos {
+            pos: self.frame.pos,
+            program_counter: self.frame.program_counter,
+            lambda_id: self.frame.lambda_id,
+        })
+    }
+
+    fn push(&mut self, value: StackValue) -> Result<(), InterpreterError> {
+        self.push_from(value, self.origin())
+    }
 
-            fn call_lambda(&mut self, id: u64) -> Result<(), InterpreterError> {
+    /// Push a value that keeps its origin, e.g. when it is only moved.
+    #[inline(always)]
+    fn push_from(
+        &mut self,
+        value: StackValue,
+        origin: Option<ProgramPos>,
+    ) -> Result<(), InterpreterError> {
+        if let Some(limit) = self.limits.max_stack_depth
+            && self.data_stack.len() >= limit
+        {
+            return Err(InterpreterError::stack_limit_exceeded(
+                self.error_backtrace(),
+                limit,
+            ));
+        }
+        self.data_stack.push(value);
+        if let Some(origins) = &mut self.origins {
+            origins.push(origin);
+        }
+        self.record(Change::Push);
+        Ok(())
+    }
+
+    /// The value `index` values below the top of the stack and its origin.
+    fn peek_from(&self, index: usize) -> Option<(StackValue, Option<ProgramPos>)> {
+        let value = *self.data_stack.iter().rev().nth(index)?;
+        Some((value, self.origins.as_ref().and_then(|o| o.top(index))))
+    }
+
+    fn peek(&self) -> Result<(StackValue, Option<ProgramPos>), InterpreterError> {
+        self.peek_from(0).ok_or_else(|| {
+            InterpreterError::tried_to_pop_from_empty_data_stack(self.error_backtrace())
+        })
+    }
+
+    fn pop(&mut self) -> Result<StackValue, InterpreterError> {
+        self.pop_from().map(|(value, _)| value)
+    }
+
+    #[inline(always)]
+    fn pop_from(&mut self) -> Result<(StackValue, Option<ProgramPos>), InterpreterError> {
+        let value = self.data_stack.pop().ok_or_else(|| {
+            InterpreterError::tried_to_pop_from_empty_data_stack(self.error_backtrace())
+        })?;
+        let origin = self.origins.as_mut().and_then(|o| o.pop(value));
+        self.record(Change::Pop(value, origin));
+        Ok((value, origin))
+    }
+
+    // inlined like the other helpers of every command, so the change is only built when there
+    // is a history.
+    #[inline(always)]
+    fn record(&mut self, change: Change) {
+        if let Some(history) = &mut self.history {
+            history.record(change);
+        }
+    }
+
+    /// Revert a change of an undone step.
+    fn undo(&mut self, change: Change) {
+        match change {
+            Change::Push => {
+                self.data_stack.pop();
+                if let Some(origins) = &mut self.origins {
+                    origins.stack.pop();
+                }
+            }
+            Change::Pop(value, origin) => {
+                self.data_stack.push(value);
+                if let Some(origins) = &mut self.origins {
+                    origins.stack.push(origin);
+                }
+            }
+            Change::Call => self.frame = self.call_stack.pop().unwrap(),
+            Change::Return(callee, call) => {
+                let caller = std::mem::replace(&mut self.frame, callee);
                 self.call_stack.push(StackFrame {
-                    lambda_id: self.current_lambda_id,
-                    program_counter: self.program_counter,
-                    loop_state: self.loop_state,
+                    pos: call,
+                    ..caller
                 });
-                self.current_lambda_id = id;
-                self.program_counter = 0;
-                self.current_pos = Pos::at_start();
-                self.current_lambda = get_lambda(self.program, id, || self.backtrace())?;
-                self.loop_state = LoopState::None;
-                Ok(())
-            }
-
-            fn ret_lambda(&mut self) -> Result<(), InterpreterError> {
-                let frame = self.call_stack.pop().ok_or_else(|| {
-                    InterpreterError::tried_to_pop_from_empty_call_stack(self.backtrace())
-                })?;
-                self.current_lambda_id = frame.lambda_id;
-                self.program_counter = frame.program_counter;
-                self.loop_state = frame.loop_state;
-                self.current_pos = Pos::at_start();
-                self.current_lambda =
-                    get_lambda(self.program, frame.lambda_id, || self.backtrace())?;
-                Ok(())
-            }
-
-            fn push(&mut self, value: StackValue) {
-                self.data_stack.push(value);
             }
-
-            fn peek(&self) -> Result<StackValue, InterpreterError> {
-                self.data_stack.last().copied().ok_or_else(|| {
-                    InterpreterError::tried_to_pop_from_empty_data_stack(self.backtrace())
-                })
+            Change::Store(var, value, origin) => {
+                match value {
+                    Some(value) => self.variables.insert(var, value),
+                    None => self.variables.remove(&var),
+                };
+                if let Some(origins) = &mut self.origins {
+                    match origin {
+                        Some(origin) => origins.variables.insert(var, origin),
+                        None => origins.variables.remove(&var),
+                    };
+                }
+            }
+            Change::Name(id, name) => {
+                match name {
+                    Some(name) => self.lambda_names.insert(id, name),
+                    None => self.lambda_names.remove(&id),
+                };
             }
+            Change::Read(byte) => self.history.as_mut().unwrap().unread(byte),
+        }
+    }
 
-            fn pop(&mut self) -> Result<StackValue, InterpreterError> {
-                self.data_stack.pop().ok_or_else(|| {
-                    InterpreterError::tried_to_pop_from_empty_data_stack(self.backtrace())
-                })
+    /// The state of the program for an error of `kind` in the current command.
+    fn error_context(&self, program: &Program, kind: &InterpreterErrorKind) -> ErrorContext {
+        let origins = self.origins.as_ref().unwrap();
+        let mut data_stack = self.data_stack.clone();
+        data_stack.extend(origins.popped.iter().rev().map(|(value, _)| *value));
+        let hidden_values = data_stack.len().saturating_sub(ErrorContext::STACK_TOP);
+        data_stack.drain(..hidden_values);
+        let mut variables: Vec<_> = self.variables.iter().map(|(k, v)| (*k, *v)).collect();
+        variables.sort_by_key(|(name, _)| *name);
+        let value = match kind {
+            InterpreterErrorKind::TypeCastError { .. } => origins.popped.last(),
+            // the divisor and the index are popped first.
+            InterpreterErrorKind::DivisionByZero | InterpreterErrorKind::IndexOutOfBounds(..) => {
+                origins.popped.first()
             }
+            _ => None,
+        };
+        ErrorContext {
+            data_stack,
+            hidden_values,
+            variables,
+            value: value.map(|(value, origin)| OffendingValue {
+                value: *value,
+                origin: origin.and_then(|origin| {
+                    let lambda = program.lambdas.get(&origin.lambda_id)?;
+                    let (_, span) = lambda.get(origin.program_counter)?;
+                    Some(span.into_owned())
+                }),
+            }),
+        }
+    }
+
+    fn pushi(&mut self, i: impl Into<i64>) -> Result<(), InterpreterError> {
+        self.push(StackValue::Integer(i.into()))
+    }
 
-            fn pushi(&mut self, i: impl Into<i64>) {
-                self.data_stack.push(StackValue::Integer(i.into()));
+    /// Count executed commands and check the instruction and time limits.
+    #[inline(always)]
+    fn count_instruction(&mut self) -> Result<(), InterpreterError> {
+        self.instructions += 1;
+        if let Some(limit) = self.limits.max_instructions
+            && self.instructions > limit
+        {
+            return Err(InterpreterError::instruction_limit_exceeded(
+                self.error_backtrace(),
+                limit,
+            ));
+        }
+        if let Some(ms) = self.limits.timeout_ms {
+            let started = *self.started.get_or_insert_with(Instant::now);
+            // reading the clock is relatively slow, so only do it every few commands.
+            if self.instructions.is_multiple_of(1024) && started.elapsed().as_millis() >= ms as u128
+            {
+                return Err(InterpreterError::timeout(self.error_backtrace(), ms));
             }
         }
+        Ok(())
+    }
 
-        trait TypeValidation {
-            fn into_integer(self, config: &Config, state: &State) -> Result<i64, InterpreterError>;
-            fn into_var(self, config: &Config, state: &State) -> Result<char, InterpreterError>;
-            fn into_lambda(self, config: &Config, state: &State) -> Result<u64, InterpreterError>;
+    fn write(
+        &mut self,
+        output: &mut impl Write,
+        observer: &mut impl ExecutionObserver,
+        bytes: &[u8],
+    ) -> Result<(), InterpreterError> {
+        if let Some(limit) = self.limits.max_output_bytes
+            && self.output_bytes + bytes.len() as u64 > limit
+        {
+            return Err(InterpreterError::output_limit_exceeded(
+                self.error_backtrace(),
+                limit,
+            ));
         }
+        self.output_bytes += bytes.len() as u64;
+        output
+            .write_all(bytes)
+            .map_err(|e| InterpreterError::io_error(self.error_backtrace(), e))?;
+        observer.write(bytes);
+        Ok(())
+    }
 
-        impl TypeValidation for StackValue {
-            fn into_integer(self, config: &Config, state: &State) -> Result<i64, InterpreterError> {
-                use StackValue::*;
-                match (self, config.type_safety) {
-                    (Integer(i), _) => Ok(i),
-                    (Var(_), TypeSafety::Full) => Err(InterpreterError::type_cast_error(
-                        state.backtrace(),
-                        "Integer",
-                        "Var",
-                    )),
-                    (Var(c), _) => Ok((c as u8 - b'a') as i64),
-                    (Lambda(_), TypeSafety::Full) => Err(InterpreterError::type_cast_error(
-                        state.backtrace(),
-                        "Integer",
-                        "Lambda",
-                    )),
-                    (Lambda(id), _) => Ok(id as i64),
-                }
+    fn pop_integer(&mut self) -> Result<i64, InterpreterError> {
+        let value = self.pop()?;
+        self.cast_integer(value)
+    }
+
+    fn pop_var(&mut self) -> Result<char, InterpreterError> {
+        let value = self.pop()?;
+        self.cast_var(value)
+    }
+
+    /// Pop a lambda or a builtin for `!`, `?` or `#`.
+    fn pop_callee(&mut self, builtins: &Builtins) -> Result<u64, InterpreterError> {
+        let value = self.pop()?;
+        builtins
+            .callee(value, self.type_safety)
+            .map_err(|(from, to)| {
+                InterpreterError::type_cast_error(self.error_backtrace(), from, to)
+            })
+    }
+
+    fn call_builtin(
+        &mut self,
+        builtins: &mut Builtins,
+        id: u64,
+        input: &mut impl Read,
+        output: &mut impl Write,
+        observer: &mut impl ExecutionObserver,
+    ) -> Result<(), InterpreterError> {
+        let backtrace = self.error_backtrace();
+        let origin = self.origin();
+        let builtin = builtins.get_mut(id).unwrap();
+        builtin(&mut BuiltinContext {
+            data_stack: &mut self.data_stack,
+            origins: self.origins.as_mut(),
+            history: self.history.as_mut(),
+            origin,
+            input,
+            output,
+            observer,
+            type_safety: self.type_safety,
+            limits: self.limits,
+            output_bytes: &mut self.output_bytes,
+            backtrace,
+        })
+    }
+
+    /// Pick the result of an integer operation for the configured [Arithmetic].
+    fn arithmetic(
+        &self,
+        op: &'static str,
+        checked: impl FnOnce() -> Option<i64>,
+        wrapping: impl FnOnce() -> i64,
+        saturating: impl FnOnce() -> i64,
+    ) -> Result<i64, InterpreterError> {
+        arithmetic(self.arithmetic, checked, wrapping, saturating)
+            .ok_or_else(|| InterpreterError::integer_overflow(self.error_backtrace(), op))
+    }
+
+    fn cast_integer(&self, value: StackValue) -> Result<i64, InterpreterError> {
+        cast_integer(value, self.type_safety).map_err(|(from, to)| {
+            InterpreterError::type_cast_error(self.error_backtrace(), from, to)
+        })
+    }
+
+    fn cast_var(&self, value: StackValue) -> Result<char, InterpreterError> {
+        cast_var(value, self.type_safety).map_err(|(from, to)| {
+            InterpreterError::type_cast_error(self.error_backtrace(), from, to)
+        })
+    }
+}
+
+/// Read a byte for `^` or a builtin, or `None` at the end of the input. Steps that are
+/// executed again after they were undone read the same bytes as before.
+fn read_byte(
+    input: &mut (impl Read + ?Sized),
+    mut history: Option<&mut History>,
+) -> std::io::Result<Option<u8>> {
+    let reread = history.as_deref_mut().and_then(History::reread);
+    let byte = match reread {
+        Some(byte) => byte,
+        None => {
+            let mut buf = [0];
+            match input.read_exact(&mut buf) {
+                Ok(()) => Some(buf[0]),
+                Err(e) if e.kind() == ErrorKind::UnexpectedEof => None,
+                Err(e) => return Err(e),
             }
+        }
+    };
+    if let Some(history) = history {
+        history.record(Change::Read(byte));
+    }
+    Ok(byte)
+}
 
-            fn into_var(self, config: &Config, state: &State) -> Result<char, InterpreterError> {
-                use StackValue::*;
-                match (self, config.type_safety) {
-                    (Integer(_), TypeSafety::Full | TypeSafety::LambdaAndVar) => Err(
-                        InterpreterError::type_cast_error(state.backtrace(), "Integer", "Var"),
-                    ),
-                    (Integer(i), _) => Ok(((i as u8 & 31) + b'a') as char),
-                    (Var(c), _) => Ok(c),
-                    (Lambda(_), TypeSafety::Full | TypeSafety::LambdaAndVar) => Err(
-                        InterpreterError::type_cast_error(state.backtrace(), "Lambda", "Var"),
-                    ),
-                    (Lambda(id), _) => Ok(((id as u8 & 31) + b'a') as char),
-                }
+/// The result of an integer operation for the given [Arithmetic], or `None` if it overflowed
+/// in checked mode.
+fn arithmetic(
+    mode: Arithmetic,
+    checked: impl FnOnce() -> Option<i64>,
+    wrapping: impl FnOnce() -> i64,
+    saturating: impl FnOnce() -> i64,
+) -> Option<i64> {
+    match mode {
+        Arithmetic::Wrapping => Some(wrapping()),
+        Arithmetic::Checked => checked(),
+        Arithmetic::Saturating => Some(saturating()),
+    }
+}
+
+// The casts return the type names for InterpreterError::type_cast_error on failure.
+
+fn cast_integer(
+    value: StackValue,
+    type_safety: TypeSafety,
+) -> Result<i64, (&'static str, &'static str)> {
+    use StackValue::*;
+    match (value, type_safety) {
+        (Integer(i), _) => Ok(i),
+        (Var(_), TypeSafety::Full) => Err(("Integer", "Var")),
+        (Var(c), _) => Ok((c as u8 - b'a') as i64),
+        (Lambda(_), TypeSafety::Full) => Err(("Integer", "Lambda")),
+        (Lambda(id), _) => Ok(id as i64),
+    }
+}
+
+fn cast_var(
+    value: StackValue,
+    type_safety: TypeSafety,
+) -> Result<char, (&'static str, &'static str)> {
+    use StackValue::*;
+    match (value, type_safety) {
+        (Integer(_), TypeSafety::Full | TypeSafety::LambdaAndVar) => Err(("Integer", "Var")),
+        (Integer(i), _) => Ok(((i as u8 & 31) + b'a') as char),
+        (Var(c), _) => Ok(c),
+        (Lambda(_), TypeSafety::Full | TypeSafety::LambdaAndVar) => Err(("Lambda", "Var")),
+        (Lambda(id), _) => Ok(((id as u8 & 31) + b'a') as char),
+    }
+}
+
+fn cast_lambda(
+    value: StackValue,
+    type_safety: TypeSafety,
+) -> Result<u64, (&'static str, &'static str)> {
+    use StackValue::*;
+    match (value, type_safety) {
+        (Integer(_), TypeSafety::Full | TypeSafety::LambdaAndVar | TypeSafety::Lambda) => {
+            Err(("Integer", "Lambda"))
+        }
+        (Integer(i), _) => Ok(i as u64),
+        (Var(_), TypeSafety::Full | TypeSafety::LambdaAndVar | TypeSafety::Lambda) => {
+            Err(("Var", "Lambda"))
+        }
+        (Var(c), _) => Ok(c as u64),
+        (Lambda(id), _) => Ok(id),
+    }
+}
+
+impl<'source, Input: Read, Output: Write, Observer: ExecutionObserver>
+    Interpreter<'source, Input, Output, Observer>
+{
+    pub fn program(&self) -> &Program<'source> {
+        &self.program
+    }
+
+    pub fn config(&self) -> &Config {
+        &self.config
+    }
+
+    /// The data stack, bottom first.
+    pub fn data_stack(&self) -> &[StackValue] {
+        &self.state.data_stack
+    }
+
+    /// The frame of the lambda that is currently executing.
+    pub fn current_frame(&self) -> &StackFrame {
+        &self.state.frame
+    }
+
+    /// The callers of the current frame, outermost first.
+    pub fn call_stack(&self) -> &[StackFrame] {
+        &self.state.call_stack
+    }
+
+    pub fn variables(&self) -> &HashMap<char, StackValue> {
+        &self.state.variables
+    }
+
+    /// The variable each lambda was most recently stored in, to name it in backtraces.
+    pub fn lambda_names(&self) -> &HashMap<u64, char> {
+        &self.state.lambda_names
+    }
+
+    /// Start with `data_stack` instead of an empty stack, bottom first.
+    pub fn with_data_stack(mut self, data_stack: Vec<StackValue>) -> Self {
+        if let Some(origins) = &mut self.state.origins {
+            origins.stack = vec![None; data_stack.len()];
+        }
+        self.state.data_stack = data_stack;
+        self
+    }
+
+    /// Start with `variables` already assigned.
+    pub fn with_variables(mut self, variables: HashMap<char, StackValue>) -> Self {
+        if let Some(origins) = &mut self.state.origins {
+            origins.variables = variables.keys().map(|name| (*name, None)).collect();
+        }
+        for (name, value) in &variables {
+            if let StackValue::Lambda(id) = value {
+                self.state.lambda_names.insert(*id, *name);
             }
+        }
+        self.state.variables = variables;
+        self
+    }
 
-            fn into_lambda(self, config: &Config, state: &State) -> Result<u64, InterpreterError> {
-                use StackValue::*;
-                match (self, config.type_safety) {
-                    (
-                        Integer(_),
-                        TypeSafety::Full | TypeSafety::LambdaAndVar | TypeSafety::Lambda,
-                    ) => Err(InterpreterError::type_cast_error(
-                        state.backtrace(),
-                        "Integer",
-                        "Lambda",
-                    )),
-                    (Integer(i), _) => Ok(i as u64),
-                    (Var(_), TypeSafety::Full | TypeSafety::LambdaAndVar | TypeSafety::Lambda) => {
-                        Err(InterpreterError::type_cast_error(
-                            state.backtrace(),
-                            "Var",
-                            "Lambda",
-                        ))
-                    }
-                    (Var(c), _) => Ok(c as u64),
-                    (Lambda(id), _) => Ok(id),
+    /// Capture the state in between two commands, to continue later with
+    /// [Interpreter::restore].
+    pub fn snapshot(&self) -> Snapshot {
+        Snapshot {
+            program_hash: program_hash(&self.program),
+            data_stack: self.state.data_stack.clone(),
+            call_stack: self.state.call_stack.clone(),
+            frame: self.state.frame,
+            variables: self.state.variables.clone(),
+            lambda_names: self.state.lambda_names.clone(),
+            instructions: self.state.instructions,
+            output_bytes: self.state.output_bytes,
+        }
+    }
+
+    /// Continue from a [Snapshot] of the same program, replacing the current state.
+    /// The restored values have no origins for error contexts, like the ones passed to
+    /// [Interpreter::with_data_stack].
+    pub fn restore(&mut self, snapshot: Snapshot) -> Result<(), SnapshotError> {
+        let expected = program_hash(&self.program);
+        if snapshot.program_hash != expected {
+            return Err(SnapshotError::ProgramMismatch {
+                expected,
+                found: snapshot.program_hash,
+            });
+        }
+        // callers continue after the call, the current frame may be at the end of main.
+        if let Some(frame) = snapshot
+            .call_stack
+            .iter()
+            .chain(once(&snapshot.frame))
+            .find(|frame| {
+                self.program
+                    .lambdas
+                    .get(&frame.lambda_id)
+                    .is_none_or(|lambda| frame.program_counter > lambda.len())
+            })
+        {
+            return Err(SnapshotError::InvalidFrame(*frame));
+        }
+        if let Some(origins) = &mut self.state.origins {
+            *origins = Origins {
+                stack: vec![None; snapshot.data_stack.len()],
+                variables: snapshot
+                    .variables
+                    .keys()
+                    .map(|name| (*name, None))
+                    .collect(),
+                popped: Vec::new(),
+            };
+        }
+        self.state.data_stack = snapshot.data_stack;
+        self.state.call_stack = snapshot.call_stack;
+        self.state.frame = snapshot.frame;
+        self.state.variables = snapshot.variables;
+        self.state.lambda_names = snapshot.lambda_names;
+        self.state.instructions = snapshot.instructions;
+        self.state.output_bytes = snapshot.output_bytes;
+        self.state.started = None;
+        if let Some(history) = &mut self.state.history {
+            history.clear();
+        }
+        Ok(())
+    }
+
+    /// Number of commands executed so far.
+    pub fn instructions(&self) -> u64 {
+        self.state.instructions
+    }
+
+    /// Number of bytes written to the output so far.
+    pub fn output_bytes(&self) -> u64 {
+        self.state.output_bytes
+    }
+
+    /// The command that the next [Interpreter::step] executes.
+    pub fn current_command(&self) -> Option<&(Command<'source>, Span<'source>)> {
+        self.program
+            .lambdas
+            .get(&self.state.frame.lambda_id)?
+            .get(self.state.frame.program_counter)
+    }
+
+    /// The position of the next command, followed by the positions of all calls leading to it.
+    pub fn backtrace(&self) -> Vec<ProgramPos> {
+        self.state.backtrace(true)
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.state.is_finished(&self.program)
+    }
+
+    pub fn breakpoints(&self) -> &[Breakpoint] {
+        &self.breakpoints
+    }
+
+    pub fn add_breakpoint(&mut self, breakpoint: Breakpoint) {
+        if !self.breakpoints.contains(&breakpoint) {
+            self.breakpoints.push(breakpoint);
+        }
+    }
+
+    /// Returns true if the breakpoint was set.
+    pub fn remove_breakpoint(&mut self, breakpoint: &Breakpoint) -> bool {
+        let len = self.breakpoints.len();
+        self.breakpoints.retain(|b| b != breakpoint);
+        self.breakpoints.len() != len
+    }
+
+    pub fn clear_breakpoints(&mut self) {
+        self.breakpoints.clear();
+    }
+
+    pub fn builtins(&self) -> &Builtins {
+        &self.builtins
+    }
+
+    pub fn builtins_mut(&mut self) -> &mut Builtins {
+        &mut self.builtins
+    }
+
+    /// Make `builtin` callable from FALSE code, see [Builtins::register].
+    pub fn register_builtin(
+        &mut self,
+        id: i64,
+        builtin: impl FnMut(&mut BuiltinContext) -> Result<(), InterpreterError> + Send + 'static,
+    ) {
+        self.builtins.register(id, builtin);
+    }
+
+    /// A token that stops this interpreter when cancelled.
+    pub fn cancellation_token(&self) -> CancellationToken {
+        self.state.cancellation.clone()
+    }
+
+    /// Replace the cancellation token, e.g. to stop several interpreters with one token.
+    pub fn set_cancellation_token(&mut self, token: CancellationToken) {
+        self.state.cancellation = token;
+    }
+
+    /// Replace the observer. Events are only reported from the next step on.
+    pub fn with_observer<O: ExecutionObserver>(
+        self,
+        observer: O,
+    ) -> Interpreter<'source, Input, Output, O> {
+        Interpreter {
+            input: self.input,
+            output: self.output,
+            observer,
+            program: self.program,
+            config: self.config,
+            state: self.state,
+            breakpoints: self.breakpoints,
+            builtins: self.builtins,
+        }
+    }
+
+    pub fn observer(&self) -> &Observer {
+        &self.observer
+    }
+
+    pub fn observer_mut(&mut self) -> &mut Observer {
+        &mut self.observer
+    }
+
+    /// Continue the session with another program, keeping the data stack and variables.
+    /// The lambda ids of `program` are shifted past the ones already loaded, so lambdas of
+    /// earlier programs stay valid, e.g. when they are stored in variables.
+    /// If the current program has not finished, its remaining commands are dropped.
+    pub fn load(&mut self, program: Program<'source>) {
+        let offset = self.program.lambdas.keys().max().map_or(0, |id| id + 1);
+        for (id, mut lambda) in program.lambdas {
+            for (command, _) in &mut lambda {
+                if let Command::Lambda(LambdaCommand::LambdaReference(id)) = command {
+                    *id += offset;
                 }
             }
+            self.program.lambdas.insert(id + offset, lambda);
         }
-
-        let mut state = State {
-            program: &self.program,
-            config: &self.config,
-            call_stack: Vec::new(),
-            data_stack: Vec::new(),
-            variables: HashMap::new(),
-            current_lambda_id: self.program.main_id,
+        self.program.strings.extend(program.strings);
+        self.program.main_id = program.main_id + offset;
+        self.state.call_stack.clear();
+        if let Some(history) = &mut self.state.history {
+            history.clear();
+        }
+        self.state.frame = StackFrame {
+            lambda_id: self.program.main_id,
             program_counter: 0,
-            current_pos: Pos::at_start(),
-            current_lambda: get_lambda(&self.program, self.program.main_id, Vec::new)?,
             loop_state: LoopState::None,
+            pos: Pos::at_start(),
         };
+        _ = self.state.unwind(&self.program, &mut self.observer);
+    }
 
-        #[cfg(test)]
-        {
-            let stack = self.stack.borrow();
-            for i in stack.iter() {
-                state.push(*i);
+    /// Execute commands until the next one matches a breakpoint, `stop` returns true, or the
+    /// program finishes. At least one command is executed, so that execution can be resumed
+    /// after stopping at a breakpoint.
+    pub fn run_until(
+        &mut self,
+        mut stop: impl FnMut(&Self) -> bool,
+    ) -> Result<Status, InterpreterError> {
+        loop {
+            if self.step()? == Status::Finished {
+                return Ok(Status::Finished);
             }
+            if let Some(breakpoint) = self
+                .breakpoints
+                .iter()
+                .find(|b| b.matches(&self.state.frame))
+            {
+                return Ok(Status::Breakpoint(*breakpoint));
+            }
+            if stop(self) {
+                return Ok(Status::Paused);
+            }
+        }
+    }
+
+    /// Execute commands until a breakpoint is hit or the program finishes.
+    pub fn resume(&mut self) -> Result<Status, InterpreterError> {
+        self.run_steps(Until::Breakpoint)
+    }
+
+    /// Like [Interpreter::resume], but pause after `commands` commands, e.g. to take a
+    /// [Interpreter::snapshot] every now and then.
+    pub fn run_for(&mut self, commands: u64) -> Result<Status, InterpreterError> {
+        let end = self.state.instructions.saturating_add(commands);
+        self.run_steps(Until::Instructions(end))
+    }
+
+    /// Keep the last `steps` executed commands, so that they can be undone with
+    /// [Interpreter::step_back]. 0 turns the history off.
+    ///
+    /// Undone commands that read input read the same bytes again when they are executed again.
+    /// The output and files of the standard builtins cannot be undone.
+    pub fn set_history_size(&mut self, steps: usize) {
+        match &mut self.state.history {
+            _ if steps == 0 => self.state.history = None,
+            Some(history) => history.resize(steps),
+            None => self.state.history = Some(History::new(steps)),
+        }
+    }
+
+    /// Number of commands that can be undone.
+    pub fn history_len(&self) -> usize {
+        self.state.history.as_ref().map_or(0, History::len)
+    }
+
+    /// Undo the last executed command, so that it is the next one again. Returns false if the
+    /// history is empty or turned off.
+    pub fn step_back(&mut self) -> bool {
+        let Some(step) = self.state.history.as_mut().and_then(History::pop) else {
+            return false;
+        };
+        for change in step.changes.into_iter().rev() {
+            self.state.undo(change);
+        }
+        self.state.frame = step.frame;
+        self.state.instructions = step.instructions;
+        self.state.output_bytes = step.output_bytes;
+        if let Some(origins) = &mut self.state.origins {
+            origins.popped.clear();
         }
+        true
+    }
 
+    /// Undo commands until the next one matches a breakpoint, `stop` returns true, or the
+    /// history is empty. Like [Interpreter::run_until], at least one command is undone.
+    pub fn run_back_until(&mut self, mut stop: impl FnMut(&Self) -> bool) -> Status {
         loop {
-            if state.program_counter >= state.current_lambda.len() {
-                if state.call_stack.is_empty() {
-                    break;
-                }
-                state.ret_lambda()?;
-                continue;
-            }
-            let (instruction, pos) = state.get_current_instruction()?;
-            state.current_pos = pos.start;
-            state.program_counter += 1;
-            match instruction {
-                Command::IntLiteral(i) => state.pushi(*i as i64),
-                Command::CharLiteral(c) => state.pushi(*c as i64),
-                Command::Dup => state.push(state.peek()?),
-                Command::Drop => _ = state.pop()?,
-                Command::Swap => {
-                    let a = state.pop()?;
-                    let b = state.pop()?;
-                    state.push(a);
-                    state.push(b);
-                }
-                Command::Rot => {
-                    let a = state.pop()?;
-                    let b = state.pop()?;
-                    let c = state.pop()?;
-                    state.push(b);
-                    state.push(a);
-                    state.push(c);
-                }
-                Command::Pick => {
-                    let index = state.pop()?.into_integer(&self.config, &state)?;
-                    if index < 0 || index as usize >= state.data_stack.len() {
-                        return Err(InterpreterError::index_out_of_bounds(
-                            state.backtrace(),
-                            index,
-                            state.data_stack.len(),
-                        ));
-                    }
-                    state.push(state.data_stack[state.data_stack.len() - index as usize - 1]);
-                }
-                Command::Add => {
-                    let a = state.pop()?.into_integer(&self.config, &state)?;
-                    let b = state.pop()?.into_integer(&self.config, &state)?;
-                    state.pushi(a + b);
-                }
-                Command::Sub => {
-                    let a = state.pop()?.into_integer(&self.config, &state)?;
-                    let b = state.pop()?.into_integer(&self.config, &state)?;
-                    state.pushi(b - a);
-                }
-                Command::Mul => {
-                    let a = state.pop()?.into_integer(&self.config, &state)?;
-                    let b = state.pop()?.into_integer(&self.config, &state)?;
-                    state.pushi(a * b);
-                }
-                Command::Div => {
-                    let a = state.pop()?.into_integer(&self.config, &state)?;
-                    let b = state.pop()?.into_integer(&self.config, &state)?;
-                    state.pushi(b / a);
-                }
-                Command::Neg => {
-                    let a = state.pop()?.into_integer(&self.config, &state)?;
-                    state.pushi(-a);
-                }
-                Command::BitAnd => {
-                    let a = state.pop()?.into_integer(&self.config, &state)?;
-                    let b = state.pop()?.into_integer(&self.config, &state)?;
-                    state.pushi(a & b);
-                }
-                Command::BitOr => {
-                    let a = state.pop()?.into_integer(&self.config, &state)?;
-                    let b = state.pop()?.into_integer(&self.config, &state)?;
-                    state.pushi(a | b);
-                }
-                Command::BitNot => {
-                    let a = state.pop()?.into_integer(&self.config, &state)?;
-                    state.pushi(!a);
-                }
-                Command::Gt => {
-                    let a = state.pop()?.into_integer(&self.config, &state)?;
-                    let b = state.pop()?.into_integer(&self.config, &state)?;
-                    state.pushi(if b > a { -1 } else { 0 });
-                }
-                Command::Eq => {
-                    let a = state.pop()?.into_integer(&self.config, &state)?;
-                    let b = state.pop()?.into_integer(&self.config, &state)?;
-                    state.pushi(if b == a { -1 } else { 0 });
-                }
-                Command::Lambda(LambdaCommand::LambdaReference(id)) => {
-                    state.push(StackValue::Lambda(*id));
-                }
-                Command::Lambda(LambdaCommand::LambdaDefinition(..)) => {
-                    return Err(InterpreterError::lambda_definition_not_allowed(
-                        state.backtrace(),
-                    ));
-                }
-                Command::Exec => {
-                    let id = state.pop()?.into_lambda(&self.config, &state)?;
-                    state.call_lambda(id)?;
+            if !self.step_back() {
+                return Status::StartOfHistory;
+            }
+            if let Some(breakpoint) = self
+                .breakpoints
+                .iter()
+                .find(|b| b.matches(&self.state.frame))
+            {
+                return Status::Breakpoint(*breakpoint);
+            }
+            if stop(self) {
+                return Status::Paused;
+            }
+        }
+    }
+
+    /// Undo commands until the previous breakpoint or the start of the history.
+    pub fn reverse_resume(&mut self) -> Status {
+        self.run_back_until(|_| false)
+    }
+
+    /// How many commands ago `var` was last stored, and where. Only the history is searched.
+    pub fn last_write(&self, var: char) -> Option<(usize, ProgramPos)> {
+        let history = self.state.history.as_ref()?;
+        history.steps().enumerate().find_map(|(i, step)| {
+            let stored = step
+                .changes
+                .iter()
+                .any(|change| matches!(change, Change::Store(v, ..) if *v == var));
+            stored.then_some((
+                i + 1,
+                ProgramPos {
+                    pos: step.frame.pos,
+                    program_counter: step.frame.program_counter,
+                    lambda_id: step.frame.lambda_id,
+                },
+            ))
+        })
+    }
+
+    /// Execute the program to completion, ignoring breakpoints.
+    pub fn run(mut self) -> Result<RunOutcome, InterpreterError> {
+        self.run_steps(Until::Finished)?;
+        Ok(RunOutcome {
+            data_stack: self.state.data_stack,
+            variables: self.state.variables,
+            instructions: self.state.instructions,
+            output_bytes: self.state.output_bytes,
+        })
+    }
+
+    /// Execute a single command. Lambdas that have no commands left return immediately
+    /// afterward, so that [Interpreter::current_command] is always the next one to execute.
+    pub fn step(&mut self) -> Result<Status, InterpreterError> {
+        self.run_steps(Until::NextCommand)
+    }
+
+    fn run_steps(&mut self, until: Until) -> Result<Status, InterpreterError> {
+        let Self {
+            input,
+            output,
+            observer,
+            program,
+            state,
+            builtins,
+            breakpoints,
+            ..
+        } = self;
+        // the lambda of the current frame, only looked up again when the frame changes.
+        let mut lambda = None;
+        let mut poll = true;
+        loop {
+            let before = state.history.is_some().then_some((
+                state.frame,
+                state.instructions,
+                state.output_bytes,
+            ));
+            let mut result =
+                if poll && state.cancellation.is_cancelled() && !state.is_finished(program) {
+                    // always with the full backtrace, it is all the user gets to see of where it
+                    // stopped.
+                    Err(InterpreterError::interrupted(
+                        state.backtrace(true),
+                        state.data_stack.clone(),
+                    ))
+                } else {
+                    Self::step_inner(
+                        state,
+                        program,
+                        &mut lambda,
+                        input,
+                        output,
+                        observer,
+                        builtins,
+                    )
+                };
+            if let (Some(history), Some((frame, instructions, output_bytes))) =
+                (&mut state.history, before)
+            {
+                // failed commands can be undone as well, but the end of the program and an
+                // interruption before the next command execute nothing.
+                if state.instructions != instructions {
+                    history.push(frame, instructions, output_bytes);
+                } else {
+                    history.discard();
                 }
-                Command::Conditional => {
-                    let lambda_id = state.pop()?.into_lambda(&self.config, &state)?;
-                    let condition = state.pop()?.into_integer(&self.config, &state)?;
-                    if condition != 0 {
-                        state.call_lambda(lambda_id)?;
-                    }
+            }
+            if let Err(e) = &mut result {
+                if state.origins.is_some()
+                    && !matches!(
+                        e.kind,
+                        InterpreterErrorKind::Interrupted(_) | InterpreterErrorKind::Exit(_)
+                    )
+                {
+                    e.context = Some(Box::new(state.error_context(program, &e.kind)));
                 }
-                Command::While => match state.loop_state {
-                    LoopState::None => {
-                        let body = state.pop()?.into_lambda(&self.config, &state)?;
-                        let condition = state.pop()?.into_lambda(&self.config, &state)?;
-                        state.loop_state = LoopState::ExecutingCondition(condition, body);
-                        state.program_counter -= 1;
-                        state.call_lambda(condition)?;
-                    }
-                    LoopState::ExecutingBody(condition, body) => {
-                        state.loop_state = LoopState::ExecutingCondition(condition, body);
-                        state.program_counter -= 1;
-                        state.call_lambda(condition)?;
+                observer.error(e);
+            }
+            let status = result?;
+            match until {
+                _ if status == Status::Finished => return Ok(status),
+                Until::NextCommand => return Ok(status),
+                Until::Breakpoint | Until::Instructions(_) => {
+                    if let Some(breakpoint) = breakpoints.iter().find(|b| b.matches(&state.frame)) {
+                        return Ok(Status::Breakpoint(*breakpoint));
                     }
-                    LoopState::ExecutingCondition(condition, body) => {
-                        let result = state.pop()?.into_integer(&self.config, &state)?;
-                        if result == 0 {
-                            state.loop_state = LoopState::None;
-                        } else {
-                            state.loop_state = LoopState::ExecutingBody(condition, body);
-                            state.program_counter -= 1;
-                            state.call_lambda(body)?;
-                        }
+                    if let Until::Instructions(end) = until
+                        && state.instructions >= end
+                    {
+                        return Ok(Status::Paused);
                     }
-                },
-                Command::Var(c) => state.push(StackValue::Var(*c)),
-                Command::Store => {
-                    let var = state.pop()?.into_var(&self.config, &state)?;
-                    let value = state.pop()?;
-                    state.variables.insert(var, value);
                 }
-                Command::Load => {
-                    let var = state.pop()?.into_var(&self.config, &state)?;
-                    let value = state
-                        .variables
-                        .get(&var)
-                        .copied()
-                        .unwrap_or(StackValue::Integer(0));
-                    state.push(value);
+                Until::Finished => {}
+            }
+            // checking the token is cheap, but not free.
+            poll = state.instructions.is_multiple_of(POLL_INTERVAL);
+        }
+    }
+
+    fn step_inner<'p>(
+        state: &mut State,
+        program: &'p Program<'source>,
+        cache: &mut Option<(u64, &'p Lambda<'source>)>,
+        input: &mut Input,
+        output: &mut Output,
+        observer: &mut Observer,
+        builtins: &mut Builtins,
+    ) -> Result<Status, InterpreterError> {
+        let pc = state.frame.program_counter;
+        let lambda = match *cache {
+            Some((id, lambda)) if id == state.frame.lambda_id => lambda,
+            _ => get_lambda(program, state.frame.lambda_id, || state.error_backtrace())?,
+        };
+        let Some((command, span)) = lambda.get(pc) else {
+            return if state.call_stack.is_empty() {
+                Ok(Status::Finished)
+            } else {
+                Err(InterpreterError::invalid_program_counter(
+                    state.error_backtrace(),
+                    pc,
+                ))
+            };
+        };
+        state.frame.pos = span.start;
+        if let Some(origins) = &mut state.origins {
+            origins.popped.clear();
+        }
+        state.count_instruction()?;
+        let event = CommandEvent {
+            command,
+            span,
+            lambda_id: state.frame.lambda_id,
+            program_counter: pc,
+            depth: state.call_stack.len(),
+        };
+        observer.before_command(&event);
+        // (lambda, return address)
+        let call = match command {
+            Command::Lambda(LambdaCommand::LambdaDefinition(..)) => {
+                return Err(InterpreterError::lambda_definition_not_allowed(
+                    state.error_backtrace(),
+                ));
+            }
+            Command::Exec => Some((state.pop_callee(builtins)?, pc + 1)),
+            Command::Conditional => {
+                let lambda_id = state.pop_callee(builtins)?;
+                let condition = state.pop_integer()?;
+                observer.conditional(&event, condition != 0);
+                (condition != 0).then_some((lambda_id, pc + 1))
+            }
+            // the `#` is executed again after the condition and the body return.
+            Command::While => match state.frame.loop_state {
+                LoopState::None => {
+                    let body = state.pop_callee(builtins)?;
+                    let condition = state.pop_callee(builtins)?;
+                    state.frame.loop_state = LoopState::ExecutingCondition(condition, body);
+                    Some((condition, pc))
                 }
-                Command::ReadChar => {
-                    let mut buf = [0];
-                    match self.input.read_exact(&mut buf) {
-                        Ok(()) => {
-                            state.pushi(buf[0] as i64);
-                        }
-                        Err(e) if e.kind() == ErrorKind::UnexpectedEof => {
-                            state.pushi(-1);
-                        }
-                        Err(e) => {
-                            return Err(InterpreterError::io_error(state.backtrace(), e));
-                        }
-                    };
+                LoopState::ExecutingBody(condition, body) => {
+                    state.frame.loop_state = LoopState::ExecutingCondition(condition, body);
+                    Some((condition, pc))
                 }
-                Command::WriteChar => {
-                    let c = state.pop()?.into_integer(&self.config, &state)?;
-                    self.output
-                        .write_all(&[c as u8])
-                        .map_err(|e| InterpreterError::io_error(state.backtrace(), e))?;
+                LoopState::ExecutingCondition(condition, body) => {
+                    let result = state.pop_integer()?;
+                    observer.loop_iteration(&event, result != 0);
+                    if result == 0 {
+                        state.frame.loop_state = LoopState::None;
+                        None
+                    } else {
+                        state.frame.loop_state = LoopState::ExecutingBody(condition, body);
+                        Some((body, pc))
+                    }
                 }
-                Command::StringLiteral(s) => {
-                    self.output
-                        .write_all(s.as_bytes())
-                        .map_err(|e| InterpreterError::io_error(state.backtrace(), e))?;
+            },
+            command => {
+                Self::execute(state, input, output, observer, command)?;
+                None
+            }
+        };
+        let current = match call {
+            // builtins run right away, a `#` then continues with their result.
+            Some((id, return_to)) if builtins.contains(id) => {
+                state.call_builtin(builtins, id, input, output, observer)?;
+                state.frame.program_counter = return_to;
+                lambda
+            }
+            Some((id, return_to)) => state.call_lambda(program, id, return_to, observer)?,
+            None => {
+                state.frame.program_counter += 1;
+                lambda
+            }
+        };
+        observer.after_command(&event, &state.data_stack);
+        let current = state.unwind_from(program, current, observer)?;
+        *cache = Some((state.frame.lambda_id, current));
+        Ok(
+            if state.call_stack.is_empty() && state.frame.program_counter >= current.len() {
+                Status::Finished
+            } else {
+                Status::Paused
+            },
+        )
+    }
+
+    /// Execute a command that does not affect control flow.
+    fn execute(
+        state: &mut State,
+        input: &mut Input,
+        output: &mut Output,
+        observer: &mut Observer,
+        command: &Command,
+    ) -> Result<(), InterpreterError> {
+        match command {
+            Command::IntLiteral(i) => state.pushi(*i as i64)?,
+            Command::CharLiteral(c) => state.pushi(*c as i64)?,
+            Command::Dup => {
+                let (value, origin) = state.peek()?;
+                state.push_from(value, origin)?;
+            }
+            Command::Drop => _ = state.pop()?,
+            Command::Swap => {
+                let a = state.pop_from()?;
+                let b = state.pop_from()?;
+                state.push_from(a.0, a.1)?;
+                state.push_from(b.0, b.1)?;
+            }
+            Command::Rot => {
+                let a = state.pop_from()?;
+                let b = state.pop_from()?;
+                let c = state.pop_from()?;
+                state.push_from(b.0, b.1)?;
+                state.push_from(a.0, a.1)?;
+                state.push_from(c.0, c.1)?;
+            }
+            Command::Pick => {
+                let index = state.pop_integer()?;
+                if index < 0 || index as usize >= state.data_stack.len() {
+                    return Err(InterpreterError::index_out_of_bounds(
+                        state.error_backtrace(),
+                        index,
+                        state.data_stack.len(),
+                    ));
                 }
-                Command::WriteInt => {
-                    let i = state.pop()?.into_integer(&self.config, &state)?;
-                    write!(self.output, "{}", i)
-                        .map_err(|e| InterpreterError::io_error(state.backtrace(), e))?;
+                let (value, origin) = state.peek_from(index as usize).unwrap();
+                state.push_from(value, origin)?;
+            }
+            Command::Add => {
+                let a = state.pop_integer()?;
+                let b = state.pop_integer()?;
+                let result = state.arithmetic(
+                    "addition",
+                    || b.checked_add(a),
+                    || b.wrapping_add(a),
+                    || b.saturating_add(a),
+                )?;
+                state.pushi(result)?;
+            }
+            Command::Sub => {
+                let a = state.pop_integer()?;
+                let b = state.pop_integer()?;
+                let result = state.arithmetic(
+                    "subtraction",
+                    || b.checked_sub(a),
+                    || b.wrapping_sub(a),
+                    || b.saturating_sub(a),
+                )?;
+                state.pushi(result)?;
+            }
+            Command::Mul => {
+                let a = state.pop_integer()?;
+                let b = state.pop_integer()?;
+                let result = state.arithmetic(
+                    "multiplication",
+                    || b.checked_mul(a),
+                    || b.wrapping_mul(a),
+                    || b.saturating_mul(a),
+                )?;
+                state.pushi(result)?;
+            }
+            Command::Div => {
+                let a = state.pop_integer()?;
+                let b = state.pop_integer()?;
+                if a == 0 {
+                    return Err(InterpreterError::division_by_zero(state.error_backtrace()));
                 }
-                Command::Flush => {
-                    self.output
-                        .flush()
-                        .map_err(|e| InterpreterError::io_error(state.backtrace(), e))?;
+                // only i64::MIN / -1 overflows
+                let result = state.arithmetic(
+                    "division",
+                    || b.checked_div(a),
+                    || b.wrapping_div(a),
+                    || b.saturating_div(a),
+                )?;
+                state.pushi(result)?;
+            }
+            Command::Neg => {
+                let a = state.pop_integer()?;
+                let result = state.arithmetic(
+                    "negation",
+                    || a.checked_neg(),
+                    || a.wrapping_neg(),
+                    || a.saturating_neg(),
+                )?;
+                state.pushi(result)?;
+            }
+            Command::BitAnd => {
+                let a = state.pop_integer()?;
+                let b = state.pop_integer()?;
+                state.pushi(a & b)?;
+            }
+            Command::BitOr => {
+                let a = state.pop_integer()?;
+                let b = state.pop_integer()?;
+                state.pushi(a | b)?;
+            }
+            Command::BitNot => {
+                let a = state.pop_integer()?;
+                state.pushi(!a)?;
+            }
+            Command::Gt => {
+                let a = state.pop_integer()?;
+                let b = state.pop_integer()?;
+                state.pushi(if b > a { -1 } else { 0 })?;
+            }
+            Command::Eq => {
+                let a = state.pop_integer()?;
+                let b = state.pop_integer()?;
+                state.pushi(if b == a { -1 } else { 0 })?;
+            }
+            Command::Lambda(LambdaCommand::LambdaReference(id)) => {
+                state.push(StackValue::Lambda(*id))?;
+            }
+            Command::Var(c) if !c.is_ascii_lowercase() => {
+                return Err(InterpreterError::invalid_variable_name(
+                    state.error_backtrace(),
+                    *c,
+                ));
+            }
+            Command::Var(c) => state.push(StackValue::Var(*c))?,
+            Command::Store => {
+                let var = state.pop_var()?;
+                let (value, origin) = state.pop_from()?;
+                let previous = state.variables.insert(var, value);
+                let previous_origin = state
+                    .origins
+                    .as_mut()
+                    .and_then(|origins| origins.variables.insert(var, origin));
+                state.record(Change::Store(var, previous, previous_origin));
+                if let StackValue::Lambda(id) = value {
+                    let previous = state.lambda_names.insert(id, var);
+                    state.record(Change::Name(id, previous));
                 }
-                Command::Comment(_) => {}
+                observer.variable_store(var, value);
             }
+            Command::Load => {
+                let var = state.pop_var()?;
+                let value = state
+                    .variables
+                    .get(&var)
+                    .copied()
+                    .unwrap_or(StackValue::Integer(0));
+                observer.variable_load(var, value);
+                let origin = match state.origins.as_ref().and_then(|o| o.variables.get(&var)) {
+                    Some(origin) => *origin,
+                    // an unassigned variable is 0 from where it is loaded.
+                    None => state.origin(),
+                };
+                state.push_from(value, origin)?;
+            }
+            Command::ReadChar => {
+                let byte = read_byte(input, state.history.as_mut())
+                    .map_err(|e| InterpreterError::io_error(state.error_backtrace(), e))?;
+                observer.read(byte);
+                state.pushi(byte.map_or(-1, i64::from))?;
+            }
+            Command::WriteChar => {
+                let c = state.pop_integer()?;
+                state.write(output, observer, &[c as u8])?;
+            }
+            Command::StringLiteral(s) => state.write(output, observer, s.as_bytes())?,
+            Command::WriteInt => {
+                let i = state.pop_integer()?;
+                state.write(output, observer, i.to_string().as_bytes())?;
+            }
+            Command::Flush => {
+                output
+                    .flush()
+                    .map_err(|e| InterpreterError::io_error(state.error_backtrace(), e))?;
+            }
+            Command::Comment(_) => {}
+            Command::Lambda(LambdaCommand::LambdaDefinition(..))
+            | Command::Exec
+            | Command::Conditional
+            | Command::While => unreachable!("control flow is handled by step"),
         }
-
-        #[cfg(test)]
-        {
-            let mut stack = self.stack.borrow_mut();
-            stack.clear();
-            stack.extend(state.data_stack.iter().copied());
-        }
-
         Ok(())
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::{Interpreter, StackValue};
+    use crate::coverage::{Branches, Coverage};
+    use crate::error::{InterpreterError, InterpreterErrorKind};
+    use crate::observer::{CommandEvent, ExecutionObserver};
+    use crate::{Breakpoint, Interpreter, StackValue, Status};
     use falsec_types::source::LambdaCommand::LambdaReference;
     use falsec_types::source::{Command, Pos, Program, Span};
-    use falsec_types::{Config, TypeSafety};
+    use falsec_types::{Arithmetic, Config, Limits, TypeSafety};
     use std::borrow::Cow;
-    use std::cell::RefCell;
     use std::collections::HashMap;
-    use std::ops::Deref;
-    use std::rc::Rc;
 
-    fn run_simple(program: Program, stack: Rc<RefCell<Vec<StackValue>>>) {
-        let interpreter = Interpreter::<&[_], &mut [_]> {
-            input: &[],
-            output: &mut [],
-            program,
-            config: Default::default(),
-            stack,
-        };
-        interpreter.run().unwrap();
+    /// Run `program` on `data_stack` and return the final data stack.
+    fn run_simple(program: Program, data_stack: Vec<StackValue>) -> Vec<StackValue> {
+        Interpreter::<&[_], &mut [_]>::new(&[], &mut [], program, Default::default())
+            .with_data_stack(data_stack)
+            .run()
+            .unwrap()
+            .data_stack
     }
 
     /// ```
@@ -507,9 +1577,7 @@ fn empty_program() {
             lambdas: HashMap::from([(0, Vec::new())]),
             ..Default::default()
         };
-        let stack = Rc::<RefCell<_>>::default();
-        run_simple(program, stack.clone());
-        assert_eq!(stack.borrow().len(), 0);
+        assert_eq!(run_simple(program, Vec::new()), []);
     }
 
     #[test]
@@ -529,9 +1597,7 @@ fn push_integer() {
             )]),
             ..Default::default()
         };
-        let stack = Rc::<RefCell<_>>::default();
-        run_simple(program, stack.clone());
-        assert_eq!(stack.borrow().deref(), &[StackValue::Integer(123)]);
+        assert_eq!(run_simple(program, Vec::new()), [StackValue::Integer(123)]);
     }
 
     #[test]
@@ -541,9 +1607,7 @@ fn simple_program() {
             Command::IntLiteral(321),
             Command::Add,
         ];
-        let stack = Rc::<RefCell<_>>::default();
-        run_simple(program, stack.clone());
-        assert_eq!(stack.borrow().deref(), &[StackValue::Integer(444)]);
+        assert_eq!(run_simple(program, Vec::new()), [StackValue::Integer(444)]);
     }
 
     #[test]
@@ -555,9 +1619,7 @@ fn store_load() {
             Command::Var('a'),
             Command::Load,
         ];
-        let stack = Rc::<RefCell<_>>::default();
-        run_simple(program, stack.clone());
-        assert_eq!(stack.borrow().deref(), &[StackValue::Integer(123)]);
+        assert_eq!(run_simple(program, Vec::new()), [StackValue::Integer(123)]);
     }
 
     #[test]
@@ -577,9 +1639,7 @@ fn basic_lambda() {
             ]),
             ..Default::default()
         };
-        let stack = Rc::<RefCell<_>>::default();
-        run_simple(program, stack.clone());
-        assert_eq!(stack.borrow().deref(), &[StackValue::Integer(444)]);
+        assert_eq!(run_simple(program, Vec::new()), [StackValue::Integer(444)]);
     }
 
     #[test]
@@ -595,12 +1655,14 @@ fn conditional() {
             ]),
             ..Default::default()
         };
-        let stack_false = Rc::new(RefCell::new(vec![StackValue::Integer(0)]));
-        run_simple(program.clone(), stack_false.clone());
-        assert_eq!(stack_false.borrow().deref(), &[]);
-        let stack_true = Rc::new(RefCell::new(vec![StackValue::Integer(-1)]));
-        run_simple(program, stack_true.clone());
-        assert_eq!(stack_true.borrow().deref(), &[StackValue::Integer(123)]);
+        assert_eq!(
+            run_simple(program.clone(), vec![StackValue::Integer(0)]),
+            []
+        );
+        assert_eq!(
+            run_simple(program, vec![StackValue::Integer(-1)]),
+            [StackValue::Integer(123)]
+        );
     }
 
     #[test]
@@ -646,25 +1708,12 @@ fn factorial() {
             ]),
             ..Default::default()
         };
-        let stack0 = Rc::new(RefCell::new(vec![StackValue::Integer(0)]));
-        let stack1 = Rc::new(RefCell::new(vec![StackValue::Integer(1)]));
-        let stack2 = Rc::new(RefCell::new(vec![StackValue::Integer(2)]));
-        let stack3 = Rc::new(RefCell::new(vec![StackValue::Integer(3)]));
-        let stack4 = Rc::new(RefCell::new(vec![StackValue::Integer(4)]));
-        let stack5 = Rc::new(RefCell::new(vec![StackValue::Integer(5)]));
-        run_simple(program.clone(), stack0.clone());
-        run_simple(program.clone(), stack1.clone());
-        run_simple(program.clone(), stack2.clone());
-        run_simple(program.clone(), stack3.clone());
-        run_simple(program.clone(), stack4.clone());
-        run_simple(program, stack5.clone());
-
-        assert_eq!(stack0.borrow().deref(), &[StackValue::Integer(1)]);
-        assert_eq!(stack1.borrow().deref(), &[StackValue::Integer(1)]);
-        assert_eq!(stack2.borrow().deref(), &[StackValue::Integer(2)]);
-        assert_eq!(stack3.borrow().deref(), &[StackValue::Integer(6)]);
-        assert_eq!(stack4.borrow().deref(), &[StackValue::Integer(24)]);
-        assert_eq!(stack5.borrow().deref(), &[StackValue::Integer(120)]);
+        for (n, factorial) in [(0, 1), (1, 1), (2, 2), (3, 6), (4, 24), (5, 120)] {
+            assert_eq!(
+                run_simple(program.clone(), vec![StackValue::Integer(n)]),
+                [StackValue::Integer(factorial)]
+            );
+        }
     }
 
     #[test]
@@ -676,16 +1725,11 @@ fn output_char() {
             Command::WriteChar,
             Command::Flush,
         ];
-        let stack = Rc::<RefCell<_>>::default();
         let mut output = Vec::new();
-        let interpreter = Interpreter {
-            input: &[] as &[u8],
-            output: &mut output,
-            program,
-            config: Default::default(),
-            stack,
-        };
-        interpreter.run().unwrap();
+        let interpreter = Interpreter::new(&[] as &[u8], &mut output, program, Default::default());
+        let outcome = interpreter.run().unwrap();
+        assert_eq!(outcome.instructions, 5);
+        assert_eq!(outcome.output_bytes, 2);
         assert_eq!(output, b"Hi");
     }
 
@@ -695,15 +1739,8 @@ fn output_string() {
             Command::StringLiteral(Cow::Borrowed("Hello, World!")),
             Command::Flush,
         ];
-        let stack = Rc::<RefCell<_>>::default();
         let mut output = Vec::new();
-        let interpreter = Interpreter {
-            input: &[] as &[u8],
-            output: &mut output,
-            program,
-            config: Default::default(),
-            stack,
-        };
+        let interpreter = Interpreter::new(&[] as &[u8], &mut output, program, Default::default());
         interpreter.run().unwrap();
         assert_eq!(output, b"Hello, World!");
     }
@@ -717,15 +1754,8 @@ fn output_number() {
             Command::WriteInt,
             Command::Flush,
         ];
-        let stack = Rc::<RefCell<_>>::default();
         let mut output = Vec::new();
-        let interpreter = Interpreter {
-            input: &[] as &[u8],
-            output: &mut output,
-            program,
-            config: Default::default(),
-            stack,
-        };
+        let interpreter = Interpreter::new(&[] as &[u8], &mut output, program, Default::default());
         interpreter.run().unwrap();
         assert_eq!(output, b"123456");
     }
@@ -739,16 +1769,10 @@ fn input_char() {
             Command::WriteChar,
             Command::Flush,
         ];
-        let stack = Rc::<RefCell<_>>::default();
         let input = b"Hi";
         let mut output = Vec::new();
-        let interpreter = Interpreter {
-            input: input.as_ref(),
-            output: &mut output,
-            program,
-            config: Default::default(),
-            stack,
-        };
+        let interpreter =
+            Interpreter::new(input.as_ref(), &mut output, program, Default::default());
         interpreter.run().unwrap();
         assert_eq!(output, b"Hi");
     }
@@ -872,16 +1896,10 @@ fn add() {
             ]),
             ..Default::default()
         };
-        let stack = Rc::<RefCell<_>>::default();
         let input = b"123\n321\n";
         let mut output = Vec::new();
-        let interpreter = Interpreter {
-            input: input.as_ref(),
-            output: &mut output,
-            program,
-            config: Default::default(),
-            stack,
-        };
+        let interpreter =
+            Interpreter::new(input.as_ref(), &mut output, program, Default::default());
         interpreter.run().unwrap();
         assert_eq!(output, b"A: 123\nB: 321\n123 + 321 = 444\n");
     }
@@ -895,20 +1913,22 @@ fn number_as_var() {
             Command::IntLiteral(2),
             Command::Load,
         ];
-        let stack = Rc::<RefCell<_>>::default();
-        Interpreter::<&[_], &mut [_]> {
-            input: &[],
-            output: &mut [],
+        let outcome = Interpreter::<&[_], &mut [_]>::new(
+            &[],
+            &mut [],
             program,
-            config: Config {
+            Config {
                 type_safety: TypeSafety::None,
                 ..Default::default()
             },
-            stack: stack.clone(),
-        }
+        )
         .run()
         .unwrap();
-        assert_eq!(stack.borrow().deref(), &[StackValue::Integer(123)]);
+        assert_eq!(outcome.data_stack, [StackValue::Integer(123)]);
+        assert_eq!(
+            outcome.variables,
+            HashMap::from([('c', StackValue::Integer(123))])
+        );
     }
 
     #[test]
@@ -921,20 +1941,394 @@ fn test_empty_input() {
             Command::ReadChar,
             Command::WriteInt,
         ];
-        let stack = Rc::<RefCell<_>>::default();
         let mut output = Vec::<u8>::new();
-        Interpreter {
-            input: &b"x"[..],
-            output: &mut output,
+        Interpreter::new(
+            &b"x"[..],
+            &mut output,
             program,
-            config: Config {
+            Config {
                 type_safety: TypeSafety::None,
                 ..Default::default()
             },
-            stack,
-        }
+        )
         .run()
         .unwrap();
         assert_eq!(output, format!("{}-1-1", b'x').as_bytes());
     }
+
+    fn basic_lambda_program() -> Program<'static> {
+        Program {
+            main_id: 0,
+            lambdas: HashMap::from([
+                (
+                    0,
+                    simple_lambda![
+                        Command::IntLiteral(123),
+                        Command::Lambda(LambdaReference(1)),
+                        Command::Exec,
+                    ],
+                ),
+                (1, simple_lambda![Command::IntLiteral(321), Command::Add]),
+            ]),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn step() {
+        let mut interpreter = Interpreter::<&[_], &mut [_]>::new(
+            &[],
+            &mut [],
+            basic_lambda_program(),
+            Default::default(),
+        );
+        assert_eq!(interpreter.step().unwrap(), Status::Paused);
+        assert_eq!(interpreter.data_stack(), &[StackValue::Integer(123)]);
+        assert_eq!(interpreter.step().unwrap(), Status::Paused);
+        assert_eq!(interpreter.step().unwrap(), Status::Paused);
+        assert_eq!(interpreter.current_frame().lambda_id, 1);
+        assert_eq!(interpreter.call_stack().len(), 1);
+        assert_eq!(interpreter.call_stack()[0].program_counter, 3);
+        assert_eq!(interpreter.step().unwrap(), Status::Paused);
+        // returning from lambda 1 is part of the last step.
+        assert_eq!(interpreter.step().unwrap(), Status::Finished);
+        assert!(interpreter.is_finished());
+        assert!(interpreter.current_command().is_none());
+        assert_eq!(interpreter.data_stack(), &[StackValue::Integer(444)]);
+        assert_eq!(interpreter.step().unwrap(), Status::Finished);
+    }
+
+    #[test]
+    fn lambda_breakpoint() {
+        let mut interpreter = Interpreter::<&[_], &mut [_]>::new(
+            &[],
+            &mut [],
+            basic_lambda_program(),
+            Default::default(),
+        );
+        interpreter.add_breakpoint(Breakpoint::Lambda(1));
+        assert_eq!(
+            interpreter.resume().unwrap(),
+            Status::Breakpoint(Breakpoint::Lambda(1))
+        );
+        assert_eq!(
+            interpreter.current_command().map(|(c, _)| c),
+            Some(&Command::IntLiteral(321))
+        );
+        let backtrace = interpreter.backtrace();
+        assert_eq!(backtrace.len(), 2);
+        assert_eq!(
+            (backtrace[1].lambda_id, backtrace[1].program_counter),
+            (0, 2)
+        );
+        assert_eq!(interpreter.resume().unwrap(), Status::Finished);
+    }
+
+    #[test]
+    fn pos_breakpoint() {
+        let span = |column| {
+            Span::new(
+                Pos::new(column, 1, column),
+                Pos::new(column + 1, 1, column + 1),
+                "",
+            )
+        };
+        let program = Program {
+            main_id: 0,
+            lambdas: HashMap::from([(
+                0,
+                vec![
+                    (Command::IntLiteral(1), span(0)),
+                    (Command::IntLiteral(2), span(1)),
+                    (Command::Add, span(2)),
+                    (Command::Var('a'), span(3)),
+                    (Command::Store, span(4)),
+                ],
+            )]),
+            ..Default::default()
+        };
+        let mut interpreter =
+            Interpreter::<&[_], &mut [_]>::new(&[], &mut [], program, Default::default());
+        interpreter.add_breakpoint(Breakpoint::Pos(Pos::new(0, 1, 2)));
+        interpreter.add_breakpoint(Breakpoint::Span(Pos::new(0, 1, 4), Pos::new(0, 2, 0)));
+        assert!(matches!(
+            interpreter.resume(),
+            Ok(Status::Breakpoint(Breakpoint::Pos(_)))
+        ));
+        assert_eq!(interpreter.data_stack().len(), 2);
+        assert!(matches!(
+            interpreter.resume(),
+            Ok(Status::Breakpoint(Breakpoint::Span(..)))
+        ));
+        assert!(interpreter.variables().is_empty());
+        assert_eq!(interpreter.resume().unwrap(), Status::Finished);
+        assert_eq!(interpreter.variables()[&'a'], StackValue::Integer(3));
+    }
+
+    #[test]
+    fn load() {
+        let mut interpreter = Interpreter::<&[_], &mut [_]>::new(
+            &[],
+            &mut [],
+            simple_program![Command::IntLiteral(123)],
+            Default::default(),
+        );
+        assert_eq!(interpreter.resume().unwrap(), Status::Finished);
+        interpreter.load(Program {
+            main_id: 0,
+            lambdas: HashMap::from([
+                (
+                    0,
+                    simple_lambda![
+                        Command::Lambda(LambdaReference(1)),
+                        Command::Var('f'),
+                        Command::Store,
+                    ],
+                ),
+                (1, simple_lambda![Command::IntLiteral(321), Command::Add]),
+            ]),
+            ..Default::default()
+        });
+        assert_eq!(interpreter.resume().unwrap(), Status::Finished);
+        assert_eq!(interpreter.variables()[&'f'], StackValue::Lambda(2));
+        // the lambda stored by the previous program is still callable.
+        interpreter.load(simple_program![
+            Command::Var('f'),
+            Command::Load,
+            Command::Exec,
+        ]);
+        assert_eq!(interpreter.resume().unwrap(), Status::Finished);
+        assert_eq!(interpreter.data_stack(), &[StackValue::Integer(444)]);
+    }
+
+    #[test]
+    fn division_by_zero() {
+        let program =
+            simple_program![Command::IntLiteral(1), Command::IntLiteral(0), Command::Div,];
+        let err = Interpreter::<&[_], &mut [_]>::new(&[], &mut [], program, Default::default())
+            .run()
+            .unwrap_err();
+        assert!(matches!(err.kind, InterpreterErrorKind::DivisionByZero));
+        assert_eq!(err.backtrace[0].program_counter, 2);
+    }
+
+    #[test]
+    fn overflow() {
+        let run = |arithmetic| {
+            let program = simple_program![
+                Command::IntLiteral(i64::MAX as u64),
+                Command::IntLiteral(1),
+                Command::Add,
+            ];
+            let config = Config {
+                arithmetic,
+                ..Default::default()
+            };
+            let mut interpreter = Interpreter::<&[_], &mut [_]>::new(&[], &mut [], program, config);
+            interpreter
+                .resume()
+                .map(|_| interpreter.data_stack().to_vec())
+        };
+        assert_eq!(
+            run(Arithmetic::Wrapping).unwrap(),
+            &[StackValue::Integer(i64::MIN)]
+        );
+        assert_eq!(
+            run(Arithmetic::Saturating).unwrap(),
+            &[StackValue::Integer(i64::MAX)]
+        );
+        assert!(matches!(
+            run(Arithmetic::Checked).unwrap_err().kind,
+            InterpreterErrorKind::IntegerOverflow("addition")
+        ));
+    }
+
+    #[test]
+    fn limits() {
+        let run = |program, limits| {
+            let config = Config {
+                limits,
+                ..Default::default()
+            };
+            Interpreter::<&[_], Vec<_>>::new(&[], Vec::new(), program, config)
+                .resume()
+                .unwrap_err()
+                .kind
+        };
+        let literals = || {
+            simple_program![
+                Command::IntLiteral(1),
+                Command::IntLiteral(2),
+                Command::IntLiteral(3),
+            ]
+        };
+        assert!(matches!(
+            run(
+                literals(),
+                Limits {
+                    max_instructions: Some(2),
+                    ..Default::default()
+                }
+            ),
+            InterpreterErrorKind::InstructionLimitExceeded(2)
+        ));
+        assert!(matches!(
+            run(
+                literals(),
+                Limits {
+                    max_stack_depth: Some(2),
+                    ..Default::default()
+                }
+            ),
+            InterpreterErrorKind::StackLimitExceeded(2)
+        ));
+        assert!(matches!(
+            run(
+                simple_program![Command::StringLiteral(Cow::Borrowed("hello"))],
+                Limits {
+                    max_output_bytes: Some(3),
+                    ..Default::default()
+                }
+            ),
+            InterpreterErrorKind::OutputLimitExceeded(3)
+        ));
+        let recursion = Program {
+            main_id: 0,
+            lambdas: HashMap::from([
+                (
+                    0,
+                    simple_lambda![Command::Lambda(LambdaReference(1)), Command::Exec],
+                ),
+                (
+                    1,
+                    simple_lambda![Command::Lambda(LambdaReference(1)), Command::Exec],
+                ),
+            ]),
+            ..Default::default()
+        };
+        assert!(matches!(
+            run(
+                recursion,
+                Limits {
+                    max_call_depth: Some(10),
+                    ..Default::default()
+                }
+            ),
+            InterpreterErrorKind::CallDepthLimitExceeded(10)
+        ));
+    }
+
+    #[test]
+    fn cancellation() {
+        let mut interpreter = Interpreter::<&[_], Vec<_>>::new(
+            &[],
+            Vec::new(),
+            basic_lambda_program(),
+            Default::default(),
+        );
+        assert_eq!(interpreter.step().unwrap(), Status::Paused);
+        interpreter.cancellation_token().cancel();
+        let err = interpreter.resume().unwrap_err();
+        assert_eq!(err.backtrace[0].pos, interpreter.current_frame().pos);
+        assert!(matches!(
+            err.kind,
+            InterpreterErrorKind::Interrupted(stack) if stack == [StackValue::Integer(123)]
+        ));
+    }
+
+    #[test]
+    fn coverage() {
+        let mut program = basic_lambda_program();
+        program.lambdas.get_mut(&0).unwrap().extend(simple_lambda![
+            Command::IntLiteral(0),
+            Command::Lambda(LambdaReference(1)),
+            Command::Conditional,
+        ]);
+        let mut interpreter =
+            Interpreter::<&[_], Vec<_>>::new(&[], Vec::new(), program, Default::default())
+                .with_observer(Coverage::default());
+        assert_eq!(interpreter.resume().unwrap(), Status::Finished);
+        let coverage = interpreter.observer();
+        assert_eq!(coverage.commands.len(), 8);
+        assert!(coverage.commands.values().all(|count| *count == 1));
+        assert_eq!(coverage.calls, HashMap::from([(1, 1)]));
+        assert_eq!(
+            coverage.branches,
+            HashMap::from([(
+                (0, 5),
+                Branches {
+                    taken: 0,
+                    not_taken: 1
+                }
+            )])
+        );
+    }
+
+    #[test]
+    fn observer() {
+        #[derive(Default)]
+        struct Log(Vec<String>);
+
+        impl ExecutionObserver for Log {
+            fn before_command(&mut self, event: &CommandEvent) {
+                let e = event;
+                self.0
+                    .push(format!("before {}:{}", e.lambda_id, e.program_counter));
+            }
+
+            fn after_command(&mut self, _event: &CommandEvent, data_stack: &[StackValue]) {
+                self.0.push(format!("after {}", data_stack.len()));
+            }
+
+            fn lambda_call(&mut self, lambda_id: u64) {
+                self.0.push(format!("call {lambda_id}"));
+            }
+
+            fn lambda_return(&mut self, lambda_id: u64) {
+                self.0.push(format!("return {lambda_id}"));
+            }
+
+            fn error(&mut self, error: &InterpreterError) {
+                self.0.push(format!("error {}", error.kind));
+            }
+        }
+
+        let mut program = basic_lambda_program();
+        program
+            .lambdas
+            .get_mut(&0)
+            .unwrap()
+            .extend(simple_lambda![Command::Add]);
+        let mut interpreter =
+            Interpreter::<&[_], Vec<_>>::new(&[], Vec::new(), program, Default::default())
+                .with_observer(Log::default());
+        interpreter.resume().unwrap_err();
+        assert_eq!(
+            interpreter.observer().0,
+            [
+                "before 0:0",
+                "after 1",
+                "before 0:1",
+                "after 2",
+                "before 0:2",
+                "call 1",
+                "after 1",
+                "before 1:0",
+                "after 2",
+                "before 1:1",
+                "after 1",
+                "return 1",
+                "before 0:3",
+                "error Tried to pop from empty data stack",
+            ]
+        );
+    }
+
+    /// Interpreters are moved to worker threads, so builtins must not make them `!Send`.
+    #[test]
+    fn send() {
+        fn assert_send<T: Send>() {}
+        assert_send::<Interpreter<'static, std::io::Empty, std::io::Sink>>();
+        assert_send::<crate::vm::Vm<std::io::Empty, std::io::Sink>>();
+    }
 }
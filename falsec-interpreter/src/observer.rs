@@ -0,0 +1,124 @@
+use crate::StackValue;
+use crate::error::InterpreterError;
+use falsec_types::source::{Command, Span};
+
+/// Callbacks for events during execution, e.g. for tracing, profiling or coverage.
+/// Attach one with [crate::Interpreter::with_observer].
+///
+/// All methods do nothing by default. The interpreter is generic over its observer, so the
+/// default `()` observer is compiled away entirely. Several observers can be combined in a
+/// tuple, and an `Option` of an observer can be switched off at runtime.
+pub trait ExecutionObserver {
+    /// Called before a command is executed, after limits and cancellation were checked.
+    fn before_command(&mut self, _event: &CommandEvent) {}
+
+    /// Called after a command was executed successfully, before lambdas that have no
+    /// commands left return. `data_stack` is bottom first.
+    fn after_command(&mut self, _event: &CommandEvent, _data_stack: &[StackValue]) {}
+
+    /// Called when `!`, `?` or `#` calls a lambda, after the new frame was entered.
+    fn lambda_call(&mut self, _lambda_id: u64) {}
+
+    /// Called when a lambda returns to its caller.
+    fn lambda_return(&mut self, _lambda_id: u64) {}
+
+    /// Called when `?` popped its condition.
+    fn conditional(&mut self, _event: &CommandEvent, _taken: bool) {}
+
+    /// Called each time the condition of a `#` loop returned. The body is executed next if
+    /// `continues` is true, otherwise the loop ends.
+    fn loop_iteration(&mut self, _event: &CommandEvent, _continues: bool) {}
+
+    fn variable_store(&mut self, _var: char, _value: StackValue) {}
+
+    fn variable_load(&mut self, _var: char, _value: StackValue) {}
+
+    /// Called after `^` read a byte, or `None` at the end of the input.
+    fn read(&mut self, _byte: Option<u8>) {}
+
+    /// Called after the program wrote to its output.
+    fn write(&mut self, _bytes: &[u8]) {}
+
+    /// Called when a step fails.
+    fn error(&mut self, _error: &InterpreterError) {}
+}
+
+/// The command that is executed, see [ExecutionObserver::before_command].
+#[derive(Copy, Clone, Debug)]
+pub struct CommandEvent<'a, 'source> {
+    pub command: &'a Command<'source>,
+    pub span: &'a Span<'source>,
+    pub lambda_id: u64,
+    pub program_counter: usize,
+    /// The number of callers of the lambda that executes the command.
+    pub depth: usize,
+}
+
+impl ExecutionObserver for () {}
+
+macro_rules! forward {
+    ($self:ident => $($target:expr),+) => {
+        fn before_command(&mut $self, event: &CommandEvent) {
+            $($target.before_command(event);)+
+        }
+
+        fn after_command(&mut $self, event: &CommandEvent, data_stack: &[StackValue]) {
+            $($target.after_command(event, data_stack);)+
+        }
+
+        fn lambda_call(&mut $self, lambda_id: u64) {
+            $($target.lambda_call(lambda_id);)+
+        }
+
+        fn lambda_return(&mut $self, lambda_id: u64) {
+            $($target.lambda_return(lambda_id);)+
+        }
+
+        fn conditional(&mut $self, event: &CommandEvent, taken: bool) {
+            $($target.conditional(event, taken);)+
+        }
+
+        fn loop_iteration(&mut $self, event: &CommandEvent, continues: bool) {
+            $($target.loop_iteration(event, continues);)+
+        }
+
+        fn variable_store(&mut $self, var: char, value: StackValue) {
+            $($target.variable_store(var, value);)+
+        }
+
+        fn variable_load(&mut $self, var: char, value: StackValue) {
+            $($target.variable_load(var, value);)+
+        }
+
+        fn read(&mut $self, byte: Option<u8>) {
+            $($target.read(byte);)+
+        }
+
+        fn write(&mut $self, bytes: &[u8]) {
+            $($target.write(bytes);)+
+        }
+
+        fn error(&mut $self, error: &InterpreterError) {
+            $($target.error(error);)+
+        }
+    };
+}
+
+impl<O: ExecutionObserver> ExecutionObserver for Option<O> {
+    forward!(self => (match self {
+        Some(o) => o,
+        None => return,
+    }));
+}
+
+impl<A: ExecutionObserver, B: ExecutionObserver> ExecutionObserver for (A, B) {
+    forward!(self => self.0, self.1);
+}
+
+impl<O: ExecutionObserver + ?Sized> ExecutionObserver for &mut O {
+    forward!(self => (**self));
+}
+
+impl<O: ExecutionObserver + ?Sized> ExecutionObserver for Box<O> {
+    forward!(self => (**self));
+}
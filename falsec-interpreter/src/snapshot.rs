@@ -0,0 +1,90 @@
+use crate::{StackFrame, StackValue};
+use falsec_types::source::Program;
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+
+/// The state of an interpreter in between two commands, see [crate::Interpreter::snapshot] and
+/// [crate::Interpreter::restore].
+///
+/// The input and output are not part of the snapshot: a restored program continues reading
+/// wherever its new input starts.
+#[derive(Clone, Eq, PartialEq, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Snapshot {
+    /// The [program_hash] of the program the snapshot was taken of.
+    pub program_hash: u64,
+    /// The data stack, bottom first.
+    pub data_stack: Vec<StackValue>,
+    /// Callers of the current frame, outermost first.
+    pub call_stack: Vec<StackFrame>,
+    /// The frame of the lambda that is executing, with the program counter of the next command.
+    pub frame: StackFrame,
+    pub variables: HashMap<char, StackValue>,
+    /// The variable each lambda was most recently stored in, to name it in backtraces.
+    pub lambda_names: HashMap<u64, char>,
+    /// Number of commands executed so far, for [falsec_types::Limits::max_instructions].
+    pub instructions: u64,
+    /// Number of bytes written so far, for [falsec_types::Limits::max_output_bytes].
+    pub output_bytes: u64,
+}
+
+#[derive(Clone, Eq, PartialEq, Debug)]
+pub enum SnapshotError {
+    /// The snapshot was taken of another program.
+    ProgramMismatch { expected: u64, found: u64 },
+    /// A frame refers to a lambda or command that does not exist.
+    InvalidFrame(StackFrame),
+}
+
+impl fmt::Display for SnapshotError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SnapshotError::ProgramMismatch { expected, found } => write!(
+                f,
+                "The snapshot was taken of another program (hash {found:016x}, expected {expected:016x})"
+            ),
+            SnapshotError::InvalidFrame(frame) => write!(
+                f,
+                "The snapshot refers to command {} of lambda {}, which does not exist",
+                frame.program_counter, frame.lambda_id
+            ),
+        }
+    }
+}
+
+impl Error for SnapshotError {}
+
+/// Identifies a program, so that snapshots are only restored into the program they were taken
+/// of. The hash is the same on every machine, but may change between versions of falsec.
+pub fn program_hash(program: &Program) -> u64 {
+    // FNV-1a, as the std hashers are not guaranteed to be stable.
+    fn write(hash: &mut u64, bytes: &[u8]) {
+        for byte in bytes {
+            *hash = (*hash ^ *byte as u64).wrapping_mul(0x100000001b3);
+        }
+    }
+
+    let mut hash = 0xcbf29ce484222325;
+    write(&mut hash, &program.main_id.to_le_bytes());
+    let mut lambdas: Vec<_> = program.lambdas.iter().collect();
+    lambdas.sort_by_key(|(id, _)| **id);
+    for (id, lambda) in lambdas {
+        write(&mut hash, &id.to_le_bytes());
+        write(&mut hash, &(lambda.len() as u64).to_le_bytes());
+        for (command, span) in lambda {
+            let command = format!("{command:?}");
+            write(&mut hash, &(command.len() as u64).to_le_bytes());
+            write(&mut hash, command.as_bytes());
+            write(&mut hash, &(span.start.offset as u64).to_le_bytes());
+        }
+    }
+    let mut strings: Vec<_> = program.strings.iter().collect();
+    strings.sort_by_key(|(id, _)| **id);
+    for (id, string) in strings {
+        write(&mut hash, &id.to_le_bytes());
+        write(&mut hash, &(string.len() as u64).to_le_bytes());
+        write(&mut hash, string.as_bytes());
+    }
+    hash
+}
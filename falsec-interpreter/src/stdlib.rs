@@ -0,0 +1,146 @@
+//! The standard builtins from [falsec_types::builtins].
+
+use crate::builtins::{BuiltinContext, Builtins};
+use crate::error::InterpreterError;
+use falsec_types::builtins::{ARG, ARGC, CLOSE, EXIT, GETENV, OPEN, READ, WRITE};
+use std::collections::HashMap;
+use std::ffi::OsString;
+use std::fs::{File, OpenOptions};
+use std::io::{BufReader, BufWriter, ErrorKind, Read, Write};
+use std::sync::{Arc, Mutex};
+
+enum Handle {
+    Read(BufReader<File>),
+    Write(BufWriter<File>),
+}
+
+/// Files opened by the program, by file descriptor.
+#[derive(Default)]
+struct Files(HashMap<i64, Handle>);
+
+impl Files {
+    fn get(&mut self, ctx: &BuiltinContext, fd: i64) -> Result<&mut Handle, InterpreterError> {
+        self.0
+            .get_mut(&fd)
+            .ok_or_else(|| ctx.error(format!("Invalid file descriptor: {fd}")))
+    }
+}
+
+/// Register the standard builtins for a program that is run with `args`.
+pub fn register_standard_builtins(builtins: &mut Builtins, args: Vec<OsString>) {
+    let args: Vec<_> = args.into_iter().map(OsString::into_encoded_bytes).collect();
+    let files = Arc::new(Mutex::new(Files::default()));
+
+    let argc = args.len() as i64;
+    builtins.register(ARGC, move |ctx| ctx.push_integer(argc));
+    builtins.register(ARG, move |ctx| {
+        let i = ctx.pop_integer()?;
+        match usize::try_from(i).ok().and_then(|i| args.get(i)) {
+            Some(arg) => push_string(ctx, arg),
+            None => ctx.push_integer(-1),
+        }
+    });
+    builtins.register(GETENV, |ctx| {
+        let name = pop_string(ctx)?;
+        match std::env::var_os(String::from_utf8_lossy(&name).as_ref()) {
+            Some(value) => push_string(ctx, value.as_encoded_bytes()),
+            None => ctx.push_integer(-1),
+        }
+    });
+    let f = files.clone();
+    builtins.register(OPEN, move |ctx| {
+        let mode = ctx.pop_integer()?;
+        let path = String::from_utf8_lossy(&pop_string(ctx)?).into_owned();
+        let file = match mode {
+            0 => File::open(path).map(|f| Handle::Read(BufReader::new(f))),
+            1 | 2 => OpenOptions::new()
+                .create(true)
+                .write(true)
+                .truncate(mode == 1)
+                .append(mode == 2)
+                .open(path)
+                .map(|f| Handle::Write(BufWriter::new(f))),
+            _ => return Err(ctx.error(format!("Invalid file mode: {mode}"))),
+        };
+        let Ok(file) = file else {
+            return ctx.push_integer(-1);
+        };
+        let mut files = f.lock().unwrap();
+        let fd = (3..).find(|fd| !files.0.contains_key(fd)).unwrap();
+        files.0.insert(fd, file);
+        ctx.push_integer(fd)
+    });
+    let f = files.clone();
+    builtins.register(CLOSE, move |ctx| {
+        let fd = ctx.pop_integer()?;
+        match f.lock().unwrap().0.remove(&fd) {
+            Some(Handle::Write(mut file)) => file.flush().map_err(|e| ctx.io_error(e)),
+            Some(Handle::Read(_)) => Ok(()),
+            None => Err(ctx.error(format!("Invalid file descriptor: {fd}"))),
+        }
+    });
+    let f = files.clone();
+    builtins.register(READ, move |ctx| {
+        let fd = ctx.pop_integer()?;
+        let byte = match fd {
+            0 => ctx.read_byte()?,
+            fd => match f.lock().unwrap().get(ctx, fd)? {
+                Handle::Read(file) => {
+                    let mut buf = [0];
+                    match file.read_exact(&mut buf) {
+                        Ok(()) => Some(buf[0]),
+                        Err(e) if e.kind() == ErrorKind::UnexpectedEof => None,
+                        Err(e) => return Err(ctx.io_error(e)),
+                    }
+                }
+                Handle::Write(_) => {
+                    return Err(ctx.error(format!("File descriptor {fd} is not readable")));
+                }
+            },
+        };
+        ctx.push_integer(byte.map_or(-1, i64::from))
+    });
+    builtins.register(WRITE, move |ctx| {
+        let fd = ctx.pop_integer()?;
+        let byte = [ctx.pop_integer()? as u8];
+        match fd {
+            1 => ctx.write(&byte),
+            2 => {
+                // keep the order of messages that go to the same terminal.
+                ctx.flush()?;
+                std::io::stderr()
+                    .write_all(&byte)
+                    .map_err(|e| ctx.io_error(e))
+            }
+            fd => match files.lock().unwrap().get(ctx, fd)? {
+                Handle::Write(file) => file.write_all(&byte).map_err(|e| ctx.io_error(e)),
+                Handle::Read(_) => Err(ctx.error(format!("File descriptor {fd} is not writable"))),
+            },
+        }
+    });
+    builtins.register(EXIT, |ctx| {
+        let status = ctx.pop_integer()?;
+        ctx.flush()?;
+        Err(ctx.exit(status))
+    });
+}
+
+/// Pop a string that is passed as its bytes followed by its length.
+fn pop_string(ctx: &mut BuiltinContext) -> Result<Vec<u8>, InterpreterError> {
+    let len = ctx.pop_integer()?;
+    if len < 0 || len as usize > ctx.data_stack().len() {
+        return Err(ctx.error(format!("Invalid string length: {len}")));
+    }
+    let mut bytes = (0..len)
+        .map(|_| ctx.pop_integer().map(|c| c as u8))
+        .collect::<Result<Vec<_>, _>>()?;
+    bytes.reverse();
+    Ok(bytes)
+}
+
+fn push_string(ctx: &mut BuiltinContext, bytes: &[u8]) -> Result<(), InterpreterError> {
+    for byte in bytes {
+        ctx.push_integer(*byte as i64)?;
+    }
+    ctx.push_integer(bytes.len() as i64)
+}
@@ -0,0 +1,1197 @@
+//! A faster alternative to [crate::Interpreter] that runs programs to completion.
+//!
+//! The program is lowered into flat bytecode first: lambdas are laid out one after another,
+//! calls of lambda literals like `[...]!` jump to their resolved address, variables live in an
+//! array, and common sequences of commands like `a;`, `1+` or `$@\\` are fused into one
+//! instruction. Loops of lambda literals get an inlined copy, which runs without calls. The
+//! [Vm] cannot be paused or observed, but otherwise behaves like the interpreter, including type
+//! safety, arithmetic modes, limits, cancellation and builtins.
+
+use crate::builtins::{BuiltinContext, Builtins};
+use crate::error::{InterpreterError, ProgramPos};
+use crate::{CancellationToken, StackValue, arithmetic, cast_integer, cast_var};
+use falsec_types::Config;
+use falsec_types::source::{Command, LambdaCommand, Program, Span};
+use std::collections::HashMap;
+use std::io::{ErrorKind, Read, Write};
+use std::time::Instant;
+
+/// How many instructions are executed between checks of the clock and the cancellation token.
+/// Without an instruction limit, this counts calls and loop iterations instead.
+const POLL_INTERVAL: u64 = 1024;
+
+/// How many values a [Shuffle] can take from the stack.
+const SHUFFLE_INPUTS: usize = 8;
+
+/// How deep loops are inlined into each other.
+const INLINE_DEPTH: usize = 4;
+
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+enum Op {
+    Push(i64),
+    PushLambda(u64),
+    PushVar(char),
+    Dup,
+    Drop,
+    Swap,
+    Rot,
+    Pick,
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Neg,
+    BitAnd,
+    BitOr,
+    BitNot,
+    Gt,
+    Eq,
+    Exec,
+    Conditional,
+    While,
+    Store,
+    Load,
+    ReadChar,
+    WriteChar,
+    /// Index into [Bytecode::strings].
+    WriteString(u32),
+    WriteInt,
+    Flush,
+    /// Return to the caller, or finish in the main lambda.
+    Return,
+    /// A lambda definition that the analyzer did not extract.
+    LambdaDefinition,
+    /// A variable name that is not in `a..=z`.
+    InvalidVar(char),
+    /// Call the builtin with this id. It is always followed by a `Return`.
+    Builtin(u64),
+    /// Continue at this address, after the commands of a superinstruction ran one by one.
+    Jump(u32),
+    /// The `#` at the end of an inlined loop, with the address of its body. It continues there,
+    /// or returns from the loop.
+    LoopTest(u32),
+
+    // superinstructions, each replaces the commands in its comment.
+    /// `a;`
+    LoadVar(char),
+    /// `a:`
+    StoreVar(char),
+    /// `1+`
+    AddConst(i64),
+    /// `1-`
+    SubConst(i64),
+    /// `[...]!`, with the address of the lambda.
+    Call(u32),
+    /// `[...]?`, with the address of the lambda.
+    CallIf(u32),
+    /// `=[...]?`, with the address of the lambda.
+    CallIfEq(u32),
+    /// `>[...]?`, with the address of the lambda.
+    CallIfGt(u32),
+    /// `[...][...]#`, with the addresses of the condition and the body.
+    Loop(u32, u32),
+    /// A [Op::Loop] with the address of its inlined copy, which runs without an instruction
+    /// limit.
+    InlineLoop(u32, u32, u32),
+    /// `$#` at the end of an inlined loop, with the address of its body.
+    LoopTestDup(u32),
+    /// A run of `$`, `%`, `\\` and `@`, with the index into [Bytecode::shuffles] and the number
+    /// of commands.
+    Shuffle(u32, u32),
+}
+
+impl Op {
+    /// The number of commands the instruction stands for, for [falsec_types::Limits].
+    fn commands(self) -> u64 {
+        if let Op::Shuffle(_, commands) = self {
+            return commands as u64;
+        }
+        match self {
+            Op::LoadVar(_) | Op::StoreVar(_) | Op::AddConst(_) | Op::SubConst(_) => 2,
+            Op::Call(_) | Op::CallIf(_) | Op::LoopTestDup(_) => 2,
+            Op::CallIfEq(_) | Op::CallIfGt(_) | Op::Loop(..) | Op::InlineLoop(..) => 3,
+            Op::Return | Op::Builtin(_) | Op::Jump(_) => 0,
+            _ => 1,
+        }
+    }
+}
+
+/// The effect of a run of stack commands, which only move values.
+struct Shuffle {
+    /// How many values it takes from the stack.
+    inputs: usize,
+    /// How many values more than at the start the stack holds at most in between.
+    peak: usize,
+    /// The values it pushes, as indices into its inputs, bottom first.
+    outputs: Box<[u8]>,
+}
+
+impl Shuffle {
+    /// The shuffle for the longest run of stack commands at the start of `commands`, and how
+    /// many commands it covers.
+    fn new(commands: &[&Command]) -> (Self, usize) {
+        // the stack bottom first, with the inputs numbered from the top.
+        let mut stack: Vec<usize> = Vec::new();
+        let mut inputs = 0;
+        let mut peak = 0;
+        let mut len = 0;
+        for command in commands {
+            let needs: usize = match command {
+                Command::Dup | Command::Drop => 1,
+                Command::Swap => 2,
+                Command::Rot => 3,
+                _ => break,
+            };
+            let missing = needs.saturating_sub(stack.len());
+            if inputs + missing > SHUFFLE_INPUTS {
+                break;
+            }
+            stack.splice(0..0, (inputs..inputs + missing).rev());
+            inputs += missing;
+            let top = stack.len() - 1;
+            match command {
+                Command::Dup => stack.push(stack[top]),
+                Command::Drop => _ = stack.pop(),
+                Command::Swap => stack.swap(top, top - 1),
+                _ => {
+                    let third = stack.remove(top - 2);
+                    stack.push(third);
+                }
+            }
+            // the values of the real stack, compared to the start.
+            peak = peak.max(stack.len().saturating_sub(inputs));
+            len += 1;
+        }
+        let outputs = stack.iter().map(|input| (inputs - 1 - input) as u8);
+        let shuffle = Shuffle {
+            inputs,
+            peak,
+            outputs: outputs.collect(),
+        };
+        (shuffle, len)
+    }
+}
+
+struct Bytecode {
+    code: Vec<Op>,
+    /// Where each instruction comes from. For superinstructions, this is their last command.
+    origins: Vec<ProgramPos>,
+    /// The address of the commands of each superinstruction as single instructions, by the
+    /// address of the superinstruction. They run instead when it reaches a limit or a check,
+    /// so that those happen at the same command as in the interpreter.
+    unfused: HashMap<u32, u32>,
+    shuffles: Vec<Shuffle>,
+    /// The address of each lambda with a small id, indexed by lambda id.
+    entries: Vec<Option<u32>>,
+    /// The address of the lambdas with larger ids, which would make [Bytecode::entries] huge.
+    sparse_entries: HashMap<u64, u32>,
+    /// The address of each builtin, by lambda id.
+    builtin_entries: HashMap<u64, u32>,
+    strings: Vec<Box<[u8]>>,
+    main_id: u64,
+}
+
+impl Bytecode {
+    fn compile(program: &Program) -> Self {
+        let mut ids: Vec<_> = program.lambdas.keys().copied().collect();
+        ids.sort_unstable();
+        // the analyzer numbers lambdas from 0, other programs may use any ids.
+        let dense = ids
+            .iter()
+            .take_while(|id| **id < 2 * ids.len() as u64 + 16)
+            .last()
+            .map_or(0, |id| *id as usize + 1);
+        let mut bytecode = Bytecode {
+            code: Vec::new(),
+            origins: Vec::new(),
+            unfused: HashMap::new(),
+            shuffles: Vec::new(),
+            entries: vec![None; dense],
+            sparse_entries: HashMap::new(),
+            builtin_entries: HashMap::new(),
+            strings: Vec::new(),
+            main_id: program.main_id,
+        };
+        let mut pending = Pending::default();
+        for id in ids {
+            let address = bytecode.code.len() as u32;
+            match bytecode.entries.get_mut(id as usize) {
+                Some(entry) => *entry = Some(address),
+                None => _ = bytecode.sparse_entries.insert(id, address),
+            }
+            bytecode.emit(program, id, &mut pending, &[]);
+            let lambda = &program.lambdas[&id];
+            let end = lambda
+                .last()
+                .map_or(Default::default(), |(_, span)| span.end);
+            bytecode.code.push(Op::Return);
+            bytecode.origins.push(ProgramPos {
+                pos: end,
+                program_counter: lambda.len(),
+                lambda_id: id,
+            });
+        }
+        // loops of literals get a copy of their body and then their condition, which ends with
+        // the test of the `#`, so that an iteration needs no calls and returns.
+        while let Some((address, inlined)) = pending.loops.pop() {
+            let Op::Loop(condition, body) = bytecode.code[address] else {
+                unreachable!("only loops are inlined");
+            };
+            if inlined.len() >= 2 * INLINE_DEPTH
+                || inlined.contains(&condition)
+                || inlined.contains(&body)
+            {
+                continue;
+            }
+            let inlined = [&inlined[..], &[condition, body]].concat();
+            let body_address = bytecode.code.len() as u32;
+            bytecode.emit(program, body as u64, &mut pending, &inlined);
+            let condition_address = bytecode.code.len() as u32;
+            bytecode.emit(program, condition as u64, &mut pending, &inlined);
+            let test = bytecode.origins[address];
+            let op = match bytecode.code.last() {
+                Some(Op::Dup) if bytecode.code.len() as u32 > condition_address => {
+                    bytecode.code.pop();
+                    let dup = bytecode.origins.pop().unwrap();
+                    let ops = vec![(Op::Dup, dup), (Op::LoopTest(body_address), test)];
+                    pending.fused.push((bytecode.code.len() as u32, ops));
+                    Op::LoopTestDup(body_address)
+                }
+                _ => Op::LoopTest(body_address),
+            };
+            bytecode.code.push(op);
+            bytecode.origins.push(test);
+            bytecode.code[address] = Op::InlineLoop(condition, body, condition_address);
+        }
+        let entry = |bytecode: &Bytecode, id: u32| bytecode.entry(id as u64).unwrap();
+        for i in pending.calls {
+            bytecode.code[i] = match bytecode.code[i] {
+                Op::Call(id) => Op::Call(entry(&bytecode, id)),
+                Op::CallIf(id) => Op::CallIf(entry(&bytecode, id)),
+                Op::CallIfEq(id) => Op::CallIfEq(entry(&bytecode, id)),
+                Op::CallIfGt(id) => Op::CallIfGt(entry(&bytecode, id)),
+                Op::Loop(c, b) => Op::Loop(entry(&bytecode, c), entry(&bytecode, b)),
+                Op::InlineLoop(c, b, copy) => {
+                    Op::InlineLoop(entry(&bytecode, c), entry(&bytecode, b), copy)
+                }
+                op => op,
+            };
+        }
+        for (address, ops) in pending.fused {
+            bytecode.unfused.insert(address, bytecode.code.len() as u32);
+            for (op, origin) in ops {
+                bytecode.code.push(op);
+                bytecode.origins.push(origin);
+            }
+            bytecode.code.push(Op::Jump(address + 1));
+            bytecode.origins.push(bytecode.origins[address as usize]);
+        }
+        bytecode
+    }
+
+    /// Emit the commands of a lambda, without the return at its end. `inlined` are the lambdas
+    /// of the loops that the code is a copy of.
+    fn emit(&mut self, program: &Program, id: u64, pending: &mut Pending, inlined: &[u32]) {
+        let commands: Vec<_> = program.lambdas[&id]
+            .iter()
+            .enumerate()
+            .filter(|(_, (command, _))| !matches!(command, Command::Comment(_)))
+            .collect();
+        let origin = |(pc, (_, span)): (usize, &(Command, Span))| ProgramPos {
+            pos: span.start,
+            program_counter: pc,
+            lambda_id: id,
+        };
+        let mut i = 0;
+        while i < commands.len() {
+            let (op, len) = self.fuse(program, &commands[i..]);
+            let address = self.code.len() as u32;
+            if matches!(
+                op,
+                Op::Call(_) | Op::CallIf(_) | Op::CallIfEq(_) | Op::CallIfGt(_) | Op::Loop(..)
+            ) {
+                pending.calls.push(address as usize);
+            }
+            if let Op::Loop(..) = op {
+                pending.loops.push((address as usize, inlined.to_vec()));
+            }
+            if len > 1 {
+                let mut ops = Vec::with_capacity(len);
+                for command in &commands[i..i + len] {
+                    ops.push((self.op(&command.1.0), origin(*command)));
+                }
+                pending.fused.push((address, ops));
+            }
+            self.code.push(op);
+            self.origins.push(origin(commands[i + len - 1]));
+            i += len;
+        }
+    }
+
+    /// The address of a lambda.
+    fn entry(&self, id: u64) -> Option<u32> {
+        match self.entries.get(id as usize) {
+            Some(entry) => *entry,
+            None => self.sparse_entries.get(&id).copied(),
+        }
+    }
+
+    /// The instruction for the start of `commands`, and how many commands it covers.
+    fn fuse(&mut self, program: &Program, commands: &[(usize, &(Command, Span))]) -> (Op, usize) {
+        let command = |i: usize| commands.get(i).map(|(_, (command, _))| command);
+        // only lambdas with a small id that exist can be called directly.
+        let literal = |i: usize| match command(i) {
+            Some(Command::Lambda(LambdaCommand::LambdaReference(id)))
+                if program.lambdas.contains_key(id) && *id <= u32::MAX as u64 =>
+            {
+                Some(*id as u32)
+            }
+            _ => None,
+        };
+        let int = |i: usize| match command(i) {
+            Some(Command::IntLiteral(n)) => Some(*n as i64),
+            Some(Command::CharLiteral(c)) => Some(*c as i64),
+            _ => None,
+        };
+        match (command(0).unwrap(), command(1), command(2)) {
+            (Command::Var(c), _, _) if !c.is_ascii_lowercase() => return (Op::InvalidVar(*c), 1),
+            (Command::Var(c), Some(Command::Load), _) => return (Op::LoadVar(*c), 2),
+            (Command::Var(c), Some(Command::Store), _) => return (Op::StoreVar(*c), 2),
+            (_, Some(Command::Add), _) if int(0).is_some() => {
+                return (Op::AddConst(int(0).unwrap()), 2);
+            }
+            (_, Some(Command::Sub), _) if int(0).is_some() => {
+                return (Op::SubConst(int(0).unwrap()), 2);
+            }
+            (_, Some(Command::Exec), _) if literal(0).is_some() => {
+                return (Op::Call(literal(0).unwrap()), 2);
+            }
+            (_, Some(Command::Conditional), _) if literal(0).is_some() => {
+                return (Op::CallIf(literal(0).unwrap()), 2);
+            }
+            (Command::Eq, _, Some(Command::Conditional)) if literal(1).is_some() => {
+                return (Op::CallIfEq(literal(1).unwrap()), 3);
+            }
+            (Command::Gt, _, Some(Command::Conditional)) if literal(1).is_some() => {
+                return (Op::CallIfGt(literal(1).unwrap()), 3);
+            }
+            (_, _, Some(Command::While)) if literal(0).is_some() && literal(1).is_some() => {
+                return (Op::Loop(literal(0).unwrap(), literal(1).unwrap()), 3);
+            }
+            (Command::Dup | Command::Drop | Command::Swap | Command::Rot, Some(_), _) => {
+                let commands: Vec<_> = commands.iter().map(|(_, (command, _))| command).collect();
+                let (shuffle, len) = Shuffle::new(&commands);
+                if len > 1 {
+                    self.shuffles.push(shuffle);
+                    return (Op::Shuffle(self.shuffles.len() as u32 - 1, len as u32), len);
+                }
+            }
+            _ => (),
+        }
+        (self.op(command(0).unwrap()), 1)
+    }
+
+    /// The instruction for a single command.
+    fn op(&mut self, command: &Command) -> Op {
+        match command {
+            Command::IntLiteral(n) => Op::Push(*n as i64),
+            Command::CharLiteral(c) => Op::Push(*c as i64),
+            Command::Dup => Op::Dup,
+            Command::Drop => Op::Drop,
+            Command::Swap => Op::Swap,
+            Command::Rot => Op::Rot,
+            Command::Pick => Op::Pick,
+            Command::Add => Op::Add,
+            Command::Sub => Op::Sub,
+            Command::Mul => Op::Mul,
+            Command::Div => Op::Div,
+            Command::Neg => Op::Neg,
+            Command::BitAnd => Op::BitAnd,
+            Command::BitOr => Op::BitOr,
+            Command::BitNot => Op::BitNot,
+            Command::Gt => Op::Gt,
+            Command::Eq => Op::Eq,
+            Command::Lambda(LambdaCommand::LambdaReference(id)) => Op::PushLambda(*id),
+            Command::Lambda(LambdaCommand::LambdaDefinition(_)) => Op::LambdaDefinition,
+            Command::Exec => Op::Exec,
+            Command::Conditional => Op::Conditional,
+            Command::While => Op::While,
+            // invalid names fail like in the interpreter, before they reach a variable slot.
+            Command::Var(c) if !c.is_ascii_lowercase() => Op::InvalidVar(*c),
+            Command::Var(c) => Op::PushVar(*c),
+            Command::Store => Op::Store,
+            Command::Load => Op::Load,
+            Command::ReadChar => Op::ReadChar,
+            Command::WriteChar => Op::WriteChar,
+            Command::StringLiteral(s) => {
+                self.strings.push(s.as_bytes().into());
+                Op::WriteString(self.strings.len() as u32 - 1)
+            }
+            Command::WriteInt => Op::WriteInt,
+            Command::Flush => Op::Flush,
+            Command::Comment(_) => unreachable!("comments are skipped"),
+        }
+    }
+}
+
+/// What is left to do once all lambdas are emitted.
+#[derive(Default)]
+struct Pending {
+    /// Calls of literals, which are emitted with the lambda id until all lambdas have their
+    /// address.
+    calls: Vec<usize>,
+    /// Superinstructions, with the single instructions for their commands.
+    fused: Vec<(u32, Vec<(Op, ProgramPos)>)>,
+    /// Loops to inline, with the lambdas of the loops they are inlined in.
+    loops: Vec<(usize, Vec<u32>)>,
+}
+
+#[derive(Copy, Clone, Debug)]
+enum Frame {
+    /// Return address
+    Call(u32),
+    /// (return address, condition, body)
+    LoopCondition(u32, u32, u32),
+    /// (return address, condition, body)
+    LoopBody(u32, u32, u32),
+}
+
+impl Frame {
+    fn return_to(self) -> u32 {
+        match self {
+            Frame::Call(pc) | Frame::LoopCondition(pc, ..) | Frame::LoopBody(pc, ..) => pc,
+        }
+    }
+}
+
+/// Runs a program compiled to bytecode. See the [module documentation](self).
+pub struct Vm<Input: Read, Output: Write> {
+    input: Input,
+    output: Output,
+    bytecode: Bytecode,
+    config: Config,
+    cancellation: CancellationToken,
+    builtins: Builtins,
+    data_stack: Vec<StackValue>,
+    /// Indexed by the variable name minus `'a'`.
+    variables: [StackValue; 32],
+    /// The variable each lambda was most recently stored in.
+    lambda_names: HashMap<u64, char>,
+    frames: Vec<Frame>,
+    instructions: u64,
+    output_bytes: u64,
+}
+
+impl<Input: Read, Output: Write> Vm<Input, Output> {
+    pub fn new(input: Input, output: Output, program: &Program, config: Config) -> Self {
+        Self {
+            input,
+            output,
+            bytecode: Bytecode::compile(program),
+            config,
+            cancellation: CancellationToken::default(),
+            builtins: Builtins::default(),
+            data_stack: Vec::new(),
+            variables: [StackValue::Integer(0); 32],
+            lambda_names: HashMap::new(),
+            frames: Vec::new(),
+            instructions: 0,
+            output_bytes: 0,
+        }
+    }
+
+    /// A token that stops this VM when cancelled. It is checked every 1024 commands, or every
+    /// 1024 calls and loop iterations without an instruction limit.
+    pub fn cancellation_token(&self) -> CancellationToken {
+        self.cancellation.clone()
+    }
+
+    pub fn set_cancellation_token(&mut self, token: CancellationToken) {
+        self.cancellation = token;
+    }
+
+    /// The data stack, bottom first.
+    pub fn data_stack(&self) -> &[StackValue] {
+        &self.data_stack
+    }
+
+    /// The variable each lambda was most recently stored in, to name it in backtraces.
+    pub fn lambda_names(&self) -> &HashMap<u64, char> {
+        &self.lambda_names
+    }
+
+    pub fn builtins(&self) -> &Builtins {
+        &self.builtins
+    }
+
+    pub fn builtins_mut(&mut self) -> &mut Builtins {
+        &mut self.builtins
+    }
+
+    /// Make `builtin` callable from FALSE code, see [Builtins::register].
+    pub fn register_builtin(
+        &mut self,
+        id: i64,
+        builtin: impl FnMut(&mut BuiltinContext) -> Result<(), InterpreterError> + Send + 'static,
+    ) {
+        self.builtins.register(id, builtin);
+    }
+
+    /// Run the program to completion.
+    pub fn run(&mut self) -> Result<(), InterpreterError> {
+        // builtins are called like lambdas, through a short stub at the end of the code.
+        for id in self.builtins.ids() {
+            let bytecode = &mut self.bytecode;
+            if let std::collections::hash_map::Entry::Vacant(entry) =
+                bytecode.builtin_entries.entry(id as u64)
+            {
+                entry.insert(bytecode.code.len() as u32);
+                bytecode.code.extend([Op::Builtin(id as u64), Op::Return]);
+                bytecode.origins.extend([ProgramPos::default(); 2]);
+            }
+        }
+        let main_id = self.bytecode.main_id;
+        let Some(main) = self.bytecode.entry(main_id) else {
+            return Err(InterpreterError::invalid_lambda_reference(
+                vec![ProgramPos {
+                    lambda_id: main_id,
+                    ..Default::default()
+                }],
+                main_id,
+            ));
+        };
+        self.execute(main as usize)
+    }
+
+    fn execute(&mut self, pc: usize) -> Result<(), InterpreterError> {
+        // the code does not change while running, and is kept apart so that it can stay in
+        // registers.
+        let code = std::mem::take(&mut self.bytecode.code);
+        let mut stack = std::mem::take(&mut self.data_stack);
+        let mut instructions = self.instructions;
+        // counting every command is only needed to stop at an instruction limit.
+        let result = match self.config.limits.max_instructions {
+            Some(_) => self.execute_on::<true>(&code, &mut stack, &mut instructions, pc),
+            None => self.execute_on::<false>(&code, &mut stack, &mut instructions, pc),
+        };
+        self.bytecode.code = code;
+        self.data_stack = stack;
+        self.instructions = instructions;
+        result
+    }
+
+    fn execute_on<const COUNTED: bool>(
+        &mut self,
+        code: &[Op],
+        stack: &mut Vec<StackValue>,
+        instructions: &mut u64,
+        mut pc: usize,
+    ) -> Result<(), InterpreterError> {
+        let type_safety = self.config.type_safety;
+        let mode = self.config.arithmetic;
+        let limits = self.config.limits;
+        let max_stack = limits.max_stack_depth.unwrap_or(usize::MAX);
+        let max_calls = limits.max_call_depth.unwrap_or(usize::MAX);
+        let started = Instant::now();
+        // the slow checks for limits, the timeout and cancellation run once this is reached.
+        let mut next_check = 0;
+        let mut count = *instructions;
+        macro_rules! leave {
+            ($result:expr) => {{
+                *instructions = count;
+                return $result;
+            }};
+        }
+        macro_rules! check {
+            ($pc:expr) => {
+                match self.check($pc, started, stack, count) {
+                    Ok(next) => next,
+                    Err(e) => leave!(Err(e)),
+                }
+            };
+        }
+        let counts: Vec<u64> = match COUNTED {
+            true => code.iter().map(|op| op.commands()).collect(),
+            false => Vec::new(),
+        };
+
+        macro_rules! fail {
+            ($e:ident($($arg:expr),*)) => {{
+                let error = |backtrace| InterpreterError::$e(backtrace, $($arg),*);
+                leave!(Err(self.error(pc - 1, error)));
+            }};
+        }
+        // run the commands of the current superinstruction one by one instead.
+        macro_rules! unfuse {
+            () => {{
+                if COUNTED {
+                    count -= code[pc - 1].commands();
+                }
+                pc = self.bytecode.unfused[&(pc as u32 - 1)] as usize;
+                continue;
+            }};
+        }
+        macro_rules! pop {
+            () => {
+                match stack.pop() {
+                    Some(value) => value,
+                    None => fail!(tried_to_pop_from_empty_data_stack()),
+                }
+            };
+        }
+        macro_rules! cast {
+            ($cast:ident($value:expr)) => {
+                match $cast($value, type_safety) {
+                    Ok(value) => value,
+                    Err((from, to)) => fail!(type_cast_error(from, to)),
+                }
+            };
+        }
+        macro_rules! pop_callee {
+            () => {
+                match self.builtins.callee(pop!(), type_safety) {
+                    Ok(id) => id,
+                    Err((from, to)) => fail!(type_cast_error(from, to)),
+                }
+            };
+        }
+        macro_rules! pop_int {
+            () => {
+                match pop!() {
+                    StackValue::Integer(i) => i,
+                    value => cast!(cast_integer(value)),
+                }
+            };
+        }
+        macro_rules! push {
+            ($value:expr) => {{
+                let value = $value;
+                if stack.len() >= max_stack {
+                    fail!(stack_limit_exceeded(max_stack));
+                }
+                stack.push(value);
+            }};
+        }
+        // the values that the first commands of a superinstruction push, and it pops again.
+        macro_rules! pushes {
+            ($n:expr) => {
+                if stack.len() + $n > max_stack {
+                    unfuse!();
+                }
+            };
+        }
+        macro_rules! arithmetic {
+            (
+                $op:literal,
+                $a:ident,
+                $b:ident,
+                $checked:ident,
+                $wrapping:ident,
+                $saturating:ident
+            ) => {
+                match arithmetic(
+                    mode,
+                    || $b.$checked($a),
+                    || $b.$wrapping($a),
+                    || $b.$saturating($a),
+                ) {
+                    Some(result) => push!(StackValue::Integer(result)),
+                    None => fail!(integer_overflow($op)),
+                }
+            };
+        }
+        macro_rules! binop {
+            (|$a:ident, $b:ident| $result:expr) => {{
+                let $a = pop_int!();
+                let $b = pop_int!();
+                push!(StackValue::Integer($result));
+            }};
+        }
+        macro_rules! entry {
+            ($id:expr) => {{
+                let id = $id;
+                match self.bytecode.entry(id) {
+                    Some(entry) => entry,
+                    None => match self.bytecode.builtin_entries.get(&id) {
+                        Some(entry) if self.builtins.contains(id) => *entry,
+                        _ => fail!(invalid_lambda_reference(id)),
+                    },
+                }
+            }};
+        }
+        macro_rules! call {
+            ($frame:expr, $target:expr) => {{
+                let target = $target as usize;
+                if !COUNTED {
+                    count += 1;
+                    if count >= next_check {
+                        next_check = check!(pc - 1);
+                    }
+                }
+                // like in the interpreter, builtins do not count as calls.
+                if self.frames.len() >= max_calls && !matches!(code[target], Op::Builtin(_)) {
+                    fail!(call_depth_limit_exceeded(max_calls));
+                }
+                self.frames.push($frame);
+                pc = target;
+            }};
+        }
+        macro_rules! write {
+            ($bytes:expr) => {{
+                let bytes: &[u8] = $bytes;
+                if let Some(limit) = limits.max_output_bytes
+                    && self.output_bytes + bytes.len() as u64 > limit
+                {
+                    fail!(output_limit_exceeded(limit));
+                }
+                self.output_bytes += bytes.len() as u64;
+                if let Err(e) = self.output.write_all(bytes) {
+                    fail!(io_error(e));
+                }
+            }};
+        }
+
+        loop {
+            let op = code[pc];
+            pc += 1;
+            if COUNTED {
+                count += counts[pc - 1];
+                if count >= next_check {
+                    if op.commands() > 1 {
+                        unfuse!();
+                    }
+                    next_check = check!(pc - 1);
+                }
+            }
+            match op {
+                Op::Push(i) => push!(StackValue::Integer(i)),
+                Op::PushLambda(id) => push!(StackValue::Lambda(id)),
+                Op::PushVar(c) => push!(StackValue::Var(c)),
+                Op::Dup => match stack.last() {
+                    Some(value) => push!(*value),
+                    None => fail!(tried_to_pop_from_empty_data_stack()),
+                },
+                Op::Drop => _ = pop!(),
+                Op::Swap => {
+                    let a = pop!();
+                    let b = pop!();
+                    push!(a);
+                    push!(b);
+                }
+                Op::Rot => {
+                    let a = pop!();
+                    let b = pop!();
+                    let c = pop!();
+                    push!(b);
+                    push!(a);
+                    push!(c);
+                }
+                Op::Pick => {
+                    let index = pop_int!();
+                    let len = stack.len();
+                    if index < 0 || index as usize >= len {
+                        fail!(index_out_of_bounds(index, len));
+                    }
+                    push!(stack[len - index as usize - 1]);
+                }
+                Op::Add => {
+                    let a = pop_int!();
+                    let b = pop_int!();
+                    arithmetic!("addition", a, b, checked_add, wrapping_add, saturating_add);
+                }
+                Op::Sub => {
+                    let a = pop_int!();
+                    let b = pop_int!();
+                    arithmetic!(
+                        "subtraction",
+                        a,
+                        b,
+                        checked_sub,
+                        wrapping_sub,
+                        saturating_sub
+                    );
+                }
+                Op::Mul => {
+                    let a = pop_int!();
+                    let b = pop_int!();
+                    arithmetic!(
+                        "multiplication",
+                        a,
+                        b,
+                        checked_mul,
+                        wrapping_mul,
+                        saturating_mul
+                    );
+                }
+                Op::Div => {
+                    let a = pop_int!();
+                    let b = pop_int!();
+                    if a == 0 {
+                        fail!(division_by_zero());
+                    }
+                    arithmetic!("division", a, b, checked_div, wrapping_div, saturating_div);
+                }
+                Op::Neg => {
+                    let a = pop_int!();
+                    match arithmetic(
+                        mode,
+                        || a.checked_neg(),
+                        || a.wrapping_neg(),
+                        || a.saturating_neg(),
+                    ) {
+                        Some(result) => push!(StackValue::Integer(result)),
+                        None => fail!(integer_overflow("negation")),
+                    }
+                }
+                Op::BitAnd => binop!(|a, b| a & b),
+                Op::BitOr => binop!(|a, b| a | b),
+                Op::BitNot => {
+                    let a = pop_int!();
+                    push!(StackValue::Integer(!a));
+                }
+                Op::Gt => binop!(|a, b| if b > a { -1 } else { 0 }),
+                Op::Eq => binop!(|a, b| if b == a { -1 } else { 0 }),
+                Op::Exec => {
+                    let id = pop_callee!();
+                    call!(Frame::Call(pc as u32), entry!(id));
+                }
+                Op::Conditional => {
+                    let id = pop_callee!();
+                    if pop_int!() != 0 {
+                        call!(Frame::Call(pc as u32), entry!(id));
+                    }
+                }
+                Op::While => {
+                    let body = pop_callee!();
+                    let condition = pop_callee!();
+                    let condition = entry!(condition);
+                    let body = entry!(body);
+                    call!(Frame::LoopCondition(pc as u32, condition, body), condition);
+                }
+                Op::Store => {
+                    let var = cast!(cast_var(pop!()));
+                    let value = pop!();
+                    if let StackValue::Lambda(id) = value {
+                        self.lambda_names.insert(id, var);
+                    }
+                    self.variables[var_index(var)] = value;
+                }
+                Op::Load => {
+                    let var = cast!(cast_var(pop!()));
+                    push!(self.variables[var_index(var)]);
+                }
+                Op::ReadChar => {
+                    let mut buf = [0];
+                    match self.input.read_exact(&mut buf) {
+                        Ok(()) => push!(StackValue::Integer(buf[0] as i64)),
+                        Err(e) if e.kind() == ErrorKind::UnexpectedEof => {
+                            push!(StackValue::Integer(-1))
+                        }
+                        Err(e) => fail!(io_error(e)),
+                    }
+                }
+                Op::WriteChar => {
+                    let c = pop_int!();
+                    write!(&[c as u8]);
+                }
+                Op::WriteString(i) => write!(&self.bytecode.strings[i as usize]),
+                Op::WriteInt => {
+                    let i = pop_int!().to_string();
+                    write!(i.as_bytes());
+                }
+                Op::Flush => {
+                    if let Err(e) = self.output.flush() {
+                        fail!(io_error(e));
+                    }
+                }
+                Op::Return => {
+                    // a loop that goes on without a check keeps its frame, and only changes its
+                    // kind.
+                    if count + 1 < next_check && self.frames.len() <= max_calls {
+                        match (self.frames.last_mut(), stack.last()) {
+                            (
+                                Some(frame @ &mut Frame::LoopCondition(return_to, condition, body)),
+                                Some(&StackValue::Integer(i)),
+                            ) => {
+                                count += 1;
+                                stack.pop();
+                                if i != 0 {
+                                    *frame = Frame::LoopBody(return_to, condition, body);
+                                    pc = body as usize;
+                                } else {
+                                    self.frames.pop();
+                                    pc = return_to as usize;
+                                }
+                                continue;
+                            }
+                            (Some(frame @ &mut Frame::LoopBody(return_to, condition, body)), _) => {
+                                count += 1;
+                                *frame = Frame::LoopCondition(return_to, condition, body);
+                                pc = condition as usize;
+                                continue;
+                            }
+                            _ => (),
+                        }
+                    }
+                    match self.frames.pop() {
+                        None => leave!(Ok(())),
+                        Some(Frame::Call(return_to)) => pc = return_to as usize,
+                        Some(Frame::LoopCondition(return_to, condition, body)) => {
+                            // the `#` is executed again, and reports errors at its position.
+                            pc = return_to as usize;
+                            count += 1;
+                            if count >= next_check {
+                                next_check = check!(pc - 1);
+                            }
+                            if pop_int!() != 0 {
+                                call!(Frame::LoopBody(return_to, condition, body), body);
+                            }
+                        }
+                        Some(Frame::LoopBody(return_to, condition, body)) => {
+                            pc = return_to as usize;
+                            count += 1;
+                            if count >= next_check {
+                                next_check = check!(pc - 1);
+                            }
+                            call!(Frame::LoopCondition(return_to, condition, body), condition);
+                        }
+                    }
+                }
+                Op::LambdaDefinition => fail!(lambda_definition_not_allowed()),
+                Op::InvalidVar(c) => fail!(invalid_variable_name(c)),
+                Op::Builtin(id) => {
+                    if let Err(e) = self.call_builtin(id, stack) {
+                        leave!(Err(e));
+                    }
+                }
+                Op::Jump(address) => pc = address as usize,
+                Op::LoopTest(body) => {
+                    count += 1;
+                    match stack.last() {
+                        Some(&StackValue::Integer(i)) if count < next_check => {
+                            stack.pop();
+                            match i {
+                                0 => pc = self.frames.pop().unwrap().return_to() as usize,
+                                _ => pc = body as usize,
+                            }
+                        }
+                        _ => {
+                            // like at the end of a condition, the `#` checks and fails outside
+                            // of the loop.
+                            let frame = self.frames.pop().unwrap();
+                            pc = frame.return_to() as usize;
+                            if count >= next_check {
+                                next_check = check!(pc - 1);
+                            }
+                            if pop_int!() != 0 {
+                                call!(frame, body);
+                            }
+                        }
+                    }
+                }
+                Op::LoadVar(c) => {
+                    pushes!(1);
+                    stack.push(self.variables[var_index(c)]);
+                }
+                Op::StoreVar(c) => {
+                    pushes!(1);
+                    let value = pop!();
+                    if let StackValue::Lambda(id) = value {
+                        self.lambda_names.insert(id, c);
+                    }
+                    self.variables[var_index(c)] = value;
+                }
+                Op::AddConst(a) => {
+                    pushes!(1);
+                    let b = pop_int!();
+                    arithmetic!("addition", a, b, checked_add, wrapping_add, saturating_add);
+                }
+                Op::SubConst(a) => {
+                    pushes!(1);
+                    let b = pop_int!();
+                    arithmetic!(
+                        "subtraction",
+                        a,
+                        b,
+                        checked_sub,
+                        wrapping_sub,
+                        saturating_sub
+                    );
+                }
+                Op::Call(target) => {
+                    pushes!(1);
+                    call!(Frame::Call(pc as u32), target);
+                }
+                Op::CallIf(target) => {
+                    pushes!(1);
+                    if pop_int!() != 0 {
+                        call!(Frame::Call(pc as u32), target);
+                    }
+                }
+                // the comparison needs integers, other values fail or are cast by the commands
+                // one by one.
+                Op::CallIfEq(target) => {
+                    let [.., StackValue::Integer(b), StackValue::Integer(a)] = stack[..] else {
+                        unfuse!();
+                    };
+                    stack.truncate(stack.len() - 2);
+                    if b == a {
+                        call!(Frame::Call(pc as u32), target);
+                    }
+                }
+                Op::CallIfGt(target) => {
+                    let [.., StackValue::Integer(b), StackValue::Integer(a)] = stack[..] else {
+                        unfuse!();
+                    };
+                    stack.truncate(stack.len() - 2);
+                    if b > a {
+                        call!(Frame::Call(pc as u32), target);
+                    }
+                }
+                Op::Loop(condition, body) => {
+                    pushes!(2);
+                    call!(Frame::LoopCondition(pc as u32, condition, body), condition);
+                }
+                Op::InlineLoop(condition, body, copy) => {
+                    pushes!(2);
+                    // with an instruction limit, the commands are counted where they are.
+                    match COUNTED {
+                        true => {
+                            let frame = Frame::LoopCondition(pc as u32, condition, body);
+                            call!(frame, condition);
+                        }
+                        false => call!(Frame::Call(pc as u32), copy),
+                    }
+                }
+                Op::LoopTestDup(body) => {
+                    pushes!(1);
+                    count += 1;
+                    match stack.last() {
+                        Some(&StackValue::Integer(i)) if count < next_check => match i {
+                            0 => pc = self.frames.pop().unwrap().return_to() as usize,
+                            _ => pc = body as usize,
+                        },
+                        _ => unfuse!(),
+                    }
+                }
+                Op::Shuffle(shuffle, _) => {
+                    let shuffle = &self.bytecode.shuffles[shuffle as usize];
+                    let len = stack.len();
+                    if len < shuffle.inputs || len + shuffle.peak > max_stack {
+                        unfuse!();
+                    }
+                    let mut inputs = [StackValue::Integer(0); SHUFFLE_INPUTS];
+                    let start = len - shuffle.inputs;
+                    inputs[..shuffle.inputs].copy_from_slice(&stack[start..]);
+                    stack.truncate(start);
+                    let outputs = shuffle.outputs.iter().map(|input| inputs[*input as usize]);
+                    stack.extend(outputs);
+                }
+            }
+        }
+    }
+
+    /// Check the instruction limit, the timeout and the cancellation token, and return the
+    /// instruction count at which to check again.
+    #[cold]
+    fn check(
+        &self,
+        pc: usize,
+        started: Instant,
+        stack: &[StackValue],
+        instructions: u64,
+    ) -> Result<u64, InterpreterError> {
+        let limits = &self.config.limits;
+        if let Some(limit) = limits.max_instructions
+            && instructions > limit
+        {
+            return Err(self.error(pc, |backtrace| {
+                InterpreterError::instruction_limit_exceeded(backtrace, limit)
+            }));
+        }
+        if let Some(ms) = limits.timeout_ms
+            && started.elapsed().as_millis() >= ms as u128
+        {
+            return Err(self.error(pc, |backtrace| InterpreterError::timeout(backtrace, ms)));
+        }
+        if self.cancellation.is_cancelled() {
+            return Err(InterpreterError::interrupted(
+                self.full_backtrace(pc),
+                stack.to_vec(),
+            ));
+        }
+        let next = instructions + POLL_INTERVAL;
+        Ok(match limits.max_instructions {
+            Some(limit) => next.min(limit + 1),
+            None => next,
+        })
+    }
+
+    fn call_builtin(
+        &mut self,
+        id: u64,
+        stack: &mut Vec<StackValue>,
+    ) -> Result<(), InterpreterError> {
+        // the builtin was entered like a lambda, errors are reported where it was called.
+        let (caller, frames) = self.frames.split_last().unwrap();
+        let pc = caller.return_to() as usize - 1;
+        let backtrace = if self.config.print_backtrace {
+            let calls = frames
+                .iter()
+                .rev()
+                .map(|frame| frame.return_to() as usize - 1);
+            std::iter::once(pc)
+                .chain(calls)
+                .map(|pc| self.bytecode.origins[pc])
+                .collect()
+        } else {
+            vec![self.bytecode.origins[pc]]
+        };
+        let builtin = self.builtins.get_mut(id).unwrap();
+        builtin(&mut BuiltinContext {
+            data_stack: stack,
+            origins: None,
+            history: None,
+            origin: None,
+            input: &mut self.input,
+            output: &mut self.output,
+            observer: &mut (),
+            type_safety: self.config.type_safety,
+            limits: self.config.limits,
+            output_bytes: &mut self.output_bytes,
+            backtrace,
+        })
+    }
+
+    /// An error of the instruction at `pc`. Kept out of [Vm::execute], which only takes the
+    /// fast paths.
+    #[cold]
+    #[inline(never)]
+    fn error(
+        &self,
+        pc: usize,
+        error: impl FnOnce(Vec<ProgramPos>) -> InterpreterError,
+    ) -> InterpreterError {
+        error(if self.config.print_backtrace {
+            self.full_backtrace(pc)
+        } else {
+            vec![self.bytecode.origins[pc]]
+        })
+    }
+
+    /// The origin of the instruction at `pc`, followed by the calls leading to it.
+    #[cold]
+    fn full_backtrace(&self, pc: usize) -> Vec<ProgramPos> {
+        let calls = self.frames.iter().rev();
+        std::iter::once(pc)
+            .chain(calls.map(|frame| frame.return_to() as usize - 1))
+            .map(|pc| self.bytecode.origins[pc])
+            .collect()
+    }
+}
+
+/// The slot of a variable. Variables are in `a..=z` or cast from an integer or a lambda, which
+/// gives one of the 32 names from `a`, see [crate::cast_var].
+fn var_index(var: char) -> usize {
+    (var as usize).wrapping_sub('a' as usize) & 31
+}
@@ -1,8 +1,12 @@
 use falsec_analyzer::Analyzer;
-use falsec_interpreter::Interpreter;
+use falsec_interpreter::builtins::Builtins;
+use falsec_interpreter::error::InterpreterErrorKind;
+use falsec_interpreter::stdlib::register_standard_builtins;
+use falsec_interpreter::vm::Vm;
+use falsec_interpreter::{Interpreter, LoopState, StackValue};
 use falsec_parser::Parser;
-use falsec_types::Config;
-use falsec_types::source::Program;
+use falsec_types::source::{OwnedProgram, Program};
+use falsec_types::{Arithmetic, Config, Limits, TypeSafety};
 
 fn parse_program<'source>(program: &'source str, config: &Config) -> Program<'source> {
     let parser = Parser::new(program, config.clone());
@@ -23,3 +27,671 @@ fn test_a() {
         .unwrap();
     assert_eq!(out, b"123");
 }
+
+#[test]
+fn run_outcome() {
+    use falsec_interpreter::StackValue::{Integer, Var};
+    use std::collections::HashMap;
+
+    // sum the seeded stack down to the 0 into `s`, print it and store it doubled in `q`
+    let config = Config::default();
+    let mut out = Vec::new();
+    let program = parse_program("[2*]f: 0s: [$][s;+s:]# % s;$. f;! q:", &config);
+    let outcome = Interpreter::new(&b""[..], &mut out, program, config)
+        .with_data_stack(vec![Integer(0), Integer(1), Integer(2), Integer(3)])
+        .with_variables(HashMap::from([('v', Var('w'))]))
+        .run()
+        .unwrap();
+    assert_eq!(outcome.data_stack, []);
+    assert_eq!(outcome.variables[&'s'], Integer(6));
+    assert_eq!(outcome.variables[&'q'], Integer(12));
+    assert_eq!(outcome.variables[&'v'], Var('w'));
+    assert_eq!(outcome.output_bytes, 1);
+    assert_eq!(out, b"6");
+}
+
+#[test]
+fn owned_program_on_worker_thread() {
+    fn load(config: &Config) -> OwnedProgram {
+        let code = String::from(include_str!("samples/a.f"));
+        parse_program(&code, config).into_owned()
+    }
+
+    let config = Config::default();
+    let program = load(&config);
+    let out = std::thread::spawn(move || {
+        let mut out = Vec::new();
+        Interpreter::new(&b"L42"[..], &mut out, program.program(), config)
+            .run()
+            .unwrap();
+        out
+    })
+    .join()
+    .unwrap();
+    assert_eq!(out, b"42");
+}
+
+/// Run `code` with the interpreter and the VM, and check that both behave the same.
+fn assert_same_as_interpreter(code: &str, input: &[u8], config: Config) {
+    assert_same_with_builtins(code, input, config, |_| ());
+}
+
+fn assert_same_with_builtins(
+    code: &str,
+    input: &[u8],
+    config: Config,
+    register: impl Fn(&mut Builtins),
+) {
+    assert_same_program(code, parse_program(code, &config), input, config, register);
+}
+
+/// Like [assert_same_with_builtins], for a program that was not parsed, named `code`.
+fn assert_same_program(
+    code: &str,
+    program: Program,
+    input: &[u8],
+    config: Config,
+    register: impl Fn(&mut Builtins),
+) {
+    let mut out = Vec::new();
+    let mut interpreter = Interpreter::new(input, &mut out, program.clone(), config.clone());
+    register(interpreter.builtins_mut());
+    let expected = interpreter
+        .resume()
+        .map(|_| interpreter.data_stack().to_vec());
+    drop(interpreter);
+
+    let mut vm_out = Vec::new();
+    let mut vm = Vm::new(input, &mut vm_out, &program, config);
+    register(vm.builtins_mut());
+    let actual = vm.run().map(|_| vm.data_stack().to_vec());
+    drop(vm);
+
+    assert_eq!(
+        String::from_utf8_lossy(&vm_out),
+        String::from_utf8_lossy(&out),
+        "{code}"
+    );
+    match (actual, expected) {
+        (Ok(actual), Ok(expected)) => assert_eq!(actual, expected, "{code}"),
+        (Err(actual), Err(expected)) => {
+            assert_eq!(actual.to_string(), expected.to_string(), "{code}");
+            assert_eq!(actual.backtrace, expected.backtrace, "{code}");
+        }
+        (actual, expected) => panic!("{code}: {actual:?} != {expected:?}"),
+    }
+}
+
+#[test]
+fn vm() {
+    let config = Config {
+        print_backtrace: true,
+        ..Default::default()
+    };
+    assert_same_as_interpreter(include_str!("../../examples/simple.f"), b"", config.clone());
+    assert_same_as_interpreter(include_str!("samples/a.f"), b"L123", config.clone());
+    for code in [
+        "[$1>[$1-f;!\\2-f;!+]?]f: 15f;!.",
+        "1 2 3@\\$ø%",
+        "a;b:b;1+c: 'a 1- [1]!",
+        "0[$10>~][$1+]#",
+        "5[$][1-]#3_.",
+        "^^^,,,",
+        "1 0/",
+        "[[%]!]!",
+        "1 2 3 5ø",
+        "1 2 3 4%$\\@$%\\",
+        "1\\$@",
+        "3[1-$][$.]#",
+        "3[1-$][$[1-$][$.]#%]#",
+        "3[1-$][1 0/]#",
+        "[][]#",
+    ] {
+        assert_same_as_interpreter(code, b"ab", config.clone());
+    }
+
+    let strict = Config {
+        type_safety: TypeSafety::Full,
+        arithmetic: Arithmetic::Checked,
+        limits: Limits {
+            max_call_depth: Some(100),
+            ..Default::default()
+        },
+        ..config
+    };
+    for code in [
+        "a 1+",
+        "[1]2+",
+        "9223372036854775807 1+",
+        "[$1+g;!]g:0g;!",
+        "1a:",
+        "1[$[]][%]#",
+        "[[]$][%]#",
+    ] {
+        assert_same_as_interpreter(code, b"", strict.clone());
+    }
+}
+
+#[test]
+fn vm_limits() {
+    // every limit, so that each command of the superinstructions is the one that fails once.
+    for code in [
+        "1 2+ 3- a: a; b:",
+        "[1+]f: 0f;! [2]? 0[$3>~][1+]#",
+        "0[$2>~][[$]![%]?1+]#",
+        "1_[$1>[$1-f;!\\2-f;!+]?]f: 6f;!",
+        "1 2 3\\$@$@%\\ 4$$%@",
+        "2[1-$][$[1-$][]#%]#",
+    ] {
+        for limit in 0..80 {
+            let instructions = Config {
+                limits: Limits {
+                    max_instructions: Some(limit),
+                    ..Default::default()
+                },
+                print_backtrace: true,
+                ..Default::default()
+            };
+            assert_same_as_interpreter(code, b"", instructions);
+        }
+        for limit in 0..6 {
+            let stack = Config {
+                limits: Limits {
+                    max_stack_depth: Some(limit),
+                    ..Default::default()
+                },
+                print_backtrace: true,
+                ..Default::default()
+            };
+            assert_same_as_interpreter(code, b"", stack);
+        }
+        for limit in 0..4 {
+            let calls = Config {
+                limits: Limits {
+                    max_call_depth: Some(limit),
+                    ..Default::default()
+                },
+                print_backtrace: true,
+                ..Default::default()
+            };
+            assert_same_as_interpreter(code, b"", calls);
+        }
+    }
+}
+
+#[test]
+fn vm_hand_built_program() {
+    use falsec_types::source::{Command, LambdaCommand, Pos, Span};
+    use std::collections::HashMap;
+
+    // lambda ids far apart, and a variable name the analyzer would reject
+    let span = Span::new(Pos::at_start(), Pos::at_start(), "");
+    let program = Program {
+        main_id: 1 << 40,
+        lambdas: HashMap::from([
+            (
+                1 << 40,
+                vec![
+                    (
+                        Command::Lambda(LambdaCommand::LambdaReference(u64::MAX)),
+                        span,
+                    ),
+                    (Command::Exec, span),
+                ],
+            ),
+            (
+                u64::MAX,
+                vec![
+                    (Command::IntLiteral(1), span),
+                    (Command::Var('A'), span),
+                    (Command::Store, span),
+                ],
+            ),
+        ]),
+        strings: HashMap::new(),
+    };
+    let config = Config::default();
+    assert_same_program("sparse", program.clone(), b"", config.clone(), |_| {});
+    let err = Vm::new(&b""[..], Vec::new(), &program, config)
+        .run()
+        .unwrap_err();
+    assert!(matches!(
+        err.kind,
+        InterpreterErrorKind::InvalidVariableName('A')
+    ));
+}
+
+#[test]
+fn builtins() {
+    fn register(builtins: &mut Builtins) {
+        // square
+        builtins.register(-1, |ctx| {
+            let i = ctx.pop_integer()?;
+            ctx.push_integer(i * i)
+        });
+        // echo the next input byte
+        builtins.register(-2, |ctx| match ctx.read_byte()? {
+            Some(byte) => ctx.write(&[byte, byte]),
+            None => Err(ctx.error("end of input")),
+        });
+        // count down, as the condition of a loop
+        let mut count = 3;
+        builtins.register(-3, move |ctx| {
+            count -= 1;
+            ctx.push_integer(if count >= 0 { -1 } else { 0 })
+        });
+    }
+
+    let full = Config {
+        type_safety: TypeSafety::Full,
+        print_backtrace: true,
+        ..Default::default()
+    };
+    for code in [
+        "7 1_!.",
+        "2_! 2_! 2_!",
+        "[2_!]!",
+        "1 2_?",
+        "3_[1.]#",
+        "3_a: a;!",
+        "4_!",
+        "1 [1_!]!",
+    ] {
+        assert_same_with_builtins(code, b"ab", full.clone(), register);
+        assert_same_with_builtins(code, b"ab", Config::default(), register);
+    }
+
+    let program = parse_program("5 1_! 2_!", &full);
+    let mut out = Vec::new();
+    let mut interpreter = Interpreter::new(&b"x"[..], &mut out, program, full.clone());
+    register(interpreter.builtins_mut());
+    interpreter.resume().unwrap();
+    assert_eq!(
+        interpreter.data_stack(),
+        [falsec_interpreter::StackValue::Integer(25)]
+    );
+    drop(interpreter);
+    assert_eq!(out, b"xx");
+
+    let program = parse_program("1_!", &full);
+    let err = Interpreter::new(&b""[..], Vec::new(), program, full)
+        .run()
+        .unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "Interpreter Error at 1:3: Type cast error: Integer -> Lambda"
+    );
+}
+
+/// Push the bytes of a string and its length, like the standard builtins expect.
+fn string_literal(s: &str) -> String {
+    let mut code: String = s.bytes().map(|b| format!("{b} ")).collect();
+    code.push_str(&s.len().to_string());
+    code
+}
+
+#[test]
+fn standard_builtins() {
+    use falsec_interpreter::StackValue::Integer;
+    let register = |builtins: &mut Builtins| {
+        register_standard_builtins(builtins, vec!["ab".into(), "c".into()]);
+    };
+    let config = Config::default();
+
+    assert_same_with_builtins("1_! 0 2_! 1 2_! 2 2_!", b"", config.clone(), register);
+    let program = parse_program("1_! 1 2_! 2 2_!", &config);
+    let mut vm = Vm::new(&b""[..], Vec::new(), &program, config.clone());
+    register(vm.builtins_mut());
+    vm.run().unwrap();
+    assert_eq!(
+        vm.data_stack(),
+        [Integer(2), Integer(99), Integer(1), Integer(-1)]
+    );
+
+    let dir = env!("CARGO_MANIFEST_DIR");
+    let code = format!(
+        "{} 3_! {} 3_!",
+        string_literal("CARGO_MANIFEST_DIR"),
+        string_literal("FALSEC_TEST_UNSET")
+    );
+    let program = parse_program(&code, &config);
+    let mut vm = Vm::new(&b""[..], Vec::new(), &program, config.clone());
+    register(vm.builtins_mut());
+    vm.run().unwrap();
+    let mut expected: Vec<_> = dir.bytes().map(|b| Integer(b as i64)).collect();
+    expected.extend([Integer(dir.len() as i64), Integer(-1)]);
+    assert_eq!(vm.data_stack(), expected);
+
+    // write a file, append to it and read it back, stdin and stdout are fds 0 and 1
+    let path = std::env::temp_dir().join(format!("falsec-test-{}", std::process::id()));
+    let path_code = string_literal(path.to_str().unwrap());
+    let code = format!(
+        "{path_code} 1 4_! f: 'h f;7_! f;5_! \
+         {path_code} 2 4_! f: 'i f;7_! f;5_! \
+         {path_code} 0 4_! f: f;6_! f;6_! f;6_! f;5_! \
+         0 6_! 1 7_!"
+    );
+    assert_same_with_builtins(&code, b"x", config.clone(), register);
+    let program = parse_program(&code, &config);
+    let mut out = Vec::new();
+    let mut vm = Vm::new(&b"x"[..], &mut out, &program, config.clone());
+    register(vm.builtins_mut());
+    vm.run().unwrap();
+    assert_eq!(vm.data_stack(), [Integer(104), Integer(105), Integer(-1)]);
+    drop(vm);
+    assert_eq!(out, b"x");
+    assert_eq!(std::fs::read(&path).unwrap(), b"hi");
+    std::fs::remove_file(&path).unwrap();
+
+    for (code, error) in [
+        (
+            "1 2 3 8_! 4",
+            "Interpreter Error at 1:9: Exit with status 3",
+        ),
+        (
+            "3 6_!",
+            "Interpreter Error at 1:5: Builtin error: Invalid file descriptor: 3",
+        ),
+        (
+            "0 1 5 4_!",
+            "Interpreter Error at 1:9: Builtin error: Invalid file mode: 5",
+        ),
+        (
+            "'a 5 1 4_!",
+            "Interpreter Error at 1:10: Builtin error: Invalid string length: 5",
+        ),
+    ] {
+        assert_same_with_builtins(code, b"", config.clone(), register);
+        let program = parse_program(code, &config);
+        let mut vm = Vm::new(&b""[..], Vec::new(), &program, config.clone());
+        register(vm.builtins_mut());
+        let err = vm.run().unwrap_err();
+        assert_eq!(err.to_string(), error);
+        if code.contains("8_!") {
+            assert!(matches!(err.kind, InterpreterErrorKind::Exit(3)));
+            assert_eq!(vm.data_stack(), [Integer(1), Integer(2)]);
+        }
+    }
+}
+
+#[test]
+fn error_context() {
+    use falsec_interpreter::StackValue::{Integer, Lambda};
+    use falsec_interpreter::error::ErrorContext;
+
+    let run = |code: &str, print_backtrace: bool| {
+        let config = Config {
+            type_safety: TypeSafety::Full,
+            print_backtrace,
+            ..Default::default()
+        };
+        let program = parse_program(code, &config);
+        Interpreter::new(&b""[..], Vec::new(), program, config)
+            .with_data_stack(vec![Integer(7)])
+            .run()
+            .unwrap_err()
+    };
+
+    // the `1` is stored in `a`, swapped and then executed
+    let err = run("1a: [a;+]f: 3 f;! a; 0\\ !", true);
+    let context = err.context.unwrap();
+    assert_eq!(
+        context.data_stack,
+        [Integer(7), Integer(4), Integer(0), Integer(1)]
+    );
+    assert_eq!(context.hidden_values, 0);
+    assert_eq!(context.variables, [('a', Integer(1)), ('f', Lambda(1))]);
+    let value = context.value.as_ref().unwrap();
+    assert_eq!(value.value, Integer(1));
+    assert_eq!(value.origin.as_ref().unwrap().source, "1");
+    assert_eq!(
+        context.to_string(),
+        "data stack: [7, 4, 0, 1]\nvariables: a = 1, f = <lambda 1>\n\
+         value: 1 (Integer), pushed at 1:1 by `1`"
+    );
+
+    // the failing command popped the zero, the context shows the stack from before it
+    let err = run("[1 2 3 4 5 6 7 8 9 10 11 12 13 14 15 16 17]! 0/", true);
+    let context = err.context.unwrap();
+    assert_eq!(context.hidden_values, 3);
+    assert_eq!(context.data_stack.len(), ErrorContext::STACK_TOP);
+    assert_eq!(context.data_stack.last(), Some(&Integer(0)));
+    assert!(context.variables.is_empty());
+    assert!(
+        context
+            .to_string()
+            .ends_with("value: 0 (Integer), pushed at 1:46 by `0`")
+    );
+
+    // seeded values have no origin
+    let err = run("!", true);
+    let context = err.context.unwrap();
+    assert_eq!(context.data_stack, [Integer(7)]);
+    assert_eq!(
+        context.value,
+        Some(falsec_interpreter::error::OffendingValue {
+            value: Integer(7),
+            origin: None
+        })
+    );
+    assert!(
+        context
+            .to_string()
+            .ends_with(", on the stack from the start")
+    );
+
+    assert!(run("1a: a;!", false).context.is_none());
+}
+
+#[test]
+fn symbolic_backtrace() {
+    use falsec_interpreter::error::BacktraceSymbols;
+
+    // `r` counts down through the unnamed lambda of `?` and divides by zero at the bottom
+    let source = "[$0=$[1 0/]?~[1-r;!]?]r:\n[ 5 r;! ]g:\ng;!";
+    let config = Config {
+        print_backtrace: true,
+        ..Default::default()
+    };
+    let program = parse_program(source, &config);
+    let mut interpreter = Interpreter::new(&b""[..], Vec::new(), program.clone(), config);
+    let err = loop {
+        if let Err(err) = interpreter.step() {
+            break err;
+        }
+    };
+    let symbols = BacktraceSymbols {
+        program: &program,
+        source,
+        lambda_names: interpreter.lambda_names(),
+    };
+    assert_eq!(symbols.describe_lambda(0), "main");
+    assert_eq!(symbols.describe_lambda(1), "r (defined at 1:1)");
+    assert_eq!(symbols.describe_lambda(3), "lambda 3 (defined at 1:14)");
+    assert_eq!(
+        err.fmt_symbolic_backtrace("r.f", &symbols).to_string(),
+        "  raised at   r.f:1:10 in lambda 2 (defined at 1:6)
+    1 | [$0=$[1 0/]?~[1-r;!]?]r:
+      |          ^
+  called from r.f:1:12 in r (defined at 1:1)
+    1 | [$0=$[1 0/]?~[1-r;!]?]r:
+      |            ^
+  called from r.f:1:19 in lambda 3 (defined at 1:14)
+    1 | [$0=$[1 0/]?~[1-r;!]?]r:
+      |                   ^
+  ... 8 more frames of r
+  called from r.f:1:21 in r (defined at 1:1)
+    1 | [$0=$[1 0/]?~[1-r;!]?]r:
+      |                     ^
+  called from r.f:2:7 in g (defined at 2:1)
+    2 | [ 5 r;! ]g:
+      |       ^
+  called from r.f:3:3 in main
+    3 | g;!
+      |   ^"
+    );
+}
+
+#[test]
+fn snapshot_restore() {
+    use falsec_interpreter::snapshot::{Snapshot, SnapshotError};
+
+    // print the squares of 1..=10, with the loop body in a named lambda
+    let code = "[$*.\" \"]f: 1i: [i;11=~][i;f;! i;1+i:]#";
+    let config = Config::default();
+    let mut expected = Vec::new();
+    let expected_outcome = Interpreter::new(
+        &b""[..],
+        &mut expected,
+        parse_program(code, &config),
+        config.clone(),
+    )
+    .run()
+    .unwrap();
+
+    // stop in the middle of the loop, inside `f`
+    let mut first = Vec::new();
+    let mut interpreter = Interpreter::new(
+        &b""[..],
+        &mut first,
+        parse_program(code, &config),
+        config.clone(),
+    );
+    interpreter
+        .run_until(|i| i.instructions() >= 60 && i.call_stack().len() == 2)
+        .unwrap();
+    let snapshot = interpreter.snapshot();
+    assert_eq!(
+        snapshot.call_stack[0].loop_state,
+        LoopState::ExecutingBody(2, 3)
+    );
+    let json = serde_json::to_string(&snapshot).unwrap();
+    let snapshot: Snapshot = serde_json::from_str(&json).unwrap();
+
+    let mut second = Vec::new();
+    let mut interpreter = Interpreter::new(
+        &b""[..],
+        &mut second,
+        parse_program(code, &config),
+        config.clone(),
+    );
+    interpreter.restore(snapshot.clone()).unwrap();
+    assert_eq!(interpreter.lambda_names()[&1], 'f');
+    // including the instructions and bytes counted before the snapshot
+    assert_eq!(interpreter.run().unwrap(), expected_outcome);
+    first.extend(second);
+    assert_eq!(String::from_utf8(first), String::from_utf8(expected));
+
+    let mut other = Interpreter::new(&b""[..], Vec::new(), parse_program("1 2+", &config), config);
+    assert!(matches!(
+        other.restore(snapshot),
+        Err(SnapshotError::ProgramMismatch { .. })
+    ));
+}
+
+#[test]
+fn snapshot_checkpoints() {
+    use falsec_interpreter::Status;
+
+    // continue from a snapshot every 25 commands, each time in a new interpreter
+    let code = "[$*.\" \"]f: 1i: [i;11=~][i;f;! i;1+i:]#";
+    let config = Config::default();
+    let mut expected = Vec::new();
+    let expected_outcome = Interpreter::new(
+        &b""[..],
+        &mut expected,
+        parse_program(code, &config),
+        config.clone(),
+    )
+    .run()
+    .unwrap();
+
+    let mut output = Vec::new();
+    let mut snapshot = None;
+    let mut checkpoints = 0;
+    let outcome = loop {
+        let mut interpreter = Interpreter::new(
+            &b""[..],
+            &mut output,
+            parse_program(code, &config),
+            config.clone(),
+        );
+        if let Some(snapshot) = snapshot.take() {
+            interpreter.restore(snapshot).unwrap();
+        }
+        let instructions = interpreter.instructions();
+        if interpreter.run_for(25).unwrap() == Status::Finished {
+            break interpreter.run().unwrap();
+        }
+        assert_eq!(interpreter.instructions(), instructions + 25);
+        snapshot = Some(interpreter.snapshot());
+        checkpoints += 1;
+    };
+    assert!(checkpoints > 5);
+    assert_eq!(outcome, expected_outcome);
+    assert_eq!(String::from_utf8(output), String::from_utf8(expected));
+}
+
+#[test]
+fn reverse_execution() {
+    use falsec_interpreter::{Breakpoint, Status};
+
+    // add the two bytes of the input to `s` three times, through `f`
+    let code = "^a: ^b: [s;+s:]f: 0s: 3[$][a;f;! b;f;! 1-]# % s;.";
+    let config = Config::default();
+    let mut output = Vec::new();
+    let mut interpreter = Interpreter::new(
+        &b"xy"[..],
+        &mut output,
+        parse_program(code, &config),
+        config,
+    );
+    interpreter.set_history_size(1000);
+    let mut states = vec![interpreter.snapshot()];
+    while interpreter.step().unwrap() != Status::Finished {
+        states.push(interpreter.snapshot());
+    }
+    states.push(interpreter.snapshot());
+    let finished = interpreter.snapshot();
+    assert_eq!(interpreter.history_len(), states.len() - 1);
+
+    // the last `s:` in `f`, before the final value was stored
+    let (n, pos) = interpreter.last_write('s').unwrap();
+    assert_eq!((pos.pos.line, pos.pos.column, pos.lambda_id), (1, 14, 1));
+    for _ in 0..n {
+        assert!(interpreter.step_back());
+    }
+    assert_eq!(interpreter.current_frame().pos, pos.pos);
+    assert_eq!(interpreter.variables()[&'s'], StackValue::Integer(602));
+
+    // back to the start of that call of `f`, and the one before
+    interpreter.add_breakpoint(Breakpoint::Lambda(1));
+    for s in [602, 482] {
+        assert_eq!(
+            interpreter.reverse_resume(),
+            Status::Breakpoint(Breakpoint::Lambda(1))
+        );
+        assert_eq!(interpreter.variables()[&'s'], StackValue::Integer(s));
+    }
+    interpreter.clear_breakpoints();
+
+    // every command is undone exactly, and executing them again reads the same input
+    interpreter.resume().unwrap();
+    assert_eq!(interpreter.snapshot(), finished);
+    while interpreter.step_back() {
+        states.pop();
+        assert_eq!(Some(&interpreter.snapshot()), states.last());
+    }
+    assert_eq!(states.len(), 1);
+    assert_eq!(interpreter.reverse_resume(), Status::StartOfHistory);
+    interpreter.resume().unwrap();
+    assert_eq!(interpreter.snapshot(), finished);
+
+    interpreter.set_history_size(10);
+    assert_eq!(interpreter.history_len(), 10);
+    interpreter.set_history_size(0);
+    assert!(!interpreter.step_back());
+    drop(interpreter);
+    // the output of undone commands is written again
+    assert_eq!(String::from_utf8(output).unwrap(), "723".repeat(3));
+}
@@ -0,0 +1,31 @@
+//! Ids of the standard builtins, which FALSE code calls like lambdas, e.g. `1_!` for [ARGC].
+//!
+//! The comments show what each builtin pops and pushes, top of the stack last.
+//! Strings are passed as their bytes followed by their length, so `"ab"` is `'a 'b 2`.
+//! File descriptors 0, 1 and 2 are the program's input, its output and stderr.
+
+/// `( -- n )` the number of program arguments.
+pub const ARGC: i64 = -1;
+
+/// `( i -- c1 .. cn n )` argument `i`, counting from 0, or just `-1` if there is none.
+pub const ARG: i64 = -2;
+
+/// `( c1 .. cn n -- c1 .. cm m )` the value of the environment variable named `c1 .. cn`,
+/// or just `-1` if it is not set.
+pub const GETENV: i64 = -3;
+
+/// `( c1 .. cn n mode -- fd )` open the file at the path `c1 .. cn` for reading (mode 0),
+/// writing (mode 1, truncates the file) or appending (mode 2). `fd` is `-1` if that failed.
+pub const OPEN: i64 = -4;
+
+/// `( fd -- )` close a file descriptor returned by [OPEN].
+pub const CLOSE: i64 = -5;
+
+/// `( fd -- c )` read a byte, or `-1` at the end of the file.
+pub const READ: i64 = -6;
+
+/// `( c fd -- )` write a byte, e.g. `'x 2 7_!` writes to stderr.
+pub const WRITE: i64 = -7;
+
+/// `( n -- )` flush the output and exit with status `n`.
+pub const EXIT: i64 = -8;
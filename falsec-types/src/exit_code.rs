@@ -0,0 +1,22 @@
+//! Exit statuses of `falsec` and compiled programs when something goes wrong.
+//! A program that ends normally exits with 0, or with the status it chose, see
+//! [crate::Config::exit_status_from_stack] and [crate::builtins::EXIT].
+//! `falsec` exits with 2 for invalid command line arguments.
+
+/// The program failed while running, e.g. a type error or division by zero.
+pub const RUNTIME_ERROR: i32 = 1;
+
+/// The program could not be parsed.
+pub const PARSE_ERROR: i32 = 3;
+
+/// The program was parsed, but is invalid, e.g. because of an invalid variable name.
+pub const ANALYSIS_ERROR: i32 = 4;
+
+/// The program could not be compiled.
+pub const COMPILE_ERROR: i32 = 5;
+
+/// `falsec` could not read the program file, or could not write an output file.
+pub const IO_ERROR: i32 = 6;
+
+/// The program was stopped with Ctrl-C.
+pub const INTERRUPTED: i32 = 130;
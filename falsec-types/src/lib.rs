@@ -2,6 +2,8 @@
 
 pub use tab_width::TabWidth;
 
+pub mod builtins;
+pub mod exit_code;
 pub mod source;
 
 #[derive(Clone, PartialEq, Eq, Debug, Default)]
@@ -39,6 +41,46 @@ pub struct Config {
     /// Print stack trace
     #[cfg_attr(feature = "serde", serde(default))]
     pub print_backtrace: bool,
+
+    /// if true, the number on top of the data stack when the program ends becomes its exit status.
+    /// an empty stack exits with 0.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub exit_status_from_stack: bool,
+
+    /// how integer overflow is handled. division by zero is always an error.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub arithmetic: Arithmetic,
+
+    /// resource limits for running untrusted programs. enforced by the interpreter and the vm, not by
+    /// compiled programs.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub limits: Limits,
+}
+
+/// Limits are disabled if not set.
+#[derive(Copy, Clone, PartialEq, Eq, Debug, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct Limits {
+    /// maximum number of executed commands
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub max_instructions: Option<u64>,
+
+    /// maximum number of values on the data stack
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub max_stack_depth: Option<usize>,
+
+    /// maximum number of nested lambda calls, including `#` loops
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub max_call_depth: Option<usize>,
+
+    /// maximum wall-clock time in milliseconds, measured from the first executed command
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub timeout_ms: Option<u64>,
+
+    /// maximum number of bytes written to the output
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub max_output_bytes: Option<u64>,
 }
 
 #[derive(Copy, Clone, PartialOrd, Ord, PartialEq, Eq, Debug, Default)]
@@ -58,6 +100,19 @@ pub enum TypeSafety {
     Full,
 }
 
+#[derive(Copy, Clone, PartialEq, Eq, Debug, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub enum Arithmetic {
+    #[default]
+    /// Results that do not fit into 64 bits wrap around.
+    Wrapping,
+    /// Results that do not fit into 64 bits are a runtime error.
+    Checked,
+    /// Results that do not fit into 64 bits are clamped to the smallest or largest integer.
+    Saturating,
+}
+
 mod tab_width {
     use std::ops::{Add, Div, Mul};
 
@@ -2,9 +2,11 @@
 use std::borrow::Cow;
 use std::collections::HashMap;
 use std::fmt;
+use std::ops::Range;
 
 /// A position in a source file.
 #[derive(Copy, Clone, Eq, PartialEq, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Pos {
     /// Offset in bytes
     pub offset: usize,
@@ -65,6 +67,7 @@ fn default() -> Self {
 }
 
 #[derive(Copy, Clone, Eq, PartialEq, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Span<'source> {
     /// Inclusive
     pub start: Pos,
@@ -78,20 +81,193 @@ impl<'source> Span<'source> {
     pub fn new(start: Pos, end: Pos, source: &'source str) -> Self {
         Self { start, end, source }
     }
+
+    /// Copy the source code, so that the span no longer borrows from it.
+    pub fn into_owned(self) -> OwnedSpan {
+        OwnedSpan {
+            start: self.start,
+            end: self.end,
+            source: self.source.into(),
+        }
+    }
+}
+
+/// A [Span] with its own copy of the source code.
+#[derive(Clone, Eq, PartialEq, Debug)]
+pub struct OwnedSpan {
+    /// Inclusive
+    pub start: Pos,
+    /// Exclusive
+    pub end: Pos,
+    /// Raw source code
+    pub source: String,
 }
 
 pub type Lambda<'source> = Vec<(Command<'source>, Span<'source>)>;
+
 #[derive(Clone, Debug, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Program<'source> {
     /// id of the top-level lambda. usually 0.
     pub main_id: u64,
     /// all lambdas defined in the program
+    #[cfg_attr(feature = "serde", serde(borrow))]
     pub lambdas: HashMap<u64, Lambda<'source>>,
     /// all string literals defined in the program
     pub strings: HashMap<u64, Cow<'source, str>>,
 }
 
+impl Program<'_> {
+    /// Copy all borrowed source code, so that the program can outlive the source it was
+    /// parsed from, e.g. to cache it or to send it to another thread.
+    pub fn into_owned(self) -> OwnedProgram {
+        let mut source = String::new();
+        let mut spans = HashMap::new();
+        let lambdas = self
+            .lambdas
+            .into_iter()
+            .map(|(id, lambda)| {
+                let mut lambda_spans = Vec::new();
+                let lambda = lambda_into_owned(lambda, &mut source, &mut lambda_spans);
+                spans.insert(id, lambda_spans);
+                (id, lambda)
+            })
+            .collect();
+        let strings = self
+            .strings
+            .into_iter()
+            .map(|(id, s)| (id, Cow::Owned(s.into_owned())))
+            .collect();
+        OwnedProgram {
+            program: Program {
+                main_id: self.main_id,
+                lambdas,
+                strings,
+            },
+            source,
+            spans,
+        }
+    }
+}
+
+/// A [Program] that owns its source code, so that it can be cached, returned from the function
+/// that read the source, or sent to another thread. See [Program::into_owned].
+#[derive(Clone, Debug, Default)]
+pub struct OwnedProgram {
+    /// The program, with empty spans.
+    program: Program<'static>,
+    /// The source code of all spans, one after another.
+    source: String,
+    /// Where the source code of each span is in [OwnedProgram::source], by lambda id. The spans
+    /// of a lambda definition follow the span of the definition.
+    spans: HashMap<u64, Vec<Range<usize>>>,
+}
+
+impl OwnedProgram {
+    /// The program, borrowing its source code from here.
+    pub fn program(&self) -> Program<'_> {
+        Program {
+            main_id: self.program.main_id,
+            lambdas: self
+                .program
+                .lambdas
+                .iter()
+                .map(|(id, lambda)| (*id, self.lambda(lambda, &mut self.spans[id].iter())))
+                .collect(),
+            strings: self
+                .program
+                .strings
+                .iter()
+                .map(|(id, s)| (*id, Cow::Borrowed(&**s)))
+                .collect(),
+        }
+    }
+
+    fn lambda<'a>(
+        &'a self,
+        lambda: &'a Lambda<'static>,
+        spans: &mut std::slice::Iter<Range<usize>>,
+    ) -> Lambda<'a> {
+        lambda
+            .iter()
+            .map(|(command, span)| {
+                let source = &self.source[spans.next().unwrap().clone()];
+                let command = match command {
+                    Command::Lambda(LambdaCommand::LambdaDefinition(lambda)) => {
+                        Command::Lambda(LambdaCommand::LambdaDefinition(self.lambda(lambda, spans)))
+                    }
+                    command => command.clone(),
+                };
+                (command, Span { source, ..*span })
+            })
+            .collect()
+    }
+}
+
+/// Copy the commands of a lambda, and move the source code of their spans to `source`.
+fn lambda_into_owned(
+    lambda: Lambda,
+    source: &mut String,
+    spans: &mut Vec<Range<usize>>,
+) -> Lambda<'static> {
+    lambda
+        .into_iter()
+        .map(|(command, span)| {
+            spans.push(source.len()..source.len() + span.source.len());
+            source.push_str(span.source);
+            let command = command_into_owned(command, source, spans);
+            (command, Span { source: "", ..span })
+        })
+        .collect()
+}
+
+/// Copy a command, see [lambda_into_owned].
+fn command_into_owned(
+    command: Command,
+    source: &mut String,
+    spans: &mut Vec<Range<usize>>,
+) -> Command<'static> {
+    match command {
+        Command::IntLiteral(i) => Command::IntLiteral(i),
+        Command::CharLiteral(c) => Command::CharLiteral(c),
+        Command::Dup => Command::Dup,
+        Command::Drop => Command::Drop,
+        Command::Swap => Command::Swap,
+        Command::Rot => Command::Rot,
+        Command::Pick => Command::Pick,
+        Command::Add => Command::Add,
+        Command::Sub => Command::Sub,
+        Command::Mul => Command::Mul,
+        Command::Div => Command::Div,
+        Command::Neg => Command::Neg,
+        Command::BitAnd => Command::BitAnd,
+        Command::BitOr => Command::BitOr,
+        Command::BitNot => Command::BitNot,
+        Command::Gt => Command::Gt,
+        Command::Eq => Command::Eq,
+        Command::Lambda(LambdaCommand::LambdaDefinition(lambda)) => Command::Lambda(
+            LambdaCommand::LambdaDefinition(lambda_into_owned(lambda, source, spans)),
+        ),
+        Command::Lambda(LambdaCommand::LambdaReference(id)) => {
+            Command::Lambda(LambdaCommand::LambdaReference(id))
+        }
+        Command::Exec => Command::Exec,
+        Command::Conditional => Command::Conditional,
+        Command::While => Command::While,
+        Command::Var(c) => Command::Var(c),
+        Command::Store => Command::Store,
+        Command::Load => Command::Load,
+        Command::ReadChar => Command::ReadChar,
+        Command::WriteChar => Command::WriteChar,
+        Command::StringLiteral(s) => Command::StringLiteral(Cow::Owned(s.into_owned())),
+        Command::WriteInt => Command::WriteInt,
+        Command::Flush => Command::Flush,
+        Command::Comment(s) => Command::Comment(Cow::Owned(s.into_owned())),
+    }
+}
+
 #[derive(Clone, Eq, PartialEq, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum Command<'source> {
     /// **123** put integer 123 on the stack
     IntLiteral(u64),
@@ -132,7 +308,7 @@ pub enum Command<'source> {
     Eq,
 
     /// **\[...]** define and put a lambda on the stack
-    Lambda(LambdaCommand<'source>),
+    Lambda(#[cfg_attr(feature = "serde", serde(borrow))] LambdaCommand<'source>),
     /// **!** execute a lambda
     Exec,
     /// **?** conditional execution: condition\[true]?
@@ -164,15 +340,18 @@ pub enum Command<'source> {
 }
 
 #[derive(Clone, Eq, PartialEq, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum LambdaCommand<'source> {
-    LambdaDefinition(Lambda<'source>),
+    LambdaDefinition(#[cfg_attr(feature = "serde", serde(borrow))] Lambda<'source>),
     LambdaReference(u64),
 }
 
 #[cfg(test)]
 mod tests {
     use crate::Config;
-    use crate::source::Pos;
+    use crate::source::{Command, LambdaCommand, OwnedProgram, Pos, Program, Span};
+    use std::borrow::Cow;
+    use std::collections::HashMap;
 
     #[test]
     fn pos_advance() {
@@ -199,4 +378,79 @@ fn pos_advance() {
         pos.advance('\t', &config);
         assert_eq!(pos, Pos::new(1, 1, 9));
     }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn program_serde_round_trip() {
+        let span = Span::new(Pos::new(0, 1, 1), Pos::new(3, 1, 4), "[1]");
+        let program = Program {
+            main_id: 0,
+            lambdas: HashMap::from([
+                (
+                    0,
+                    vec![(Command::Lambda(LambdaCommand::LambdaReference(1)), span)],
+                ),
+                (1, vec![(Command::IntLiteral(1), span)]),
+            ]),
+            strings: HashMap::from([(7, Cow::Borrowed("Hi"))]),
+        };
+        let json = serde_json::to_string(&program).unwrap();
+        let deserialized: Program = serde_json::from_str(&json).unwrap();
+        assert_eq!(deserialized.main_id, program.main_id);
+        assert_eq!(deserialized.lambdas, program.lambdas);
+        assert_eq!(deserialized.strings, program.strings);
+    }
+
+    #[test]
+    fn program_into_owned() {
+        fn parse_and_drop_source() -> OwnedProgram {
+            let source = String::from("[\"Hi\"[1]]");
+            let string = Span::new(Pos::new(1, 1, 2), Pos::new(5, 1, 6), &source[1..5]);
+            let one = Span::new(Pos::new(6, 1, 7), Pos::new(7, 1, 8), &source[6..7]);
+            let definition = LambdaCommand::LambdaDefinition(vec![(Command::IntLiteral(1), one)]);
+            let program = Program {
+                main_id: 0,
+                lambdas: HashMap::from([
+                    (
+                        0,
+                        vec![(
+                            Command::Lambda(LambdaCommand::LambdaReference(1)),
+                            Span::new(Pos::new(0, 1, 1), Pos::new(9, 1, 10), &source[..]),
+                        )],
+                    ),
+                    (
+                        1,
+                        vec![
+                            (Command::StringLiteral(Cow::Borrowed(&source[2..4])), string),
+                            (
+                                Command::Lambda(definition),
+                                Span::new(Pos::new(5, 1, 6), Pos::new(8, 1, 9), &source[5..8]),
+                            ),
+                        ],
+                    ),
+                ]),
+                strings: HashMap::from([(0, Cow::Borrowed(&source[2..4]))]),
+            };
+            program.into_owned()
+        }
+
+        let owned = std::thread::spawn(parse_and_drop_source).join().unwrap();
+        let program = owned.program();
+        assert_eq!(program.lambdas[&0][0].1.source, "[\"Hi\"[1]]");
+        assert!(matches!(
+            &program.lambdas[&1][0],
+            (Command::StringLiteral(s), span) if s == "Hi" && span.source == "\"Hi\""
+        ));
+        let (Command::Lambda(LambdaCommand::LambdaDefinition(lambda)), span) =
+            &program.lambdas[&1][1]
+        else {
+            panic!("expected a lambda definition");
+        };
+        assert_eq!(span.source, "[1]");
+        assert_eq!(
+            lambda[0].1,
+            Span::new(Pos::new(6, 1, 7), Pos::new(7, 1, 8), "1")
+        );
+        assert_eq!(program.strings[&0], "Hi");
+    }
 }
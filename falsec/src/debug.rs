@@ -0,0 +1,561 @@
+use anstyle::Style;
+use falsec_interpreter::error::{BacktraceSymbols, InterpreterError};
+use falsec_interpreter::{Breakpoint, Interpreter, LoopState, StackValue, Status};
+use falsec_types::Config;
+use falsec_types::source::{Pos, Program};
+use std::cell::Cell;
+use std::io::{self, BufRead, Read, Write, stdin, stdout};
+use std::rc::Rc;
+
+const HELP: &str = "\
+break, b [LINE[:COLUMN]]    set a breakpoint, or list breakpoints without argument
+delete, d [N]               delete breakpoint N, or all breakpoints
+step, s [N]                 execute N commands (default 1)
+next, n [N]                 like step, but step over the lambdas called by ! ? #
+finish, f                   run until the current lambda returns
+continue, c                 run until a breakpoint is hit or the program finishes
+reverse-step, rs [N]        undo N commands (default 1)
+reverse-continue, rc        undo commands until the previous breakpoint
+last-write, lw VAR          show where a variable was last stored
+print, p [stack|vars|VAR]   print the data stack (default), all variables or one variable
+backtrace, bt               print the current position and all calls leading to it
+list, l                     show the current command
+help, h                     show this help
+quit, q                     exit the debugger
+
+An empty line repeats the last command.";
+
+/// The interactive debugger behind `falsec debug`. Its messages are written to `Out`, the
+/// program's output goes to stdout.
+pub struct Debugger<'source, Input: Read, Out: Write> {
+    interpreter: Interpreter<'source, Input, ProgramOutput>,
+    out: Out,
+    source: &'source str,
+    path: &'source str,
+    /// Whether the program's output ends with a newline, so that debugger messages start on
+    /// their own line.
+    at_line_start: Rc<Cell<bool>>,
+    /// False once the program has finished or failed.
+    running: bool,
+    highlight: bool,
+}
+
+/// Writes the program's output to stdout and remembers whether it ended a line.
+pub struct ProgramOutput(pub Rc<Cell<bool>>);
+
+impl Write for ProgramOutput {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = stdout().write(buf)?;
+        if n > 0 {
+            self.0.set(buf[n - 1] == b'\n');
+        }
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        stdout().flush()
+    }
+}
+
+/// Reads the debugger's commands from stdin one line at a time. Stdin is not locked in
+/// between, so the program may read from it as well.
+#[derive(Default)]
+pub struct StdinLines {
+    line: String,
+    consumed: usize,
+}
+
+impl Read for StdinLines {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.fill_buf()?.read(buf)?;
+        self.consume(n);
+        Ok(n)
+    }
+}
+
+impl BufRead for StdinLines {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        if self.consumed == self.line.len() {
+            self.line.clear();
+            self.consumed = 0;
+            stdin().read_line(&mut self.line)?;
+        }
+        Ok(&self.line.as_bytes()[self.consumed..])
+    }
+
+    fn consume(&mut self, amount: usize) {
+        self.consumed += amount;
+    }
+}
+
+impl<'source, Input: Read, Out: Write> Debugger<'source, Input, Out> {
+    pub fn new(
+        input: Input,
+        out: Out,
+        program: Program<'source>,
+        config: Config,
+        source: &'source str,
+        path: &'source str,
+        history_size: usize,
+    ) -> Self {
+        let at_line_start = Rc::new(Cell::new(true));
+        let output = ProgramOutput(at_line_start.clone());
+        let mut interpreter = Interpreter::new(input, output, program, config);
+        interpreter.set_history_size(history_size);
+        Self {
+            running: !interpreter.is_finished(),
+            interpreter,
+            out,
+            source,
+            path,
+            at_line_start,
+            highlight: false,
+        }
+    }
+
+    /// Highlight the current command with colors instead of marking it on the next line.
+    pub fn with_highlight(mut self, highlight: bool) -> Self {
+        self.highlight = highlight;
+        self
+    }
+
+    /// Execute the commands until `quit` or the end of `commands`.
+    pub fn run(mut self, mut commands: impl BufRead) -> io::Result<()> {
+        self.print_location()?;
+        let mut last = String::new();
+        loop {
+            write!(self.out, "(falsec) ")?;
+            self.out.flush()?;
+            let mut line = String::new();
+            if commands.read_line(&mut line)? == 0 {
+                return writeln!(self.out);
+            }
+            let line = match line.trim() {
+                "" => last.clone(),
+                line => line.to_string(),
+            };
+            let mut words = line.split_whitespace();
+            let Some(command) = words.next() else {
+                continue;
+            };
+            let arg = words.next();
+            match command {
+                "break" | "b" => self.set_breakpoint(arg)?,
+                "delete" | "d" => self.delete_breakpoint(arg)?,
+                "step" | "s" => self.repeat(arg, |i| i.step())?,
+                "next" | "n" => self.repeat(arg, |i| {
+                    let depth = i.call_stack().len();
+                    i.run_until(|i| {
+                        i.call_stack().len() < depth
+                            || (i.call_stack().len() == depth
+                                && i.current_frame().loop_state == LoopState::None)
+                    })
+                })?,
+                "finish" | "f" => {
+                    let depth = self.interpreter.call_stack().len();
+                    if depth == 0 {
+                        writeln!(self.out, "\"finish\" is not meaningful in the main lambda.")?;
+                    } else {
+                        self.execute(|i| i.run_until(|i| i.call_stack().len() < depth))?;
+                    }
+                }
+                "continue" | "c" => self.execute(|i| i.resume())?,
+                "reverse-step" | "rs" => {
+                    let Ok(count) = arg.map_or(Ok(1), str::parse::<usize>) else {
+                        writeln!(self.out, "Invalid count: {}", arg.unwrap())?;
+                        continue;
+                    };
+                    self.reverse(|i| {
+                        if (0..count).all(|_| i.step_back()) {
+                            Status::Paused
+                        } else {
+                            Status::StartOfHistory
+                        }
+                    })?;
+                }
+                "reverse-continue" | "rc" => self.reverse(|i| i.reverse_resume())?,
+                "last-write" | "lw" => self.print_last_write(arg)?,
+                "print" | "p" => self.print(arg.unwrap_or("stack"))?,
+                "backtrace" | "bt" => self.print_backtrace()?,
+                "list" | "l" => self.print_location()?,
+                "help" | "h" => writeln!(self.out, "{HELP}")?,
+                "quit" | "q" => return Ok(()),
+                _ => writeln!(self.out, "Unknown command: {command}. Try \"help\".")?,
+            }
+            last = line;
+        }
+    }
+
+    fn set_breakpoint(&mut self, arg: Option<&str>) -> io::Result<()> {
+        let Some(arg) = arg else {
+            if self.interpreter.breakpoints().is_empty() {
+                writeln!(self.out, "No breakpoints.")?;
+            }
+            for (i, breakpoint) in self.interpreter.breakpoints().iter().enumerate() {
+                writeln!(self.out, "{}: {}", i + 1, fmt_breakpoint(breakpoint))?;
+            }
+            return Ok(());
+        };
+        let (line, column) = match arg.split_once(':') {
+            Some((line, column)) => (line.parse(), Some(column.parse())),
+            None => (arg.parse(), None),
+        };
+        let breakpoint = match (line, column) {
+            // break before the first command of the line, not before each of them.
+            (Ok(line), None) => match self
+                .interpreter
+                .program()
+                .lambdas
+                .values()
+                .flatten()
+                .map(|(_, span)| span.start)
+                .filter(|pos| pos.line == line)
+                .min_by_key(|pos| pos.column)
+            {
+                Some(pos) => Breakpoint::Pos(pos),
+                None => return writeln!(self.out, "No command starts at line {line}."),
+            },
+            (Ok(line), Some(Ok(column))) => Breakpoint::Pos(Pos::new(0, line, column)),
+            _ => {
+                return writeln!(
+                    self.out,
+                    "Invalid position: {arg}. Expected LINE or LINE:COLUMN."
+                );
+            }
+        };
+        self.interpreter.add_breakpoint(breakpoint);
+        let n = self
+            .interpreter
+            .breakpoints()
+            .iter()
+            .position(|b| *b == breakpoint)
+            .unwrap();
+        writeln!(
+            self.out,
+            "Breakpoint {}: {}",
+            n + 1,
+            fmt_breakpoint(&breakpoint)
+        )
+    }
+
+    fn delete_breakpoint(&mut self, arg: Option<&str>) -> io::Result<()> {
+        let Some(arg) = arg else {
+            self.interpreter.clear_breakpoints();
+            return writeln!(self.out, "Deleted all breakpoints.");
+        };
+        let breakpoint = arg
+            .parse::<usize>()
+            .ok()
+            .and_then(|n| self.interpreter.breakpoints().get(n.wrapping_sub(1)))
+            .copied();
+        match breakpoint {
+            Some(breakpoint) => {
+                self.interpreter.remove_breakpoint(&breakpoint);
+                writeln!(self.out, "Deleted breakpoint {arg}.")
+            }
+            None => writeln!(self.out, "No breakpoint {arg}."),
+        }
+    }
+
+    fn repeat(
+        &mut self,
+        count: Option<&str>,
+        mut f: impl FnMut(
+            &mut Interpreter<'source, Input, ProgramOutput>,
+        ) -> Result<Status, InterpreterError>,
+    ) -> io::Result<()> {
+        let Ok(count) = count.map_or(Ok(1), str::parse::<usize>) else {
+            return writeln!(self.out, "Invalid count: {}", count.unwrap());
+        };
+        self.execute(|i| {
+            let mut status = Status::Paused;
+            for _ in 0..count {
+                status = f(i)?;
+                if status != Status::Paused {
+                    break;
+                }
+            }
+            Ok(status)
+        })
+    }
+
+    fn execute(
+        &mut self,
+        f: impl FnOnce(
+            &mut Interpreter<'source, Input, ProgramOutput>,
+        ) -> Result<Status, InterpreterError>,
+    ) -> io::Result<()> {
+        if !self.running {
+            return writeln!(self.out, "The program is not being run.");
+        }
+        let result = f(&mut self.interpreter);
+        if !self.at_line_start.replace(true) {
+            writeln!(self.out)?;
+        }
+        match result {
+            Ok(Status::Paused | Status::StartOfHistory) => self.print_location(),
+            Ok(Status::Breakpoint(breakpoint)) => {
+                writeln!(self.out, "Breakpoint: {}", fmt_breakpoint(&breakpoint))?;
+                self.print_location()
+            }
+            Ok(Status::Finished) => {
+                self.running = false;
+                writeln!(self.out, "Program finished.")
+            }
+            Err(e) => {
+                self.running = false;
+                let symbols = self.symbols();
+                let backtrace = e.fmt_symbolic_backtrace(self.path, &symbols).to_string();
+                writeln!(self.out, "{e}")?;
+                writeln!(self.out, "{backtrace}")
+            }
+        }
+    }
+
+    /// Undo commands, also after the program finished or failed.
+    fn reverse(
+        &mut self,
+        f: impl FnOnce(&mut Interpreter<'source, Input, ProgramOutput>) -> Status,
+    ) -> io::Result<()> {
+        if self.interpreter.history_len() == 0 {
+            return writeln!(self.out, "There are no commands to undo.");
+        }
+        self.running = true;
+        match f(&mut self.interpreter) {
+            Status::Breakpoint(breakpoint) => {
+                writeln!(self.out, "Breakpoint: {}", fmt_breakpoint(&breakpoint))?
+            }
+            Status::StartOfHistory => writeln!(self.out, "Reached the start of the history.")?,
+            Status::Paused | Status::Finished => {}
+        }
+        self.print_location()
+    }
+
+    fn print_last_write(&mut self, var: Option<&str>) -> io::Result<()> {
+        let Some(c) = var.filter(|var| var.chars().count() == 1) else {
+            return writeln!(self.out, "Expected a variable name.");
+        };
+        let c = c.chars().next().unwrap();
+        match self.interpreter.last_write(c) {
+            Some((n, pos)) => {
+                let lambda = self.symbols().describe_lambda(pos.lambda_id);
+                writeln!(
+                    self.out,
+                    "{c} was stored {n} commands ago at {}:{}:{} in {lambda}. \"reverse-step {n}\" goes back there.",
+                    self.path, pos.pos.line, pos.pos.column,
+                )
+            }
+            None => writeln!(
+                self.out,
+                "{c} was not stored in the last {} commands.",
+                self.interpreter.history_len()
+            ),
+        }
+    }
+
+    fn print(&mut self, what: &str) -> io::Result<()> {
+        match what {
+            "stack" => writeln!(self.out, "{}", fmt_stack(self.interpreter.data_stack())),
+            "vars" => {
+                let mut variables: Vec<_> = self.interpreter.variables().iter().collect();
+                variables.sort_unstable_by_key(|(c, _)| **c);
+                if variables.is_empty() {
+                    writeln!(self.out, "No variables.")?;
+                }
+                for (c, value) in variables {
+                    writeln!(self.out, "{c} = {value}")?;
+                }
+                Ok(())
+            }
+            var if var.len() == 1 => {
+                let c = var.chars().next().unwrap();
+                match self.interpreter.variables().get(&c) {
+                    Some(value) => writeln!(self.out, "{c} = {value}"),
+                    None => writeln!(self.out, "{c} is not set"),
+                }
+            }
+            _ => writeln!(
+                self.out,
+                "Cannot print {what}. Expected stack, vars or a variable name."
+            ),
+        }
+    }
+
+    fn print_backtrace(&mut self) -> io::Result<()> {
+        if !self.running {
+            return writeln!(self.out, "The program is not being run.");
+        }
+        let symbols = self.symbols();
+        let frames: Vec<_> = self
+            .interpreter
+            .backtrace()
+            .iter()
+            .map(|pos| {
+                format!(
+                    "{}:{}:{} in {}",
+                    self.path,
+                    pos.pos.line,
+                    pos.pos.column,
+                    symbols.describe_lambda(pos.lambda_id)
+                )
+            })
+            .collect();
+        for (i, frame) in frames.iter().enumerate() {
+            writeln!(self.out, "#{i} {frame}")?;
+        }
+        Ok(())
+    }
+
+    fn symbols(&self) -> BacktraceSymbols<'_, 'source> {
+        BacktraceSymbols {
+            program: self.interpreter.program(),
+            source: self.source,
+            lambda_names: self.interpreter.lambda_names(),
+        }
+    }
+
+    /// Print the source line of the next command, with the command highlighted.
+    fn print_location(&mut self) -> io::Result<()> {
+        let Some((_, span)) = self.interpreter.current_command().filter(|_| self.running) else {
+            return writeln!(self.out, "The program is not being run.");
+        };
+        let line_start = self.source[..span.start.offset]
+            .rfind('\n')
+            .map_or(0, |i| i + 1);
+        let line_end = self.source[span.start.offset..]
+            .find('\n')
+            .map_or(self.source.len(), |i| span.start.offset + i);
+        // multi-line commands are only highlighted on their first line.
+        let end = span.end.offset.min(line_end);
+        let before = &self.source[line_start..span.start.offset];
+        let command = &self.source[span.start.offset..end];
+        let after = &self.source[end..line_end];
+        let prefix = format!("{}:{}", span.start.line, span.start.column);
+        if self.highlight {
+            let style = Style::new().bold().invert();
+            writeln!(
+                self.out,
+                "{prefix} | {before}{style}{command}{style:#}{after}"
+            )
+        } else {
+            writeln!(self.out, "{prefix} | {before}{command}{after}")?;
+            writeln!(
+                self.out,
+                "{} | {}{}",
+                " ".repeat(prefix.len()),
+                " ".repeat(before.chars().count()),
+                "^".repeat(command.chars().count().max(1))
+            )
+        }
+    }
+}
+
+/// Format the data stack bottom first, e.g. `[1, <var a>, <lambda 3>]`.
+pub fn fmt_stack(stack: &[StackValue]) -> String {
+    let values: Vec<_> = stack.iter().map(|v| v.to_string()).collect();
+    format!("[{}]", values.join(", "))
+}
+
+fn fmt_breakpoint(breakpoint: &Breakpoint) -> String {
+    match breakpoint {
+        Breakpoint::Pos(pos) => format!("{}:{}", pos.line, pos.column),
+        Breakpoint::Span(start, _) => format!("line {}", start.line),
+        Breakpoint::Lambda(id) => format!("lambda {id}"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Debugger;
+    use falsec_types::Config;
+
+    /// Run the debugger with the commands, and return what it printed.
+    fn session(source: &str, commands: &str) -> String {
+        let config = Config::default();
+        let program = crate::parse_program(source, &config);
+        let mut out = Vec::new();
+        Debugger::new(&b""[..], &mut out, program, config, source, "test.f", 100)
+            .run(commands.as_bytes())
+            .unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    /// The positions the debugger stopped at, e.g. `2:3`.
+    fn stops(transcript: &str) -> Vec<&str> {
+        transcript
+            .lines()
+            .filter_map(|line| line.trim_start_matches("(falsec) ").split_once(" | "))
+            .map(|(prefix, _)| prefix)
+            .filter(|prefix| !prefix.trim().is_empty())
+            .collect()
+    }
+
+    const PROGRAM: &str = "[1+\n2*]f:\n0f;!\n3[1-$][]#\n%";
+
+    #[test]
+    fn next() {
+        // steps over the call with `!` and the loop with `#`
+        let transcript = session(PROGRAM, "b 3\nc\nn\nn\nn\nn\nn\nn\nn\nn\nn\n");
+        assert_eq!(
+            stops(&transcript),
+            [
+                "1:1", "3:1", "3:2", "3:3", "3:4", "4:1", "4:2", "4:7", "4:9", "5:1"
+            ]
+        );
+        assert!(transcript.ends_with("Program finished.\n(falsec) \n"));
+    }
+
+    #[test]
+    fn next_in_lambda() {
+        // stepping over the last command of a lambda returns to its caller
+        let transcript = session(PROGRAM, "b 3\nc\nn 3\ns\nn\nn\nn\nn\n");
+        assert_eq!(
+            stops(&transcript),
+            ["1:1", "3:1", "3:4", "1:2", "1:3", "2:1", "2:2", "4:1"]
+        );
+    }
+
+    #[test]
+    fn finish() {
+        let transcript = session(PROGRAM, "f\nb 3:4\nc\ns\nf\nf\n");
+        assert_eq!(stops(&transcript), ["1:1", "3:4", "1:2", "4:1"]);
+        assert!(transcript.contains("\"finish\" is not meaningful in the main lambda."));
+    }
+
+    #[test]
+    fn breakpoints() {
+        let transcript = session(
+            PROGRAM,
+            "b\nb 3\nb 4:2\nb 6\nb x\nb\nd 1\nd 1\nb\nc\nd 2\nd\nb\n",
+        );
+        let messages: Vec<_> = transcript
+            .split("(falsec) ")
+            .skip(1)
+            .map(str::trim_end)
+            .collect();
+        assert_eq!(
+            messages[..9],
+            [
+                "No breakpoints.",
+                "Breakpoint 1: 3:1",
+                "Breakpoint 2: 4:2",
+                "No command starts at line 6.",
+                "Invalid position: x. Expected LINE or LINE:COLUMN.",
+                "1: 3:1\n2: 4:2",
+                "Deleted breakpoint 1.",
+                "Deleted breakpoint 1.",
+                "No breakpoints.",
+            ]
+        );
+        assert_eq!(
+            messages[9..],
+            [
+                "Program finished.",
+                "No breakpoint 2.",
+                "Deleted all breakpoints.",
+                "No breakpoints.",
+                "",
+            ]
+        );
+    }
+}
@@ -23,6 +23,9 @@ pub struct Cli {
 pub enum Commands {
     Run(Run),
     Compile(Compile),
+    Parse(Parse),
+    Debug(Debug),
+    Repl(Repl),
 }
 
 #[derive(ValueEnum, Copy, Clone, PartialEq, Eq, Debug)]
@@ -41,12 +44,12 @@ pub enum TypeSafety {
 
 mod run {
     use crate::TypeSafety;
-    use clap::{Args, ValueHint};
+    use clap::{Args, ValueEnum, ValueHint};
     use std::ffi::OsString;
 
     /// Execute a FALSE program
     #[derive(Debug, Args)]
-    #[command(version, about, long_about = None)]
+    #[command(version, long_about = None)]
     pub struct Run {
         #[arg(long, require_equals = true, value_name = "TYPE", value_enum)]
         pub type_safety: Option<TypeSafety>,
@@ -58,10 +61,75 @@ pub struct Run {
         /// Print backtrace on error
         #[arg(short = 'b', long)]
         pub print_backtrace: bool,
+
+        /// Exit with the number on top of the stack when the program ends
+        #[arg(long)]
+        pub exit_status_from_stack: bool,
+
+        /// Log every executed command to FILE, or to stderr if no file is given
+        #[arg(long, value_name = "FILE", num_args = 0..=1, require_equals = true, value_hint = ValueHint::FilePath)]
+        pub trace: Option<Option<OsString>>,
+
+        /// The format of the trace
+        #[arg(long, require_equals = true, value_enum, default_value_t = TraceFormat::Text)]
+        pub trace_format: TraceFormat,
+
+        /// How many values from the top of the data stack to log after each command
+        #[arg(long, require_equals = true, value_name = "N", default_value_t = 3)]
+        pub trace_stack: usize,
+
+        /// Print the commands and lambdas that take the most time to stderr
+        #[arg(long)]
+        pub profile: bool,
+
+        /// Write the time spent per call stack to FILE, for flamegraph tools. Implies --profile
+        #[arg(long, value_name = "FILE", value_hint = ValueHint::FilePath)]
+        pub profile_folded: Option<OsString>,
+
+        /// Write which lines, lambdas and branches were executed to FILE, in the LCOV format
+        #[arg(long, require_equals = true, value_name = "FILE", value_hint = ValueHint::FilePath)]
+        pub coverage: Option<OsString>,
+
+        /// When the program is stopped with Ctrl-C, save its state to FILE to continue with --resume
+        #[arg(long, value_name = "FILE", value_hint = ValueHint::FilePath)]
+        pub snapshot: Option<OsString>,
+
+        /// Also save a snapshot to the --snapshot FILE every COMMANDS executed commands
+        #[arg(
+            long,
+            require_equals = true,
+            value_name = "COMMANDS",
+            requires = "snapshot"
+        )]
+        pub snapshot_every: Option<u64>,
+
+        /// Continue from a snapshot saved with --snapshot, instead of from the start
+        #[arg(long, value_name = "SNAPSHOT", value_hint = ValueHint::FilePath)]
+        pub resume: Option<OsString>,
+
+        /// Save every byte the program reads, with when it was read, and its output to FILE
+        #[arg(long, value_name = "FILE", value_hint = ValueHint::FilePath, conflicts_with = "replay")]
+        pub record: Option<OsString>,
+
+        /// Run with the input and arguments saved by --record, and check that the output is the same
+        #[arg(long, value_name = "FILE", value_hint = ValueHint::FilePath, conflicts_with = "args")]
+        pub replay: Option<OsString>,
+
+        /// Arguments for the program, after `--`
+        #[arg(last = true, value_name = "ARGS")]
+        pub args: Vec<OsString>,
+    }
+
+    #[derive(ValueEnum, Copy, Clone, PartialEq, Eq, Debug)]
+    pub enum TraceFormat {
+        /// One line per command
+        Text,
+        /// One JSON object per line
+        Json,
     }
 }
 
-pub use run::Run;
+pub use run::{Run, TraceFormat};
 
 mod compile {
     use crate::TypeSafety;
@@ -70,7 +138,7 @@ mod compile {
 
     /// Compile a FALSE program
     #[derive(Debug, Args)]
-    #[command(version, about, long_about = None)]
+    #[command(version, long_about = None)]
     pub struct Compile {
         #[arg(long, require_equals = true, value_name = "TYPE", value_enum)]
         pub type_safety: Option<TypeSafety>,
@@ -79,6 +147,10 @@ pub struct Compile {
         #[arg(long, value_name = "FILE", value_hint = ValueHint::FilePath)]
         pub dump_asm: Option<OsString>,
 
+        /// Exit with the number on top of the stack when the program ends
+        #[arg(long)]
+        pub exit_status_from_stack: bool,
+
         /// The path to the compiled FALSE program
         #[arg(short, long, value_name = "FILE", value_hint = ValueHint::FilePath)]
         pub out: Option<OsString>,
@@ -91,6 +163,102 @@ pub struct Compile {
 
 pub use compile::Compile;
 
+mod parse {
+    use clap::{Args, ValueEnum, ValueHint};
+    use std::ffi::OsString;
+
+    /// Parse a FALSE program and print the result
+    #[derive(Debug, Args)]
+    #[command(version, long_about = None)]
+    pub struct Parse {
+        /// Which stage of the parse result to print
+        #[arg(long, require_equals = true, value_enum, default_value_t = Emit::Program)]
+        pub emit: Emit,
+
+        /// The output format
+        #[arg(long, require_equals = true, value_enum, default_value_t = Format::Json)]
+        pub format: Format,
+
+        /// The path to write the result to. Defaults to stdout
+        #[arg(short, long, value_name = "FILE", value_hint = ValueHint::FilePath)]
+        pub out: Option<OsString>,
+
+        /// The path to the FALSE program to parse
+        #[arg(value_name = "FILE", value_hint = ValueHint::FilePath)]
+        pub program: OsString,
+    }
+
+    #[derive(ValueEnum, Copy, Clone, PartialEq, Eq, Debug)]
+    pub enum Emit {
+        /// The kind and source span of all commands, in source order. Lambdas are split into
+        /// their opening and closing brackets.
+        Tokens,
+        /// The commands as returned by the parser, with nested lambda definitions.
+        Ast,
+        /// The analyzed program, with all lambdas extracted.
+        Program,
+    }
+
+    #[derive(ValueEnum, Copy, Clone, PartialEq, Eq, Debug)]
+    pub enum Format {
+        Json,
+        /// Rust debug output
+        Debug,
+    }
+}
+
+pub use parse::{Emit, Format, Parse};
+
+mod debug {
+    use crate::TypeSafety;
+    use clap::{Args, ValueHint};
+    use std::ffi::OsString;
+
+    /// Debug a FALSE program interactively
+    #[derive(Debug, Args)]
+    #[command(version, long_about = None)]
+    pub struct Debug {
+        #[arg(long, require_equals = true, value_name = "TYPE", value_enum)]
+        pub type_safety: Option<TypeSafety>,
+
+        /// The path to read the program's input from. Defaults to stdin, which is shared with
+        /// the debugger prompt
+        #[arg(short, long, value_name = "FILE", value_hint = ValueHint::FilePath)]
+        pub input: Option<OsString>,
+
+        /// How many executed commands can be undone with reverse-step and reverse-continue, 0 to turn
+        /// the history off
+        #[arg(
+            long,
+            require_equals = true,
+            value_name = "N",
+            default_value_t = 100_000
+        )]
+        pub history: usize,
+
+        /// The path to the FALSE program to debug
+        #[arg(value_name = "FILE", value_hint = ValueHint::FilePath)]
+        pub program: OsString,
+    }
+}
+
+pub use debug::Debug;
+
+mod repl {
+    use crate::TypeSafety;
+    use clap::Args;
+
+    /// Execute FALSE code interactively
+    #[derive(Debug, Args)]
+    #[command(version, long_about = None)]
+    pub struct Repl {
+        #[arg(long, require_equals = true, value_name = "TYPE", value_enum)]
+        pub type_safety: Option<TypeSafety>,
+    }
+}
+
+pub use repl::Repl;
+
 fn styles() -> Styles {
     Styles::styled()
         .usage(
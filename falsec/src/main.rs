@@ -1,13 +1,28 @@
+mod debug;
+mod profile;
+mod record;
+mod repl;
+mod trace;
+
 use clap::Parser;
-use falsec_cli::{Cli, Commands, Compile, Run, TypeSafety};
+use falsec_cli::{Cli, Commands, Compile, Emit, Format, Parse, Run, TypeSafety};
 use falsec_compiler::{CompileRequest, Target, compile};
-use falsec_types::Config;
-use falsec_types::source::Program;
+use falsec_interpreter::coverage::Coverage;
+use falsec_interpreter::error::{BacktraceSymbols, InterpreterErrorKind};
+use falsec_interpreter::snapshot::{Snapshot, program_hash};
+use falsec_interpreter::stdlib::register_standard_builtins;
+use falsec_interpreter::vm::Vm;
+use falsec_interpreter::{CancellationToken, Interpreter, StackValue, Status};
+use falsec_types::source::{Command, Pos, Program, Span};
+use falsec_types::{Config, exit_code};
+use serde::Serialize;
+use serde::de::DeserializeOwned;
 use std::borrow::Cow;
 use std::cell::OnceCell;
-use std::ffi::OsStr;
+use std::ffi::{OsStr, OsString};
+use std::fmt::{Debug, Display};
 use std::fs::File;
-use std::io::{Read, Write, stdin, stdout};
+use std::io::{BufReader, BufWriter, Cursor, IsTerminal, Read, Write, stderr, stdin, stdout};
 use std::path::{Path, PathBuf};
 
 trait FromArg<T> {
@@ -55,34 +70,241 @@ fn main() {
             program: program_path,
             type_safety,
             print_backtrace,
+            exit_status_from_stack,
+            trace,
+            trace_format,
+            trace_stack,
+            profile,
+            profile_folded,
+            coverage,
+            snapshot,
+            snapshot_every,
+            resume,
+            record,
+            replay,
+            mut args,
         }) => {
             if let Some(type_safety) = type_safety {
                 config.type_safety = FromArg::from_arg(type_safety);
             }
             config.print_backtrace |= print_backtrace;
+            config.exit_status_from_stack |= exit_status_from_stack;
             let print_backtrace = config.print_backtrace;
             let source_code = read_program(Path::new(&program_path));
             let program = parse_program(&source_code, &config);
-            let interpreter =
-                falsec_interpreter::Interpreter::new(stdin(), stdout(), program, config);
-            let res = interpreter.run();
+            let token = CancellationToken::new();
+            let handler_token = token.clone();
+            // the first Ctrl-C stops the program before its next command, the second one exits
+            // right away, e.g. while the program waits for input.
+            ctrlc::set_handler(move || {
+                if handler_token.is_cancelled() {
+                    std::process::exit(exit_code::INTERRUPTED);
+                }
+                handler_token.cancel();
+            })
+            .unwrap();
+            let session = replay.as_ref().map(|path| {
+                let session: record::Session = read_json(Path::new(path), "recording");
+                if session.program_hash != program_hash(&program) {
+                    eprintln!(
+                        "Cannot replay {}: it was recorded with another program",
+                        path.display()
+                    );
+                    std::process::exit(exit_code::RUNTIME_ERROR)
+                }
+                session
+            });
+            let observed = trace.is_some()
+                || profile
+                || profile_folded.is_some()
+                || coverage.is_some()
+                || record.is_some()
+                || session.is_some();
+            let snapshots = snapshot.is_some() || resume.is_some();
+            if let Some(path) = &snapshot {
+                // fail before running if snapshots cannot be written.
+                let temp = snapshot_temp_path(Path::new(path));
+                create_output(&temp, "snapshot");
+                _ = std::fs::remove_file(temp);
+            }
+            let mut saved_snapshot = None;
+            let mut divergence = None;
+            let (res, lambda_names) = if !observed && !print_backtrace && !snapshots {
+                // the VM is faster, but cannot be observed, does not capture error contexts and
+                // cannot be snapshotted.
+                let mut vm = Vm::new(stdin(), stdout(), &program, config.clone());
+                vm.set_cancellation_token(token);
+                register_standard_builtins(vm.builtins_mut(), args);
+                let res = vm.run().map(|()| exit_status(vm.data_stack(), &config));
+                (res, vm.lambda_names().clone())
+            } else {
+                let tracer = trace.as_ref().map(|path| {
+                    let out: Box<dyn Write> = match path {
+                        Some(path) => Box::new(create_output(Path::new(path), "trace")),
+                        None => Box::new(stderr()),
+                    };
+                    trace::Tracer::new(out, trace_format, trace_stack)
+                });
+                let profiler =
+                    (profile || profile_folded.is_some()).then(|| profile::Profiler::new(&program));
+                let mut folded = profile_folded
+                    .as_ref()
+                    .map(|path| (path, create_output(Path::new(path), "profile")));
+                let coverage_observer = coverage.as_ref().map(|_| Coverage::default());
+                let mut coverage_out = coverage
+                    .as_ref()
+                    .map(|path| (path, create_output(Path::new(path), "coverage")));
+                let mut record_out = record
+                    .as_ref()
+                    .map(|path| (path, create_output(Path::new(path), "recording")));
+                let recorder = record.as_ref().map(|_| {
+                    let args = args.iter().map(|arg| arg.to_string_lossy().into_owned());
+                    record::Recorder::new(program_hash(&program), args.collect())
+                });
+                let input: Box<dyn Read> = match &session {
+                    Some(session) => {
+                        args = session.args.iter().map(OsString::from).collect();
+                        Box::new(Cursor::new(session.input_bytes()))
+                    }
+                    None => Box::new(stdin()),
+                };
+                let replayer = session.map(record::Replayer::new);
+                let mut interpreter =
+                    Interpreter::new(input, stdout(), program.clone(), config.clone())
+                        .with_observer((
+                            tracer,
+                            (profiler, (coverage_observer, (recorder, replayer))),
+                        ));
+                interpreter.set_cancellation_token(token);
+                register_standard_builtins(interpreter.builtins_mut(), args);
+                if let Some(path) = resume {
+                    let snapshot = read_json(Path::new(&path), "snapshot");
+                    interpreter.restore(snapshot).unwrap_or_else(|e| {
+                        eprintln!("Cannot resume from {}: {e}", path.display());
+                        std::process::exit(exit_code::RUNTIME_ERROR)
+                    });
+                }
+                let res = loop {
+                    let status = match snapshot_every {
+                        Some(commands) => interpreter.run_for(commands),
+                        None => interpreter.resume(),
+                    };
+                    match (status, &snapshot) {
+                        (Ok(Status::Paused), Some(path)) => {
+                            save_snapshot(Path::new(path), &interpreter.snapshot());
+                        }
+                        (status, _) => {
+                            break status.map(|_| exit_status(interpreter.data_stack(), &config));
+                        }
+                    }
+                };
+                if let (Some(path), Err(e)) = (snapshot, &res)
+                    && matches!(e.kind, InterpreterErrorKind::Interrupted(_))
+                {
+                    // interrupted before a command, so the snapshot continues with that command.
+                    save_snapshot(Path::new(&path), &interpreter.snapshot());
+                    saved_snapshot = Some(path);
+                }
+                stdout().flush().unwrap();
+                if let (Some(path), (Some(tracer), _)) = (&trace, interpreter.observer_mut())
+                    && let Err(e) = tracer.finish()
+                {
+                    let path = path.as_deref().map_or(Path::new("stderr"), Path::new);
+                    write_error(path, "trace", e);
+                }
+                let (_, (profiler, (coverage_observer, (recorder, replayer)))) =
+                    interpreter.observer();
+                if let (Some((path, out)), Some(recorder)) = (&mut record_out, recorder) {
+                    let written = serde_json::to_writer(&mut *out, &recorder.session)
+                        .map_err(std::io::Error::from)
+                        .and_then(|()| out.flush());
+                    if let Err(e) = written {
+                        write_error(Path::new(path), "recording", e);
+                    }
+                }
+                divergence = replayer.as_ref().and_then(record::Replayer::divergence);
+                if let Some(profiler) = profiler {
+                    eprintln!();
+                    profiler.write_report(&mut stderr()).unwrap();
+                    if let Some((path, out)) = &mut folded
+                        && let Err(e) = profiler.write_folded(out).and_then(|()| out.flush())
+                    {
+                        write_error(Path::new(path), "profile", e);
+                    }
+                }
+                if let (Some((path, out)), Some(coverage)) = (&mut coverage_out, coverage_observer)
+                {
+                    let program_path = std::path::absolute(&program_path).unwrap();
+                    let written = coverage
+                        .write_lcov(interpreter.program(), program_path.to_str().unwrap(), out)
+                        .and_then(|()| out.flush());
+                    if let Err(e) = written {
+                        write_error(Path::new(path), "coverage", e);
+                    }
+                }
+                (res, interpreter.lambda_names().clone())
+            };
             stdout().flush().unwrap();
-            if let Err(e) = res {
-                println!("\n{e}");
-                if print_backtrace {
-                    println!("{}", e.fmt_backtrace(program_path.to_str().unwrap()));
+            if let Some(divergence) = divergence {
+                eprintln!("\nThe replay differs from the recording: {divergence}");
+                if res.is_ok() {
+                    std::process::exit(exit_code::RUNTIME_ERROR);
+                }
+            }
+            let e = match res {
+                Ok(status) => std::process::exit(status),
+                Err(e) => e,
+            };
+            if let InterpreterErrorKind::Exit(status) = e.kind {
+                std::process::exit(status as i32);
+            }
+            eprintln!("\n{e}");
+            let symbols = BacktraceSymbols {
+                program: &program,
+                source: &source_code,
+                lambda_names: &lambda_names,
+            };
+            let backtrace = e.fmt_symbolic_backtrace(program_path.to_str().unwrap(), &symbols);
+            if let InterpreterErrorKind::Interrupted(stack) = &e.kind {
+                // the top of the stack is the interesting part, and it may be very deep.
+                const TOP: usize = 16;
+                let top = &stack[stack.len().saturating_sub(TOP)..];
+                if top.len() < stack.len() {
+                    eprintln!(
+                        "data stack: {} more values, then {}",
+                        stack.len() - top.len(),
+                        debug::fmt_stack(top)
+                    );
+                } else {
+                    eprintln!("data stack: {}", debug::fmt_stack(top));
+                }
+                eprintln!("{backtrace}");
+                if let Some(path) = saved_snapshot {
+                    eprintln!(
+                        "saved a snapshot to {}, continue with --resume",
+                        path.display()
+                    );
+                }
+                std::process::exit(exit_code::INTERRUPTED);
+            } else if print_backtrace {
+                eprintln!("{backtrace}");
+                if let Some(context) = &e.context {
+                    eprintln!("{context}");
                 }
             }
+            std::process::exit(exit_code::RUNTIME_ERROR);
         }
         Commands::Compile(Compile {
             program,
             out,
             type_safety,
             dump_asm,
+            exit_status_from_stack,
         }) => {
             if let Some(type_safety) = type_safety {
                 config.type_safety = FromArg::from_arg(type_safety);
             }
+            config.exit_status_from_stack |= exit_status_from_stack;
             let out_path =
                 out.unwrap_or_else(|| PathBuf::from(&program).with_extension("").into_os_string());
             let source_code = read_program(Path::new(&program));
@@ -115,8 +337,13 @@ fn flush(&mut self) -> std::io::Result<()> {
                 dump_asm: dump_asm
                     .map(|p| PathBuf::from(p).with_extension("asm").into_os_string())
                     .map(|p| Box::new(LazyFile::new(p)) as _),
+                builtins: Vec::new(),
+                standard_builtins: true,
             })
-            .unwrap();
+            .unwrap_or_else(|e| {
+                eprintln!("{e}");
+                std::process::exit(exit_code::COMPILE_ERROR)
+            });
 
             #[cfg(unix)]
             {
@@ -127,23 +354,244 @@ fn flush(&mut self) -> std::io::Result<()> {
                 std::fs::set_permissions(&out_path, perms).unwrap();
             }
         }
+        Commands::Debug(falsec_cli::Debug {
+            program: program_path,
+            type_safety,
+            input,
+            history,
+        }) => {
+            if let Some(type_safety) = type_safety {
+                config.type_safety = FromArg::from_arg(type_safety);
+            }
+            config.print_backtrace = true;
+            let source_code = read_program(Path::new(&program_path));
+            let program = parse_program(&source_code, &config);
+            let input: Box<dyn Read> = match input {
+                Some(path) => Box::new(File::open(path).unwrap()),
+                None => Box::new(stdin()),
+            };
+            let path = program_path.to_str().unwrap();
+            debug::Debugger::new(
+                input,
+                stdout(),
+                program,
+                config,
+                &source_code,
+                path,
+                history,
+            )
+            .with_highlight(stdout().is_terminal())
+            .run(debug::StdinLines::default())
+            .unwrap();
+        }
+        Commands::Repl(falsec_cli::Repl { type_safety }) => {
+            if let Some(type_safety) = type_safety {
+                config.type_safety = FromArg::from_arg(type_safety);
+            }
+            repl::Repl::new(config).run();
+        }
+        Commands::Parse(Parse {
+            program,
+            emit,
+            format,
+            out,
+        }) => {
+            let source_code = read_program(Path::new(&program));
+            let commands = parse_commands(&source_code, &config);
+            let mut out: Box<dyn Write> = match out {
+                Some(path) => Box::new(File::create(path).unwrap()),
+                None => Box::new(stdout()),
+            };
+            match emit {
+                Emit::Tokens => write_formatted(&mut out, format, &tokens(&commands)),
+                Emit::Ast => write_formatted(&mut out, format, &commands),
+                Emit::Program => {
+                    write_formatted(&mut out, format, &analyze_program(commands, &config))
+                }
+            }
+        }
+    }
+}
+
+/// The exit status of a program that ended normally, see [Config::exit_status_from_stack].
+fn exit_status(stack: &[StackValue], config: &Config) -> i32 {
+    let Some(value) = stack.last().filter(|_| config.exit_status_from_stack) else {
+        return 0;
+    };
+    match value.as_integer(config.type_safety) {
+        Some(status) => status as i32,
+        None => {
+            eprintln!("\nRuntime Error: Expected a number as the exit status, found {value}");
+            exit_code::RUNTIME_ERROR
+        }
     }
 }
 
+/// Read a snapshot or a recording, or exit if that fails.
+fn read_json<T: DeserializeOwned>(path: &Path, what: &str) -> T {
+    let value = File::open(path)
+        .map_err(|e| e.to_string())
+        .and_then(|file| serde_json::from_reader(BufReader::new(file)).map_err(|e| e.to_string()));
+    value.unwrap_or_else(|e| {
+        eprintln!("Cannot read the {what} {}: {e}", path.display());
+        std::process::exit(exit_code::RUNTIME_ERROR)
+    })
+}
+
+/// Read the program, or exit with [exit_code::IO_ERROR] if that fails.
 fn read_program(program: &Path) -> String {
-    if program == "-" {
+    let source = if program == "-" {
         let mut buffer = String::new();
-        stdin().read_to_string(&mut buffer).unwrap();
-        buffer
+        stdin().read_to_string(&mut buffer).map(|_| buffer)
     } else {
-        std::fs::read_to_string(program).unwrap()
+        std::fs::read_to_string(program)
+    };
+    source.unwrap_or_else(|e| {
+        eprintln!("Cannot read {}: {e}", program.display());
+        std::process::exit(exit_code::IO_ERROR)
+    })
+}
+
+/// Where a snapshot is written first, before it replaces the previous one.
+fn snapshot_temp_path(path: &Path) -> PathBuf {
+    let mut temp = path.as_os_str().to_owned();
+    temp.push(".tmp");
+    temp.into()
+}
+
+/// Save a snapshot to `path`, replacing the previous one at once so that the file is never
+/// partly written, or exit with [exit_code::IO_ERROR] if that fails.
+fn save_snapshot(path: &Path, snapshot: &Snapshot) {
+    let temp = snapshot_temp_path(path);
+    let mut out = create_output(&temp, "snapshot");
+    let written = serde_json::to_writer(&mut out, snapshot)
+        .map_err(std::io::Error::from)
+        .and_then(|()| out.flush())
+        .and_then(|()| std::fs::rename(&temp, path));
+    if let Err(e) = written {
+        write_error(path, "snapshot", e);
+    }
+}
+
+/// Create an output file before the program runs, or exit with [exit_code::IO_ERROR] if that
+/// fails.
+fn create_output(path: &Path, what: &str) -> BufWriter<File> {
+    File::create(path)
+        .map(BufWriter::new)
+        .unwrap_or_else(|e| write_error(path, what, e))
+}
+
+/// Exit with [exit_code::IO_ERROR] because an output could not be written.
+fn write_error(path: &Path, what: &str, e: impl Display) -> ! {
+    eprintln!("Cannot write the {what} to {}: {e}", path.display());
+    std::process::exit(exit_code::IO_ERROR)
+}
+
+/// Exits with [exit_code::ANALYSIS_ERROR] if the program breaks an invariant the interpreter,
+/// the VM or the compiler rely on.
+fn verify_program(program: &Program) {
+    if let Err(errors) = falsec_analyzer::verify::verify(program) {
+        for error in errors {
+            eprintln!("{error}");
+        }
+        std::process::exit(exit_code::ANALYSIS_ERROR)
     }
 }
 
 fn parse_program<'source>(program: &'source str, config: &Config) -> Program<'source> {
+    analyze_program(parse_commands(program, config), config)
+}
+
+fn parse_commands<'source>(
+    program: &'source str,
+    config: &Config,
+) -> Vec<(Command<'source>, Span<'source>)> {
     let parser = falsec_parser::Parser::new(program, config.clone());
     let commands: Result<Vec<_>, _> = parser.collect();
-    falsec_analyzer::Analyzer::new(commands.unwrap(), config.clone())
+    commands.unwrap_or_else(|e| {
+        eprintln!("{e}");
+        std::process::exit(exit_code::PARSE_ERROR)
+    })
+}
+
+fn analyze_program<'source>(
+    commands: Vec<(Command<'source>, Span<'source>)>,
+    config: &Config,
+) -> Program<'source> {
+    let program = falsec_analyzer::Analyzer::new(commands, config.clone())
         .analyze()
-        .unwrap()
+        .unwrap_or_else(|e| {
+            eprintln!("{e}");
+            std::process::exit(exit_code::ANALYSIS_ERROR)
+        });
+    verify_program(&program);
+    for warning in falsec_analyzer::lint::lint(&program) {
+        eprintln!("{warning}");
+    }
+    program
+}
+
+/// A token of `falsec parse --emit=tokens`.
+#[derive(Serialize, Debug)]
+struct Token<'source> {
+    /// The name of the command's variant, or `LambdaStart` and `LambdaEnd` for the brackets
+    /// of a lambda definition.
+    kind: &'static str,
+    span: Span<'source>,
+}
+
+/// Flatten parsed commands into tokens, in source order.
+/// A lambda definition is split into tokens for its `[` and `]`, with its body in between.
+fn tokens<'source>(commands: &[(Command<'source>, Span<'source>)]) -> Vec<Token<'source>> {
+    use falsec_types::source::LambdaCommand;
+
+    /// The serde name of the command's variant, or of the lambda command's.
+    fn kind(command: &Command) -> &'static str {
+        match command {
+            Command::Lambda(lambda) => serde_variant::to_variant_name(lambda).unwrap(),
+            command => serde_variant::to_variant_name(command).unwrap(),
+        }
+    }
+
+    fn collect<'source>(
+        commands: &[(Command<'source>, Span<'source>)],
+        out: &mut Vec<Token<'source>>,
+    ) {
+        for (command, span) in commands {
+            if let Command::Lambda(LambdaCommand::LambdaDefinition(body)) = command {
+                let open = Pos::new(
+                    span.start.offset + 1,
+                    span.start.line,
+                    span.start.column + 1,
+                );
+                let close = Pos::new(span.end.offset - 1, span.end.line, span.end.column - 1);
+                out.push(Token {
+                    kind: "LambdaStart",
+                    span: Span::new(span.start, open, &span.source[..1]),
+                });
+                collect(body, out);
+                out.push(Token {
+                    kind: "LambdaEnd",
+                    span: Span::new(close, span.end, &span.source[span.source.len() - 1..]),
+                });
+            } else {
+                out.push(Token {
+                    kind: kind(command),
+                    span: *span,
+                });
+            }
+        }
+    }
+
+    let mut out = Vec::new();
+    collect(commands, &mut out);
+    out
+}
+
+fn write_formatted(out: &mut impl Write, format: Format, value: &(impl Serialize + Debug)) {
+    match format {
+        Format::Json => serde_json::to_writer(&mut *out, value).unwrap(),
+        Format::Debug => write!(out, "{:#?}", value).unwrap(),
+    }
+    writeln!(out).unwrap();
 }
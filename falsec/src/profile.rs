@@ -0,0 +1,282 @@
+use falsec_interpreter::StackValue;
+use falsec_interpreter::observer::{CommandEvent, ExecutionObserver};
+use falsec_types::source::{Command, LambdaCommand, Program};
+use std::collections::{HashMap, HashSet};
+use std::io::{self, Write};
+use std::time::{Duration, Instant};
+
+/// How many rows the hotspot tables show.
+const ROWS: usize = 20;
+
+/// Measures where time is spent for `falsec run --profile`.
+///
+/// The time of each command is the command's self time. It is added to the inclusive time of
+/// the command itself and of every `!`, `?` and `#` that led to it, and the same way to the
+/// lambdas on the call stack. Recursive calls are only counted once per step.
+#[derive(Default)]
+pub struct Profiler {
+    /// Keyed by (lambda id, program counter).
+    commands: HashMap<(u64, usize), CommandStats>,
+    lambdas: HashMap<u64, LambdaStats>,
+    /// Self time per call stack, keyed by the lambda ids from the main lambda down.
+    stacks: HashMap<Vec<u64>, Duration>,
+    /// Where each lambda is defined, as `line:column`.
+    names: HashMap<u64, String>,
+    main_id: u64,
+    /// The lambdas that were called and the commands that called them, outermost first.
+    calls: Vec<(u64, (u64, usize))>,
+    /// The command that is executing, the call depth and when it started.
+    current: Option<((u64, usize), usize, Instant)>,
+}
+
+#[derive(Default)]
+struct CommandStats {
+    count: u64,
+    self_time: Duration,
+    inclusive: Duration,
+    location: String,
+    source: String,
+}
+
+#[derive(Default)]
+struct LambdaStats {
+    calls: u64,
+    self_time: Duration,
+    inclusive: Duration,
+}
+
+impl Profiler {
+    pub fn new(program: &Program) -> Self {
+        let names = program
+            .lambdas
+            .values()
+            .flatten()
+            .filter_map(|(command, span)| match command {
+                Command::Lambda(LambdaCommand::LambdaReference(id)) => {
+                    Some((*id, format!("{}:{}", span.start.line, span.start.column)))
+                }
+                _ => None,
+            })
+            .collect();
+        Self {
+            names,
+            main_id: program.main_id,
+            ..Default::default()
+        }
+    }
+
+    fn record(&mut self, (lambda_id, pc): (u64, usize), depth: usize, elapsed: Duration) {
+        let command = self.commands.get_mut(&(lambda_id, pc)).unwrap();
+        command.count += 1;
+        command.self_time += elapsed;
+        command.inclusive += elapsed;
+        let lambda = self.lambdas.entry(lambda_id).or_default();
+        lambda.self_time += elapsed;
+        lambda.inclusive += elapsed;
+
+        let mut seen_commands = HashSet::from([(lambda_id, pc)]);
+        let mut seen_lambdas = HashSet::from([lambda_id, self.main_id]);
+        if lambda_id != self.main_id {
+            self.lambdas.entry(self.main_id).or_default().inclusive += elapsed;
+        }
+        for (callee, call) in &self.calls[..depth] {
+            if seen_commands.insert(*call)
+                && let Some(command) = self.commands.get_mut(call)
+            {
+                command.inclusive += elapsed;
+            }
+            if seen_lambdas.insert(*callee) {
+                self.lambdas.entry(*callee).or_default().inclusive += elapsed;
+            }
+        }
+
+        let stack: Vec<_> = [self.main_id]
+            .into_iter()
+            .chain(self.calls[..depth].iter().map(|(callee, _)| *callee))
+            .collect();
+        *self.stacks.entry(stack).or_default() += elapsed;
+    }
+
+    fn lambda_name(&self, id: u64) -> String {
+        if id == self.main_id {
+            return "main".to_string();
+        }
+        match self.names.get(&id) {
+            Some(pos) => format!("lambda@{pos}"),
+            None => format!("lambda{id}"),
+        }
+    }
+
+    /// Print the commands and lambdas with the highest inclusive time.
+    pub fn write_report(&self, out: &mut impl Write) -> io::Result<()> {
+        let ms = |d: Duration| d.as_secs_f64() * 1000.0;
+
+        let mut commands: Vec<_> = self.commands.values().collect();
+        commands.sort_by(|a, b| b.inclusive.cmp(&a.inclusive).then(b.count.cmp(&a.count)));
+        writeln!(
+            out,
+            "{:>12} {:>12} {:>12}  {:<10} command",
+            "count", "self ms", "incl. ms", "position"
+        )?;
+        for c in commands.iter().take(ROWS) {
+            writeln!(
+                out,
+                "{:>12} {:>12.3} {:>12.3}  {:<10} {}",
+                c.count,
+                ms(c.self_time),
+                ms(c.inclusive),
+                c.location,
+                c.source
+            )?;
+        }
+
+        writeln!(out)?;
+        let mut lambdas: Vec<_> = self.lambdas.iter().collect();
+        lambdas.sort_by_key(|(_, l)| std::cmp::Reverse(l.inclusive));
+        writeln!(
+            out,
+            "{:>12} {:>12} {:>12}  lambda",
+            "calls", "self ms", "incl. ms"
+        )?;
+        for (id, l) in lambdas.iter().take(ROWS) {
+            writeln!(
+                out,
+                "{:>12} {:>12.3} {:>12.3}  {}",
+                l.calls,
+                ms(l.self_time),
+                ms(l.inclusive),
+                self.lambda_name(**id)
+            )?;
+        }
+        Ok(())
+    }
+
+    /// Write the self time of each call stack in nanoseconds, in the folded format read by
+    /// flamegraph tools, e.g. `main;lambda@3:5 1200`.
+    pub fn write_folded(&self, out: &mut impl Write) -> io::Result<()> {
+        let mut stacks: Vec<_> = self
+            .stacks
+            .iter()
+            .map(|(stack, time)| {
+                let names: Vec<_> = stack.iter().map(|id| self.lambda_name(*id)).collect();
+                (names.join(";"), time.as_nanos())
+            })
+            .collect();
+        stacks.sort_unstable();
+        for (stack, ns) in stacks {
+            writeln!(out, "{stack} {ns}")?;
+        }
+        Ok(())
+    }
+}
+
+impl ExecutionObserver for Profiler {
+    fn before_command(&mut self, event: &CommandEvent) {
+        let key = (event.lambda_id, event.program_counter);
+        self.commands.entry(key).or_insert_with(|| CommandStats {
+            location: format!("{}:{}", event.span.start.line, event.span.start.column),
+            source: (event.span.source.lines().next().unwrap_or_default())
+                .chars()
+                .take(20)
+                .collect(),
+            ..Default::default()
+        });
+        self.current = Some((key, self.calls.len(), Instant::now()));
+    }
+
+    fn after_command(&mut self, _event: &CommandEvent, _data_stack: &[StackValue]) {
+        if let Some((key, depth, start)) = self.current.take() {
+            self.record(key, depth, start.elapsed());
+        }
+    }
+
+    fn lambda_call(&mut self, lambda_id: u64) {
+        let (call, _, _) = self.current.unwrap();
+        self.calls.push((lambda_id, call));
+        self.lambdas.entry(lambda_id).or_default().calls += 1;
+    }
+
+    fn lambda_return(&mut self, _lambda_id: u64) {
+        self.calls.pop();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Profiler;
+    use falsec_interpreter::Interpreter;
+    use falsec_types::Config;
+    use std::collections::BTreeSet;
+
+    fn profile(source: &str) -> Profiler {
+        let config = Config::default();
+        let program = crate::parse_program(source, &config);
+        let profiler = Profiler::new(&program);
+        let mut interpreter =
+            Interpreter::new(&b""[..], Vec::new(), program, config).with_observer(profiler);
+        interpreter.resume().unwrap();
+        std::mem::take(interpreter.observer_mut())
+    }
+
+    /// The columns of each row of a table, without the times, which differ between runs.
+    fn rows(table: &str) -> BTreeSet<Vec<&str>> {
+        table
+            .lines()
+            .skip(1)
+            .map(|row| {
+                let columns: Vec<_> = row.split_whitespace().collect();
+                columns[1].parse::<f64>().unwrap();
+                columns[2].parse::<f64>().unwrap();
+                [&columns[..1], &columns[3..]].concat()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn report() {
+        let profiler = profile("[1+]f:\n0 f;! f;!");
+        let mut report = Vec::new();
+        profiler.write_report(&mut report).unwrap();
+        let report = String::from_utf8(report).unwrap();
+        let (commands, lambdas) = report.split_once("\n\n").unwrap();
+        assert!(commands.starts_with("       count      self ms     incl. ms  position"));
+        assert_eq!(
+            rows(commands),
+            BTreeSet::from([
+                vec!["1", "1:1", "[1+]"],
+                vec!["1", "1:5", "f"],
+                vec!["1", "1:6", ":"],
+                vec!["1", "2:1", "0"],
+                vec!["2", "1:2", "1"],
+                vec!["2", "1:3", "+"],
+                vec!["1", "2:3", "f"],
+                vec!["1", "2:4", ";"],
+                vec!["1", "2:5", "!"],
+                vec!["1", "2:7", "f"],
+                vec!["1", "2:8", ";"],
+                vec!["1", "2:9", "!"],
+            ])
+        );
+        assert_eq!(
+            rows(lambdas),
+            BTreeSet::from([vec!["0", "main"], vec!["2", "lambda@1:1"]])
+        );
+    }
+
+    #[test]
+    fn folded() {
+        let profiler = profile("[1+]f:\n0 f;! f;!");
+        let mut folded = Vec::new();
+        profiler.write_folded(&mut folded).unwrap();
+        let folded = String::from_utf8(folded).unwrap();
+        let stacks: Vec<_> = folded
+            .lines()
+            .map(|line| {
+                let (stack, ns) = line.rsplit_once(' ').unwrap();
+                ns.parse::<u128>().unwrap();
+                stack
+            })
+            .collect();
+        assert_eq!(stacks, ["main", "main;lambda@1:1"]);
+    }
+}
@@ -0,0 +1,280 @@
+use falsec_interpreter::observer::{CommandEvent, ExecutionObserver};
+use serde::{Deserialize, Serialize};
+use std::time::Instant;
+
+/// The input and output of a run, written by `falsec run --record` and checked by
+/// `falsec run --replay`.
+#[derive(Debug, Default, Eq, PartialEq, Serialize, Deserialize)]
+pub struct Session {
+    /// The [falsec_interpreter::snapshot::program_hash] of the recorded program.
+    pub program_hash: u64,
+    /// The arguments after `--`, which are passed to the program again when it is replayed.
+    pub args: Vec<String>,
+    /// Every read of `^` or of file descriptor 0, in order.
+    pub input: Vec<Read>,
+    pub output: Vec<u8>,
+}
+
+#[derive(Debug, Eq, PartialEq, Serialize, Deserialize)]
+pub struct Read {
+    /// The byte that was read, or `None` at the end of the input.
+    pub byte: Option<u8>,
+    /// Milliseconds since the program started.
+    pub time_ms: u64,
+    /// Number of commands executed so far, including the one that read.
+    pub instruction: u64,
+}
+
+impl Session {
+    /// The input to feed to the replayed program.
+    pub fn input_bytes(&self) -> Vec<u8> {
+        self.input.iter().filter_map(|read| read.byte).collect()
+    }
+}
+
+/// Records a [Session] for `falsec run --record`.
+pub struct Recorder {
+    pub session: Session,
+    started: Instant,
+    instructions: u64,
+}
+
+impl Recorder {
+    pub fn new(program_hash: u64, args: Vec<String>) -> Self {
+        Self {
+            session: Session {
+                program_hash,
+                args,
+                ..Default::default()
+            },
+            started: Instant::now(),
+            instructions: 0,
+        }
+    }
+}
+
+impl ExecutionObserver for Recorder {
+    fn before_command(&mut self, _event: &CommandEvent) {
+        self.instructions += 1;
+    }
+
+    fn read(&mut self, byte: Option<u8>) {
+        self.session.input.push(Read {
+            byte,
+            time_ms: self.started.elapsed().as_millis() as u64,
+            instruction: self.instructions,
+        });
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        self.session.output.extend_from_slice(bytes);
+    }
+}
+
+/// Compares a replayed run to its [Session] for `falsec run --replay`, and remembers where
+/// they first differ.
+pub struct Replayer {
+    session: Session,
+    instructions: u64,
+    reads: usize,
+    output_bytes: usize,
+    /// The first difference, the later ones usually follow from it.
+    divergence: Option<String>,
+}
+
+impl Replayer {
+    pub fn new(session: Session) -> Self {
+        Self {
+            session,
+            instructions: 0,
+            reads: 0,
+            output_bytes: 0,
+            divergence: None,
+        }
+    }
+
+    /// Why the run did not match the recording, if it did not. Call this after the run.
+    pub fn divergence(&self) -> Option<String> {
+        let recorded_reads = self.session.input.len();
+        let recorded = self.session.output.len();
+        self.divergence
+            .clone()
+            .or_else(|| {
+                (self.reads < recorded_reads).then(|| {
+                    format!(
+                        "the program read {} times, but {recorded_reads} reads were recorded",
+                        self.reads
+                    )
+                })
+            })
+            .or_else(|| {
+                (self.output_bytes < recorded).then(|| {
+                    format!(
+                        "the program wrote {} bytes, but {recorded} were recorded",
+                        self.output_bytes
+                    )
+                })
+            })
+    }
+}
+
+impl ExecutionObserver for Replayer {
+    fn before_command(&mut self, _event: &CommandEvent) {
+        self.instructions += 1;
+    }
+
+    fn read(&mut self, _byte: Option<u8>) {
+        let (index, now) = (self.reads, self.instructions);
+        self.reads += 1;
+        if self.divergence.is_some() {
+            return;
+        }
+        self.divergence = match self.session.input.get(index) {
+            Some(read) if read.instruction == now => None,
+            Some(read) => Some(format!(
+                "read #{index} happened at instruction {now}, but at instruction {} in the recording",
+                read.instruction
+            )),
+            None => Some(format!(
+                "read #{index} at instruction {now} is not in the recording"
+            )),
+        };
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        let (start, now) = (self.output_bytes, self.instructions);
+        self.output_bytes += bytes.len();
+        if self.divergence.is_some() {
+            return;
+        }
+        let recorded = self.session.output.get(start..).unwrap_or_default();
+        let differs = bytes
+            .iter()
+            .zip(recorded)
+            .position(|(written, recorded)| written != recorded);
+        self.divergence = if let Some(i) = differs {
+            Some(format!(
+                "output byte {} is {:?} at instruction {now}, but {:?} in the recording",
+                start + i,
+                bytes[i] as char,
+                recorded[i] as char
+            ))
+        } else if bytes.len() > recorded.len() {
+            Some(format!(
+                "the program wrote more than the {} recorded bytes at instruction {now}",
+                self.session.output.len()
+            ))
+        } else {
+            None
+        };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Recorder, Replayer, Session};
+    use falsec_interpreter::Interpreter;
+    use falsec_interpreter::observer::ExecutionObserver;
+    use falsec_types::Config;
+
+    /// Run the program with the observer, and `f` on the observer afterwards.
+    fn run<O: ExecutionObserver, R>(
+        source: &str,
+        input: &[u8],
+        observer: O,
+        f: impl FnOnce(&mut O) -> R,
+    ) -> R {
+        let config = Config::default();
+        let program = crate::parse_program(source, &config);
+        let mut interpreter =
+            Interpreter::new(input, Vec::new(), program, config).with_observer(observer);
+        interpreter.resume().unwrap();
+        f(interpreter.observer_mut())
+    }
+
+    fn record(source: &str, input: &[u8]) -> Session {
+        run(source, input, Recorder::new(0, Vec::new()), |recorder| {
+            std::mem::take(&mut recorder.session)
+        })
+    }
+
+    fn replay(recorded: &str, replayed: &str, input: &[u8]) -> Option<String> {
+        let session = record(recorded, input);
+        let input = session.input_bytes();
+        run(replayed, &input, Replayer::new(session), |replayer| {
+            replayer.divergence()
+        })
+    }
+
+    #[test]
+    fn recorder() {
+        let session = record("^,^,^%\"!\"", b"ab");
+        let reads: Vec<_> = session
+            .input
+            .iter()
+            .map(|read| (read.byte, read.instruction))
+            .collect();
+        assert_eq!(reads, [(Some(b'a'), 1), (Some(b'b'), 3), (None, 5)]);
+        assert_eq!(session.output, b"ab!");
+        assert_eq!(session.input_bytes(), b"ab");
+    }
+
+    #[test]
+    fn matching_replay() {
+        assert_eq!(replay("^,^,\"!\"", "^,^,\"!\"", b"ab"), None);
+    }
+
+    #[test]
+    fn extra_read() {
+        assert_eq!(
+            replay("^,", "^^,,", b"ab").unwrap(),
+            "read #1 at instruction 2 is not in the recording"
+        );
+    }
+
+    #[test]
+    fn missing_read() {
+        assert_eq!(
+            replay("^^%,", "^,", b"ab").unwrap(),
+            "the program read 1 times, but 2 reads were recorded"
+        );
+    }
+
+    #[test]
+    fn shifted_read() {
+        assert_eq!(
+            replay("^,", "1%^,", b"a").unwrap(),
+            "read #0 happened at instruction 3, but at instruction 1 in the recording"
+        );
+    }
+
+    #[test]
+    fn differing_output() {
+        assert_eq!(
+            replay("\"ab\"", "\"ax\"", b"").unwrap(),
+            "output byte 1 is 'x' at instruction 1, but 'b' in the recording"
+        );
+        assert_eq!(
+            replay("\"ab\"", "\"abc\"", b"").unwrap(),
+            "the program wrote more than the 2 recorded bytes at instruction 1"
+        );
+    }
+
+    #[test]
+    fn short_output() {
+        assert_eq!(
+            replay("\"ab\"", "\"a\"", b"").unwrap(),
+            "the program wrote 1 bytes, but 2 were recorded"
+        );
+    }
+
+    #[test]
+    fn session_serde() {
+        let session = Session {
+            args: vec!["--verbose".to_string()],
+            ..record("^,^,", b"a")
+        };
+        let json = serde_json::to_string(&session).unwrap();
+        assert_eq!(serde_json::from_str::<Session>(&json).unwrap(), session);
+    }
+}
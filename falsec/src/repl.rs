@@ -0,0 +1,194 @@
+use crate::debug::{ProgramOutput, fmt_stack};
+use falsec_interpreter::Interpreter;
+use falsec_types::Config;
+use falsec_types::source::Program;
+use rustyline::DefaultEditor;
+use rustyline::error::ReadlineError;
+use std::cell::Cell;
+use std::io::{ErrorKind, Stdin, stdin};
+use std::path::PathBuf;
+use std::rc::Rc;
+
+const HELP: &str = "\
+Enter FALSE code to execute it. The data stack and variables are kept between lines.
+
+:load FILE   execute a FALSE program in this session
+:reset       clear the data stack and variables
+:stack       print the data stack
+:help        show this help
+:quit        exit the REPL";
+
+/// The interactive session behind `falsec repl`.
+pub struct Repl {
+    config: Config,
+    interpreter: Interpreter<'static, Stdin, ProgramOutput>,
+    at_line_start: Rc<Cell<bool>>,
+}
+
+impl Repl {
+    pub fn new(config: Config) -> Self {
+        let at_line_start = Rc::new(Cell::new(true));
+        Self {
+            interpreter: Self::interpreter(&config, &at_line_start),
+            config,
+            at_line_start,
+        }
+    }
+
+    fn interpreter(
+        config: &Config,
+        at_line_start: &Rc<Cell<bool>>,
+    ) -> Interpreter<'static, Stdin, ProgramOutput> {
+        Interpreter::new(
+            stdin(),
+            ProgramOutput(at_line_start.clone()),
+            Program::default(),
+            config.clone(),
+        )
+    }
+
+    pub fn run(mut self) {
+        let mut editor = DefaultEditor::new().unwrap();
+        let history = history_path();
+        if let Some(path) = &history {
+            match editor.load_history(path) {
+                Err(ReadlineError::Io(e)) if e.kind() == ErrorKind::NotFound => (),
+                Err(e) => eprintln!("Cannot load the history from {}: {e}", path.display()),
+                Ok(()) => (),
+            }
+        }
+        self.read_eval_print(&mut editor);
+        if let Some(path) = &history {
+            let saved = path
+                .parent()
+                .map_or(Ok(()), std::fs::create_dir_all)
+                .map_err(ReadlineError::Io)
+                .and_then(|()| editor.save_history(path));
+            if let Err(e) = saved {
+                eprintln!("Cannot save the history to {}: {e}", path.display());
+            }
+        }
+    }
+
+    fn read_eval_print(&mut self, editor: &mut DefaultEditor) {
+        loop {
+            let Some(input) = self.read_input(editor) else {
+                return;
+            };
+            let _ = editor.add_history_entry(input.trim_end());
+            match input.trim().split_once(' ').unwrap_or((input.trim(), "")) {
+                (":load", path) if !path.is_empty() => match std::fs::read_to_string(path) {
+                    Ok(source) => self.execute(&source),
+                    Err(e) => println!("Cannot read {path}: {e}"),
+                },
+                (":reset", "") => {
+                    self.interpreter = Self::interpreter(&self.config, &self.at_line_start);
+                }
+                (":stack", "") => println!("{}", fmt_stack(self.interpreter.data_stack())),
+                (":help", "") => println!("{HELP}"),
+                (":quit", "") => return,
+                (command, _) if command.starts_with(':') => {
+                    println!("Unknown command: {}. Try \":help\".", input.trim())
+                }
+                _ => self.execute(&input),
+            }
+        }
+    }
+
+    /// Read lines until all lambdas, strings and comments are closed.
+    fn read_input(&self, editor: &mut DefaultEditor) -> Option<String> {
+        let mut input = String::new();
+        loop {
+            let prompt = if input.is_empty() { "> " } else { ". " };
+            match editor.readline(prompt) {
+                Ok(line) => {
+                    input.push_str(&line);
+                    input.push('\n');
+                    if !is_incomplete(&input, &self.config) {
+                        return Some(input);
+                    }
+                }
+                // discard the current input
+                Err(ReadlineError::Interrupted) => input.clear(),
+                Err(ReadlineError::Eof) => return None,
+                Err(e) => panic!("{e}"),
+            }
+        }
+    }
+
+    fn execute(&mut self, source: &str) {
+        // the lambdas of every line stay loaded for the rest of the session, and so does the
+        // source code their spans borrow.
+        let source: &'static str = Box::leak(source.into());
+        let commands: Result<Vec<_>, _> =
+            falsec_parser::Parser::new(source, self.config.clone()).collect();
+        let program = match commands {
+            Ok(commands) => falsec_analyzer::Analyzer::new(commands, self.config.clone())
+                .analyze()
+                .map_err(|e| e.to_string())
+                .and_then(|program| {
+                    falsec_analyzer::verify::verify(&program).map_err(|errors| {
+                        let errors: Vec<_> = errors.iter().map(ToString::to_string).collect();
+                        errors.join("\n")
+                    })?;
+                    Ok(program)
+                }),
+            Err(e) => Err(e.to_string()),
+        };
+        match program {
+            Ok(program) => {
+                self.interpreter.load(program);
+                let result = self.interpreter.resume();
+                if !self.at_line_start.replace(true) {
+                    println!();
+                }
+                if let Err(e) = result {
+                    println!("{e}");
+                }
+                println!("{}", fmt_stack(self.interpreter.data_stack()));
+            }
+            Err(e) => println!("{e}"),
+        }
+    }
+}
+
+/// Where the inputs are kept between sessions, in the user's data directory.
+fn history_path() -> Option<PathBuf> {
+    dirs::data_dir().map(|dir| dir.join("falsec").join("repl_history"))
+}
+
+/// Whether `source` ends inside a lambda, string or comment.
+fn is_incomplete(source: &str, config: &Config) -> bool {
+    let mut chars = source.chars();
+    let mut lambdas = 0;
+    while let Some(c) = chars.next() {
+        match c {
+            '\'' => {
+                chars.next();
+            }
+            '[' => lambdas += 1,
+            ']' => lambdas -= 1,
+            '"' => loop {
+                match chars.next() {
+                    None => return true,
+                    Some('"') => break,
+                    Some('\\') => _ = chars.next(),
+                    Some(_) => (),
+                }
+            },
+            '{' => {
+                let mut level = 1;
+                while level > 0 {
+                    match chars.next() {
+                        None => return true,
+                        Some('{') if config.balance_comments => level += 1,
+                        Some('}') => level -= 1,
+                        Some(_) => (),
+                    }
+                }
+            }
+            _ => (),
+        }
+    }
+    lambdas > 0
+}
@@ -0,0 +1,178 @@
+use falsec_cli::TraceFormat;
+use falsec_interpreter::StackValue;
+use falsec_interpreter::observer::{CommandEvent, ExecutionObserver};
+use std::io::Write;
+
+/// Writes one record per executed command for `falsec run --trace`.
+pub struct Tracer<W: Write> {
+    out: W,
+    format: TraceFormat,
+    /// How many values from the top of the data stack are logged.
+    stack_values: usize,
+    /// The first error writing the trace, after which it stops.
+    error: Option<std::io::Error>,
+}
+
+impl<W: Write> Tracer<W> {
+    pub fn new(out: W, format: TraceFormat, stack_values: usize) -> Self {
+        Self {
+            out,
+            format,
+            stack_values,
+            error: None,
+        }
+    }
+
+    /// Flush the trace, or return the first error writing it.
+    pub fn finish(&mut self) -> std::io::Result<()> {
+        match self.error.take() {
+            Some(e) => Err(e),
+            None => self.out.flush(),
+        }
+    }
+
+    fn write(&mut self, event: &CommandEvent, stack: &[StackValue]) -> std::io::Result<()> {
+        let command = abbreviate(event.span.source);
+        match self.format {
+            TraceFormat::Text => {
+                let stack: Vec<_> = stack.iter().map(|v| v.to_string()).collect();
+                writeln!(
+                    self.out,
+                    "{}:{}\tlambda {} pc {}\tdepth {}\t{}\t[{}]",
+                    event.span.start.line,
+                    event.span.start.column,
+                    event.lambda_id,
+                    event.program_counter,
+                    event.depth,
+                    command,
+                    stack.join(", ")
+                )
+            }
+            TraceFormat::Json => {
+                let stack: Vec<_> = stack
+                    .iter()
+                    .map(|v| match v {
+                        StackValue::Integer(i) => serde_json::json!(i),
+                        v => serde_json::json!(v.to_string()),
+                    })
+                    .collect();
+                let value = serde_json::json!({
+                    "line": event.span.start.line,
+                    "column": event.span.start.column,
+                    "lambda": event.lambda_id,
+                    "pc": event.program_counter,
+                    "depth": event.depth,
+                    "command": command,
+                    "stack": stack,
+                });
+                writeln!(self.out, "{value}")
+            }
+        }
+    }
+}
+
+impl<W: Write> ExecutionObserver for Tracer<W> {
+    fn after_command(&mut self, event: &CommandEvent, data_stack: &[StackValue]) {
+        if self.error.is_some() {
+            return;
+        }
+        let top = &data_stack[data_stack.len().saturating_sub(self.stack_values)..];
+        if let Err(e) = self.write(event, top) {
+            self.error = Some(e);
+        }
+    }
+}
+
+/// Shorten long commands like strings and lambdas to their first line and a few characters.
+fn abbreviate(source: &str) -> String {
+    const MAX: usize = 20;
+    let line = source.lines().next().unwrap_or_default();
+    if line.len() < source.len() || line.chars().count() > MAX {
+        let mut short: String = line.chars().take(MAX).collect();
+        short.push_str("...");
+        short
+    } else {
+        line.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Tracer, abbreviate};
+    use falsec_cli::TraceFormat;
+    use falsec_interpreter::Interpreter;
+    use falsec_types::Config;
+
+    fn trace(source: &str, format: TraceFormat, stack_values: usize) -> String {
+        let config = Config::default();
+        let program = crate::parse_program(source, &config);
+        let mut trace = Vec::new();
+        Interpreter::new(&b""[..], Vec::new(), program, config)
+            .with_observer(Tracer::new(&mut trace, format, stack_values))
+            .run()
+            .unwrap();
+        String::from_utf8(trace).unwrap()
+    }
+
+    #[test]
+    fn text() {
+        // `!` is traced once it is executed, which is before the lambda it calls
+        assert_eq!(
+            trace("1 [2+]!\n$", TraceFormat::Text, 2),
+            "\
+1:1\tlambda 0 pc 0\tdepth 0\t1\t[1]
+1:3\tlambda 0 pc 1\tdepth 0\t[2+]\t[1, <lambda 1>]
+1:7\tlambda 0 pc 2\tdepth 0\t!\t[1]
+1:4\tlambda 1 pc 0\tdepth 1\t2\t[1, 2]
+1:5\tlambda 1 pc 1\tdepth 1\t+\t[3]
+2:1\tlambda 0 pc 3\tdepth 0\t$\t[3, 3]
+"
+        );
+    }
+
+    #[test]
+    fn json() {
+        let trace = trace(
+            "1 2\"a long string literal that is cut\"",
+            TraceFormat::Json,
+            1,
+        );
+        let records: Vec<serde_json::Value> = trace
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(records.len(), 3);
+        assert_eq!(
+            records[1],
+            serde_json::json!({
+                "line": 1,
+                "column": 3,
+                "lambda": 0,
+                "pc": 1,
+                "depth": 0,
+                "command": "2",
+                "stack": [2],
+            })
+        );
+        assert_eq!(records[2]["command"], "\"a long string liter...");
+    }
+
+    #[test]
+    fn write_error() {
+        // the program still runs, and the error is reported at the end
+        let config = Config::default();
+        let program = crate::parse_program("1 2 3", &config);
+        let mut buffer = [0; 16];
+        let mut interpreter = Interpreter::new(&b""[..], Vec::new(), program, config)
+            .with_observer(Tracer::new(&mut buffer[..], TraceFormat::Text, 1));
+        interpreter.resume().unwrap();
+        assert_eq!(interpreter.data_stack().len(), 3);
+        assert!(interpreter.observer_mut().finish().is_err());
+    }
+
+    #[test]
+    fn abbreviate_commands() {
+        assert_eq!(abbreviate("[1+]"), "[1+]");
+        assert_eq!(abbreviate("[1+\n2*]"), "[1+...");
+    }
+}
@@ -0,0 +1,95 @@
+use falsec_types::exit_code;
+use std::io::Write;
+use std::process::{Command, Output, Stdio};
+
+/// Run `falsec` with the program on stdin.
+fn falsec(args: &[&str], program: &str) -> Output {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_falsec"))
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child
+        .stdin
+        .take()
+        .unwrap()
+        .write_all(program.as_bytes())
+        .unwrap();
+    child.wait_with_output().unwrap()
+}
+
+#[test]
+fn success() {
+    let output = falsec(&["run", "-"], "1 2+.");
+    assert_eq!(output.status.code(), Some(0));
+    assert_eq!(output.stdout, b"3");
+}
+
+// there is no test for exit_code::ANALYSIS_ERROR, as the parser only produces commands the
+// analyzer and the verifier accept.
+#[test]
+fn parse_error() {
+    for args in [&["run", "-"][..], &["parse", "-"], &["compile", "-"]] {
+        let output = falsec(args, "[1");
+        assert_eq!(
+            output.status.code(),
+            Some(exit_code::PARSE_ERROR),
+            "{args:?}"
+        );
+    }
+}
+
+#[test]
+fn runtime_error() {
+    // the VM and the interpreter exit with the same status
+    for args in [&["run", "-"][..], &["run", "-b", "-"]] {
+        let output = falsec(args, "1 0/");
+        assert_eq!(
+            output.status.code(),
+            Some(exit_code::RUNTIME_ERROR),
+            "{args:?}"
+        );
+        assert!(String::from_utf8_lossy(&output.stderr).contains("Division by zero"));
+    }
+}
+
+#[test]
+fn exit_status_from_stack() {
+    let output = falsec(&["run", "--exit-status-from-stack", "-"], "42");
+    assert_eq!(output.status.code(), Some(42));
+    let output = falsec(&["run", "-"], "42");
+    assert_eq!(output.status.code(), Some(0));
+}
+
+#[test]
+fn missing_program() {
+    let output = falsec(&["run", "does-not-exist.f"], "");
+    assert_eq!(output.status.code(), Some(exit_code::IO_ERROR));
+    assert!(String::from_utf8_lossy(&output.stderr).starts_with("Cannot read does-not-exist.f"));
+}
+
+#[test]
+fn emit_tokens() {
+    let output = falsec(&["parse", "--emit=tokens", "--format=json", "-"], "1[$]!");
+    assert_eq!(output.status.code(), Some(0));
+    let tokens: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    let tokens = tokens.as_array().unwrap();
+    let kinds: Vec<_> = tokens
+        .iter()
+        .map(|token| token["kind"].as_str().unwrap())
+        .collect();
+    assert_eq!(
+        kinds,
+        ["IntLiteral", "LambdaStart", "Dup", "LambdaEnd", "Exec"]
+    );
+    assert_eq!(
+        tokens[2]["span"],
+        serde_json::json!({
+            "start": {"offset": 2, "line": 1, "column": 3},
+            "end": {"offset": 3, "line": 1, "column": 4},
+            "source": "$",
+        })
+    );
+}